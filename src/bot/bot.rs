@@ -1,9 +1,25 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::Arc};
 
 use crate::{
     aws::{command::MINECRAFTCOMMANDS_GROUP, ec2::Ec2Client},
-    chatgpt::{animeboys_ai::AnimeboysAI, command::AICOMMANDS_GROUP},
-    wz::WZCOMMANDS_GROUP,
+    chatgpt::{
+        actions,
+        animeboys_ai::{AiReply, AnimeboysAI},
+        backend::LlmBackend,
+        command::AICOMMANDS_GROUP,
+        context::UserTurn,
+        digest::DailyDigest,
+        knowledge::KnowledgeBase,
+        lifecycle::ConversationLifecycle,
+        moderation::Moderator,
+        persona::{PersonaRegistry, DEFAULT_CHAT_PERSONA},
+        retry::RetryPolicy,
+        sandbox::Sandbox,
+        usage::UsageTracker,
+        vision::Ocr,
+    },
+    config::env_var,
+    wz::{client::WzStatsClient, meta_watch::MetaWatch, WZCOMMANDS_GROUP},
 };
 use serenity::{
     async_trait,
@@ -15,7 +31,11 @@ use serenity::{
         },
         StandardFramework,
     },
-    model::prelude::{Channel, GuildChannel, PartialGuildChannel, UserId},
+    http::Http,
+    model::{
+        event::MessageUpdateEvent,
+        prelude::{Channel, ChannelId, GuildChannel, PartialGuildChannel, Reaction, UserId},
+    },
 };
 use serenity::{framework::standard::macros::command, model::gateway::Ready};
 use serenity::{framework::standard::macros::hook, model::channel::Message};
@@ -23,6 +43,8 @@ use serenity::{framework::standard::Args, model::prelude::Member};
 use serenity::{framework::standard::CommandResult, prelude::*};
 use tracing::{error, info};
 
+use super::paginator::{self, Paginator};
+
 const MEMBER_ROLE_ID: u64 = 342563599572664321;
 struct Handler;
 
@@ -30,21 +52,48 @@ struct Handler;
 impl EventHandler for Handler {
     async fn thread_update(&self, ctx: Context, thread: GuildChannel) {
         info!("Updating thread: {}", thread.id);
-        // If the thread is locked, then remove it from the AI
-        if thread.thread_metadata.unwrap().locked {
-            // Delete the thread from the AI
-            let mut data = ctx.data.write().await;
-            let ai = data.get_mut::<AnimeboysAI>().unwrap();
-            ai.remove_conversation(&thread.id).await;
-        }
+        let (ai, lifecycle) = {
+            let data = ctx.data.read().await;
+            (
+                data.get::<AnimeboysAI>().unwrap().clone(),
+                data.get::<ConversationLifecycle>().unwrap().clone(),
+            )
+        };
+        lifecycle.thread_updated(&ai, &thread).await;
     }
 
     async fn thread_delete(&self, ctx: Context, thread: PartialGuildChannel) {
         info!("Deleting thread: {}", thread.id);
+        let (ai, lifecycle) = {
+            let data = ctx.data.read().await;
+            (
+                data.get::<AnimeboysAI>().unwrap().clone(),
+                data.get::<ConversationLifecycle>().unwrap().clone(),
+            )
+        };
         // Delete the thread from the AI
-        let mut data = ctx.data.write().await;
-        let ai = data.get_mut::<AnimeboysAI>().unwrap();
-        ai.remove_conversation(&thread.id).await;
+        lifecycle.end(&ai, &thread.id).await;
+    }
+
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        if let Err(e) = actions::handle_reaction(&ctx, &reaction).await {
+            error!("Error handling reaction: {:?}", e);
+        }
+        if let Err(e) = paginator::handle_reaction(&ctx, &reaction).await {
+            error!("Error turning page: {:?}", e);
+        }
+    }
+
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _old_if_available: Option<Message>,
+        _new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        if let Err(e) = actions::handle_edit(&ctx, &event).await {
+            error!("Error handling message edit: {:?}", e);
+        }
     }
 
     async fn guild_member_addition(&self, ctx: Context, new_member: Member) {
@@ -169,6 +218,12 @@ async fn dispatch_error(ctx: &Context, msg: &Message, error: DispatchError, _com
                 .await
                 .unwrap();
         }
+        DispatchError::LackingPermissions(_) => {
+            msg.channel_id
+                .say(&ctx.http, "You do not have permission to use this command")
+                .await
+                .unwrap();
+        }
         _ => {
             // Contact the dev that something went wrong
             let dm = 1155886697582690345;
@@ -199,68 +254,145 @@ async fn before(_ctx: &Context, msg: &Message, command_name: &str) -> bool {
 #[hook]
 async fn normal_message(ctx: &Context, msg: &Message) {
     info!("Got message '{}'", msg.content);
-    // Check to see if the message was sent in a thread
-    let mut data = ctx.data.write().await;
-    let ai = data.get_mut::<AnimeboysAI>().unwrap();
+    // Only hold the data lock long enough to get the shared state,
+    // the AI can take a while to respond
+    let (ai, moderator, lifecycle) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<AnimeboysAI>().unwrap().clone(),
+            data.get::<Moderator>().unwrap().clone(),
+            data.get::<ConversationLifecycle>().unwrap().clone(),
+        )
+    };
 
-    if ai.does_conversation_exist(&msg.channel_id) {
+    // Check to see if the message was sent in a thread,
+    // the conversation may have expired and need to be resumed
+    if lifecycle.resume(&ai, &msg.channel_id).await {
         info!("Message was sent in a thread");
+        // Banned users never get their attachments downloaded or read
+        if moderator.is_banned(&msg.author.id) {
+            info!("Ignoring message from banned user {}", msg.author.name);
+            return;
+        }
+        let mut turn = UserTurn::from_message(ctx, msg).await;
+        let vision = ai
+            .conversation_vision(&msg.channel_id, None, DEFAULT_CHAT_PERSONA)
+            .await;
+        ai.read_images(&mut turn, vision).await;
+        if !moderate_ai_input(ctx, &moderator, msg, &mut turn).await {
+            return;
+        }
         // Start Typing
         let typing = ctx.http.start_typing(msg.channel_id.0).unwrap();
         // Send the message to the AI
-        let response = ai.send_message(&msg.content, &msg.channel_id).await;
+        let response = ai.send_message(&turn, &msg.channel_id).await;
 
         // Get the guild channel from the channel id
         let channel = ctx.http.get_channel(msg.channel_id.0).await.unwrap();
 
         // Send the response to the thread
-        send_message_in_streams(ctx, channel, response)
+        send_ai_response(ctx, &ai, &moderator, channel, response)
             .await
             .unwrap();
         // Stop typing
         drop(typing);
+    } else if lifecycle.take_expired(&msg.channel_id) {
+        info!("Message was sent in a thread whose conversation expired");
+        if let Err(e) = msg
+            .reply(
+                ctx,
+                "This conversation has expired. Start a new one with `$ai chat`",
+            )
+            .await
+        {
+            error!("Error sending message: {:?}", e);
+        }
     } else {
         info!("Message was not sent in a thread");
     }
 }
 
-/// Sends a message in multiple streams
-/// If the message is too long, then it will be split into multiple messages
-/// and sent in multiple streams
-pub async fn send_message_in_streams(
+/// Checks a user's turn before it is sent to the AI
+/// Returns false, after letting the user know, if the author is banned or the turn was blocked
+pub async fn moderate_ai_input(
     ctx: &Context,
+    moderator: &Moderator,
+    msg: &Message,
+    turn: &mut UserTurn,
+) -> bool {
+    if moderator.is_banned(&msg.author.id) {
+        info!("Ignoring message from banned user {}", msg.author.name);
+        return false;
+    }
+    if !moderator
+        .moderate_turn(&ctx.http, msg.channel_id, turn)
+        .await
+    {
+        if let Err(e) = msg
+            .reply(
+                ctx,
+                "Your message was blocked by moderation and was not sent to the AI",
+            )
+            .await
+        {
+            error!("Error sending message: {:?}", e);
+        }
+        return false;
+    }
+    true
+}
+
+/// Sends the AI's response to the channel, logging the incident if moderation changed it
+/// The sent messages are remembered by the conversation and get the reply actions as reactions
+pub async fn send_ai_response(
+    ctx: &Context,
+    ai: &AnimeboysAI,
+    moderator: &Moderator,
     channel: Channel,
-    msg: String,
+    response: AiReply,
 ) -> CommandResult {
-    match channel {
-        Channel::Private(channel) => {
-            if msg.bytes().len() > 2000 {
-                let msg = msg.bytes().collect::<Vec<u8>>();
-                for chunk in msg.chunks(2000) {
-                    let msg = String::from_utf8(chunk.to_vec()).unwrap();
-                    channel.send_message(&ctx.http, |m| m.content(msg)).await?;
-                }
-            } else {
-                channel.send_message(&ctx.http, |m| m.content(msg)).await?;
-            }
-        }
-        Channel::Guild(channel) => {
-            if msg.bytes().len() > 2000 {
-                let msg = msg.bytes().collect::<Vec<u8>>();
-                for chunk in msg.chunks(2000) {
-                    let msg = String::from_utf8(chunk.to_vec()).unwrap();
-                    channel.send_message(&ctx.http, |m| m.content(msg)).await?;
-                }
-            } else {
-                channel.send_message(&ctx.http, |m| m.content(msg)).await?;
+    let channel_id = channel.id();
+    moderator
+        .log_output_incident(&ctx.http, channel_id, response.incident.as_ref())
+        .await;
+    let messages = send_text_in_chunks(&ctx.http, channel_id, &response.content).await?;
+
+    if let Some(last) = messages.last() {
+        for emoji in [actions::REGENERATE_EMOJI, actions::CONTINUE_EMOJI] {
+            if let Err(e) = last.react(&ctx.http, emoji).await {
+                error!("Error adding reaction: {:?}", e);
             }
         }
-        _ => {}
     }
-
+    ai.set_reply_messages(&channel_id, messages.iter().map(|m| m.id).collect())
+        .await;
     Ok(())
 }
 
+/// Splits text into chunks that fit in a Discord message
+/// Splits on characters, never inside one
+pub fn message_chunks(text: &str) -> Vec<String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    chars
+        .chunks(2000)
+        .map(|chunk| chunk.iter().collect::<String>())
+        .collect()
+}
+
+/// Sends text that may be longer than a Discord message, split into as many messages as needed
+/// Returns the messages that were sent
+pub async fn send_text_in_chunks(
+    http: &Http,
+    channel_id: ChannelId,
+    text: &str,
+) -> CommandResult<Vec<Message>> {
+    let mut sent = vec![];
+    for chunk in message_chunks(text) {
+        sent.push(channel_id.say(http, chunk).await?);
+    }
+    Ok(sent)
+}
+
 /// Create Bot Framework
 pub fn create_framework() -> StandardFramework {
     let framework = StandardFramework::new()
@@ -286,17 +418,59 @@ pub fn create_framework() -> StandardFramework {
 pub async fn create_bot(
     token: String,
     intents: GatewayIntents,
-    api_key: String,
+    backend: Arc<dyn LlmBackend>,
     instance_id: String,
 ) -> Client {
     let framework = create_framework();
+    let knowledge = Arc::new(KnowledgeBase::from_env());
+    let usage = Arc::new(UsageTracker::from_env());
+    let moderator = Arc::new(Moderator::from_env());
+    let ai = Arc::new(
+        AnimeboysAI::new(backend, PersonaRegistry::from_env())
+            .with_ocr(Ocr::from_env())
+            .with_knowledge(knowledge.clone())
+            .with_usage(usage.clone())
+            .with_retry(RetryPolicy::from_env())
+            .with_moderator(moderator.clone()),
+    );
+    usage.clone().spawn_saver();
+    // The metrics are only served when an address is configured, e.g. `0.0.0.0:9100`
+    if let Some(addr) = env_var("AI_METRICS_ADDR") {
+        if let Err(e) = usage.serve_metrics(&addr).await {
+            error!("Error serving AI metrics on {}: {:?}", addr, e);
+        }
+    }
+    let lifecycle = ConversationLifecycle::from_env();
+    lifecycle.spawn_sweeper(ai.clone());
+    let digest = Arc::new(DailyDigest::from_env());
+    let wz_client = Arc::new(WzStatsClient::from_env());
+    wz_client.clone().spawn_saver();
+    let meta_watch = Arc::new(MetaWatch::from_env());
+
     let client = Client::builder(&token, intents)
         .event_handler(Handler)
         .framework(framework)
-        .type_map_insert::<AnimeboysAI>(AnimeboysAI::new(&api_key))
+        .type_map_insert::<AnimeboysAI>(ai.clone())
+        .type_map_insert::<ConversationLifecycle>(lifecycle)
+        .type_map_insert::<DailyDigest>(digest.clone())
+        .type_map_insert::<Sandbox>(Sandbox::from_env())
+        .type_map_insert::<Moderator>(moderator.clone())
+        .type_map_insert::<WzStatsClient>(wz_client.clone())
+        .type_map_insert::<MetaWatch>(meta_watch.clone())
+        .type_map_insert::<Paginator>(Arc::new(Paginator::default()))
         .type_map_insert::<Ec2Client>(Ec2Client::new(instance_id).await)
         .await
         .expect("Err creating client");
 
+    // Pinned messages can only be loaded once the client can reach Discord
+    let http = client.cache_and_http.http.clone();
+    tokio::spawn(async move {
+        knowledge.reload(&http).await;
+    });
+    if digest.is_enabled() {
+        digest.spawn_scheduler(ai, moderator, client.cache_and_http.http.clone());
+    }
+    meta_watch.spawn_watcher(wz_client, client.cache_and_http.http.clone());
+
     client
 }
@@ -1,3 +1,5 @@
+#[allow(clippy::module_inception)]
 mod bot;
+pub mod paginator;
 
 pub use bot::*;
@@ -0,0 +1,150 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serenity::{
+    builder::CreateEmbed,
+    framework::standard::CommandResult,
+    model::prelude::{ChannelId, MessageId, Reaction, ReactionType, UserId},
+    prelude::{Context, TypeMapKey},
+};
+use tracing::error;
+
+/// Reacting with this on a paged message shows the previous page
+pub const PREVIOUS_EMOJI: char = '◀';
+/// Reacting with this on a paged message shows the next page
+pub const NEXT_EMOJI: char = '▶';
+/// Paged messages stop turning after this long
+const EXPIRY: Duration = Duration::from_secs(15 * 60);
+
+/// Which way to turn a paged message
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PageTurn {
+    Previous,
+    Next,
+}
+
+struct PagedMessage {
+    pages: Vec<CreateEmbed>,
+    current: usize,
+    /// Only the user who ran the command can turn the pages
+    author: UserId,
+    sent_at: Instant,
+}
+
+/// Keeps the pages of the embeds sent with [`send_pages`], so they can be turned with reactions
+#[derive(Default)]
+pub struct Paginator {
+    messages: Mutex<HashMap<MessageId, PagedMessage>>,
+}
+
+impl TypeMapKey for Paginator {
+    type Value = Arc<Paginator>;
+}
+
+impl Paginator {
+    /// Keeps the pages of a message, forgetting the messages that expired
+    pub fn register(&self, message_id: MessageId, author: UserId, pages: Vec<CreateEmbed>) {
+        let mut messages = self.messages.lock().unwrap();
+        messages.retain(|_, m| m.sent_at.elapsed() < EXPIRY);
+        messages.insert(
+            message_id,
+            PagedMessage {
+                pages,
+                current: 0,
+                author,
+                sent_at: Instant::now(),
+            },
+        );
+    }
+
+    /// Turns the page of a message, wrapping around at either end
+    /// Returns the page to show, or None if the user can't turn the pages of the message
+    pub fn turn(
+        &self,
+        message_id: MessageId,
+        user_id: UserId,
+        turn: PageTurn,
+    ) -> Option<CreateEmbed> {
+        let mut messages = self.messages.lock().unwrap();
+        let message = messages
+            .get_mut(&message_id)
+            .filter(|m| m.author == user_id && m.sent_at.elapsed() < EXPIRY)?;
+        let count = message.pages.len();
+        message.current = match turn {
+            PageTurn::Previous => (message.current + count - 1) % count,
+            PageTurn::Next => (message.current + 1) % count,
+        };
+        Some(page(&message.pages, message.current))
+    }
+}
+
+/// Returns the page with a footer showing where it is
+fn page(pages: &[CreateEmbed], index: usize) -> CreateEmbed {
+    let mut page = pages[index].clone();
+    page.footer(|f| f.text(format!("Page {}/{}", index + 1, pages.len())));
+    page
+}
+
+/// Sends the first page, adding reactions to turn the pages when there is more than one
+/// * `author` - the user who can turn the pages
+pub async fn send_pages(
+    ctx: &Context,
+    channel_id: ChannelId,
+    author: UserId,
+    pages: Vec<CreateEmbed>,
+) -> CommandResult {
+    if pages.is_empty() {
+        return Ok(());
+    }
+    let first = page(&pages, 0);
+    let message = channel_id
+        .send_message(&ctx.http, |m| m.set_embed(first))
+        .await?;
+    if pages.len() == 1 {
+        return Ok(());
+    }
+
+    let paginator = {
+        let data = ctx.data.read().await;
+        data.get::<Paginator>().unwrap().clone()
+    };
+    paginator.register(message.id, author, pages);
+    for emoji in [PREVIOUS_EMOJI, NEXT_EMOJI] {
+        message.react(&ctx.http, emoji).await?;
+    }
+    Ok(())
+}
+
+/// Handles a reaction added to a paged message
+pub async fn handle_reaction(ctx: &Context, reaction: &Reaction) -> CommandResult {
+    let user_id = match reaction.user_id {
+        Some(user_id) if user_id != ctx.cache.current_user_id() => user_id,
+        _ => return Ok(()),
+    };
+    let turn = match &reaction.emoji {
+        ReactionType::Unicode(emoji) if emoji == &PREVIOUS_EMOJI.to_string() => PageTurn::Previous,
+        ReactionType::Unicode(emoji) if emoji == &NEXT_EMOJI.to_string() => PageTurn::Next,
+        _ => return Ok(()),
+    };
+
+    let paginator = {
+        let data = ctx.data.read().await;
+        data.get::<Paginator>().unwrap().clone()
+    };
+    let page = match paginator.turn(reaction.message_id, user_id, turn) {
+        Some(page) => page,
+        None => return Ok(()),
+    };
+    reaction
+        .channel_id
+        .edit_message(&ctx.http, reaction.message_id, |m| m.set_embed(page))
+        .await?;
+    // Remove the reaction so the same page turn can be used again
+    if let Err(e) = reaction.delete(&ctx.http).await {
+        error!("Error removing reaction: {:?}", e);
+    }
+    Ok(())
+}
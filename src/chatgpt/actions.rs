@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use serenity::{
+    framework::standard::CommandResult,
+    model::{
+        event::MessageUpdateEvent,
+        prelude::{ChannelId, MessageId, Reaction, ReactionType},
+    },
+    prelude::Context,
+};
+use tracing::{error, info};
+
+use crate::bot;
+
+use super::{
+    animeboys_ai::AnimeboysAI, context::UserTurn, moderation::Moderator,
+    persona::DEFAULT_CHAT_PERSONA,
+};
+
+/// Reacting with this on an AI reply asks for a new answer
+pub const REGENERATE_EMOJI: char = '🔄';
+/// Reacting with this on an AI reply asks the AI to keep going
+pub const CONTINUE_EMOJI: char = '⏩';
+
+/// The actions that can be taken on an AI reply
+enum ReplyAction {
+    Regenerate,
+    Continue,
+}
+
+async fn shared_state(ctx: &Context) -> (Arc<AnimeboysAI>, Arc<Moderator>) {
+    let data = ctx.data.read().await;
+    (
+        data.get::<AnimeboysAI>().unwrap().clone(),
+        data.get::<Moderator>().unwrap().clone(),
+    )
+}
+
+/// Handles a reaction added to one of the AI's replies
+pub async fn handle_reaction(ctx: &Context, reaction: &Reaction) -> CommandResult {
+    // Ignore the reactions the bot adds itself
+    let user_id = match reaction.user_id {
+        Some(user_id) if user_id != ctx.cache.current_user_id() => user_id,
+        _ => return Ok(()),
+    };
+    let action = match &reaction.emoji {
+        ReactionType::Unicode(emoji) if emoji == &REGENERATE_EMOJI.to_string() => {
+            ReplyAction::Regenerate
+        }
+        ReactionType::Unicode(emoji) if emoji == &CONTINUE_EMOJI.to_string() => {
+            ReplyAction::Continue
+        }
+        _ => return Ok(()),
+    };
+
+    let (ai, moderator) = shared_state(ctx).await;
+    let channel_id = reaction.channel_id;
+    // Only the latest reply can be acted on
+    let reply_messages = ai.reply_messages(&channel_id).await;
+    if !reply_messages.contains(&reaction.message_id) || moderator.is_banned(&user_id) {
+        return Ok(());
+    }
+
+    let typing = ctx.http.start_typing(channel_id.0)?;
+    let response = match action {
+        ReplyAction::Regenerate => {
+            info!("Regenerating the last reply in {}", channel_id);
+            let response = ai.regenerate(&channel_id, user_id).await;
+            if response.is_some() {
+                delete_messages(ctx, channel_id, &reply_messages).await;
+            }
+            response
+        }
+        ReplyAction::Continue => {
+            info!("Continuing the last reply in {}", channel_id);
+            // The old reply can no longer be acted on
+            for emoji in [REGENERATE_EMOJI, CONTINUE_EMOJI] {
+                if let Err(e) = channel_id
+                    .delete_reaction(&ctx.http, reaction.message_id, None, emoji)
+                    .await
+                {
+                    error!("Error removing reaction: {:?}", e);
+                }
+            }
+            ai.continue_reply(&channel_id, user_id).await
+        }
+    };
+
+    if let Some(response) = response {
+        let channel = ctx.http.get_channel(channel_id.0).await?;
+        bot::send_ai_response(ctx, &ai, &moderator, channel, response).await?;
+    }
+    drop(typing);
+    Ok(())
+}
+
+/// Handles a user editing their last message in a conversation by
+/// replacing their turn and regenerating the reply
+pub async fn handle_edit(ctx: &Context, event: &MessageUpdateEvent) -> CommandResult {
+    // Edits without content are embeds being added, not the user editing
+    if event.content.is_none() {
+        return Ok(());
+    }
+    let (ai, moderator) = shared_state(ctx).await;
+    // Only the last message sent to the AI can be edited and resent
+    if !ai.is_last_turn(&event.channel_id, event.id).await {
+        return Ok(());
+    }
+
+    let msg = event.channel_id.message(&ctx.http, event.id).await?;
+    if msg.author.bot || moderator.is_banned(&msg.author.id) {
+        return Ok(());
+    }
+    let mut turn = UserTurn::from_message(ctx, &msg).await;
+    let vision = ai
+        .conversation_vision(&event.channel_id, None, DEFAULT_CHAT_PERSONA)
+        .await;
+    ai.read_images(&mut turn, vision).await;
+    if !bot::moderate_ai_input(ctx, &moderator, &msg, &mut turn).await {
+        return Ok(());
+    }
+
+    let reply_messages = ai.reply_messages(&event.channel_id).await;
+    let typing = ctx.http.start_typing(event.channel_id.0)?;
+    if let Some(response) = ai.edit_last_turn(&turn, &event.channel_id).await {
+        info!(
+            "Regenerating the reply to an edited message in {}",
+            event.channel_id
+        );
+        delete_messages(ctx, event.channel_id, &reply_messages).await;
+        let channel = ctx.http.get_channel(event.channel_id.0).await?;
+        bot::send_ai_response(ctx, &ai, &moderator, channel, response).await?;
+    }
+    drop(typing);
+    Ok(())
+}
+
+/// Deletes the messages of a reply that is being replaced
+async fn delete_messages(ctx: &Context, channel_id: ChannelId, messages: &[MessageId]) {
+    for message_id in messages {
+        if let Err(e) = channel_id.delete_message(&ctx.http, message_id).await {
+            error!("Error deleting message {}: {:?}", message_id, e);
+        }
+    }
+}
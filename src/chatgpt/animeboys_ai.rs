@@ -1,158 +1,826 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
 
-use chatgpt::{
-    prelude::{ChatGPT, Conversation, ModelConfigurationBuilder},
-    types::{ChatMessage, ResponseChunk, Role},
+use serenity::{
+    model::prelude::{ChannelId, MessageId, UserId},
+    prelude::TypeMapKey,
 };
-use serenity::{futures::StreamExt, model::prelude::ChannelId, prelude::TypeMapKey};
+use tokio::sync::{Mutex, OwnedMutexGuard};
 use tracing::{error, info};
 
-const DEBUG_DIRECTED_PROMPT: &str = "
-You are the Animeboys Bot. Your main purpose it to help members of the Animeboys Discord server debug their code.
-The conversation will start with a user requesting help with their code. You will then respond with a message that will help the user debug their code.
-After this, you will be placed in a thread with the user where you can continue to help them with their code.
-When you respond, always end your message with 'Thank you for using the Animeboys Bot! Is there anything else I can assist with?'.
-If the user responds with 'Yes', then you will continue to help them with their code. If the user responds with 'No', then you will end the conversation.
+use super::backend::{
+    ChatMessage, ChatRequest, ChatResponse, LlmBackend, LlmError, LlmErrorKind, Role, Usage,
+};
+use super::context::UserTurn;
+use super::knowledge::{KnowledgeBase, KnowledgeChunk};
+use super::moderation::{ModerationResult, Moderator};
+use super::persona::{Persona, PersonaRegistry, DEFAULT_CHAT_PERSONA, DEFAULT_DEBUG_PERSONA};
+use super::retry::RetryPolicy;
+use super::usage::{Outcome, UsageRecord, UsageTracker};
+use super::vision::Ocr;
+
+/// Sent to the AI when a user asks it to continue a reply that was cut off
+const CONTINUE_PROMPT: &str =
+    "Continue exactly where your last message was cut off, without repeating any of it.";
+
+/// The system prompt of one-shot requests, which get a single reply in the channel instead of a thread
+const ONE_SHOT_PROMPT: &str = "
+You are the Animeboys Bot. Your main purpose it to help members of the Animeboys Discord server.
+You are answering a single request from a member. Your reply is posted once in the channel the request was made in
+and there is no follow up, so answer it completely and concisely and do not ask questions back.
+The request starts with the member's name in brackets, e.g. `[Name]: `.
 ";
 
-const QUESTION_DIRECTED_PROMPT: &str = "
-You are the Animeboys Bot. Your main purpose it to help members of the Animeboys Discord server. 
-In this conversation you will help members by answering their questions. After every couple of messages, please
-remind the user that they can end the conversation by sending `$ai stop`.
+/// Sent to the AI with the messages of a channel to summarize them
+const SUMMARY_PROMPT: &str =
+    "Summarize the following Discord messages in a few short bullet points. \
+    Mention who said what when it matters and leave out small talk. The messages, oldest first:\n";
+
+/// The system prompt of digests, which are posted for members who missed a day in a channel
+const DIGEST_SYSTEM_PROMPT: &str = "
+You are the Animeboys Bot. You write the daily digest of a channel of the Animeboys Discord server.
+The digest is posted on its own for members who missed the conversation. Only use what is in the messages you
+are given, do not address the members or ask them anything.
 ";
 
+/// Sent to the AI with a day of messages of a channel to write its digest
+const DIGEST_PROMPT: &str = "Write a digest of the following Discord messages from the last day \
+    for members who missed them. Use short sections for the topics discussed, any decisions made \
+    and the links that were shared, leaving out sections with nothing in them. The messages, oldest first:\n";
+
+/// A response of the AI, moderated before it was kept in the history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AiReply {
+    /// The text to send, with the knowledge base citations
+    pub content: String,
+    /// Why moderation changed the response, logged by whoever sends it to a channel
+    pub incident: Option<ModerationResult>,
+}
+
+impl AiReply {
+    /// A reply that is not from the AI, e.g. why it could not respond
+    fn notice(content: &str) -> Self {
+        Self {
+            content: content.to_string(),
+            incident: None,
+        }
+    }
+}
+
+/// A conversation with the AI
+pub struct Conversation {
+    /// The persona the conversation was started with
+    pub persona: Persona,
+    /// All the messages sent and received, starting with the system prompt
+    pub history: Vec<ChatMessage>,
+    /// When the last message was sent to the AI
+    pub last_active: Instant,
+    /// The Discord message of the last user turn and its index in the history,
+    /// so it can be edited and resent
+    pub last_turn: Option<(MessageId, usize)>,
+    /// The Discord messages the last reply was sent in
+    pub reply_messages: Vec<MessageId>,
+    /// Whether the knowledge base is searched for the message being answered
+    pub knowledge: bool,
+    /// Set when the conversation is saved and dropped from memory,
+    /// so a handler that was waiting for it puts it back instead of adding to a dropped conversation
+    pub suspended: bool,
+}
+
+impl Conversation {
+    pub fn new(persona: Persona) -> Self {
+        let history = vec![ChatMessage::new(
+            Role::System,
+            persona.system_prompt.clone(),
+        )];
+        Self::with_history(persona, history)
+    }
+
+    /// Starts a conversation that only lives for one reply
+    /// The persona's model settings are used, but not its system prompt, which is written for threads
+    pub fn one_shot(persona: Persona) -> Self {
+        let history = vec![ChatMessage::new(Role::System, ONE_SHOT_PROMPT)];
+        Self::with_history(persona, history)
+    }
+
+    /// Continues a conversation from an existing history
+    pub fn with_history(persona: Persona, history: Vec<ChatMessage>) -> Self {
+        Self {
+            persona,
+            history,
+            last_active: Instant::now(),
+            last_turn: None,
+            reply_messages: vec![],
+            knowledge: true,
+            suspended: false,
+        }
+    }
+
+    /// Drops the older half of the messages after the system prompt, to fit the model's context
+    /// The message being answered is always kept
+    /// Returns false if there was nothing left to drop
+    pub fn trim_history(&mut self) -> bool {
+        let droppable = self.history.len().saturating_sub(2);
+        if droppable == 0 {
+            return false;
+        }
+        // Keep dropping until a user message comes first, so no reply is left without its question
+        let mut count = droppable.div_ceil(2);
+        while count < droppable && self.history[1 + count].role != Role::User {
+            count += 1;
+        }
+        self.history.drain(1..1 + count);
+        self.last_turn = match self.last_turn {
+            Some((message_id, index)) if index > count => Some((message_id, index - count)),
+            _ => None,
+        };
+        true
+    }
+}
+
+/// The AI, shared between every command and event handler
+///
+/// Each conversation has its own lock, which is held while the AI is responding.
+/// Messages in the same conversation are answered in order, while independent
+/// conversations and other commands are never blocked by a slow response
 pub struct AnimeboysAI {
-    client: ChatGPT,
+    backend: Arc<dyn LlmBackend>,
+    personas: PersonaRegistry,
+    /// Reads the text in images for models that cannot see them
+    ocr: Ocr,
+    /// The server's own knowledge, given to the AI along with each message
+    knowledge: Arc<KnowledgeBase>,
+    /// Records the tokens used by every request
+    usage: Arc<UsageTracker>,
+    /// How failed requests are retried
+    retry: RetryPolicy,
+    /// Moderates the responses before they are saved to the history
+    moderator: Arc<Moderator>,
     /// A map of channel ids to conversations
     /// The key is the channel id of the thread or a private message
-    conversations: HashMap<ChannelId, Conversation>,
+    /// The map lock is only held to look up or insert conversations, never across an await
+    conversations: RwLock<HashMap<ChannelId, Arc<Mutex<Conversation>>>>,
 }
 
 impl TypeMapKey for AnimeboysAI {
-    type Value = AnimeboysAI;
+    type Value = Arc<AnimeboysAI>;
 }
 
 impl AnimeboysAI {
-    pub fn new(api_key: &str) -> Self {
-        let client = ChatGPT::new_with_config(
-            api_key,
-            ModelConfigurationBuilder::default()
-                .timeout(std::time::Duration::from_secs(120))
-                .build()
-                .unwrap(),
-        )
-        .expect("Failed to create ChatGPT client");
-
+    pub fn new(backend: Arc<dyn LlmBackend>, personas: PersonaRegistry) -> Self {
         Self {
-            client,
-            conversations: HashMap::new(),
+            backend,
+            personas,
+            ocr: Ocr::default(),
+            knowledge: Arc::new(KnowledgeBase::default()),
+            usage: Arc::new(UsageTracker::default()),
+            retry: RetryPolicy::default(),
+            moderator: Arc::new(Moderator::default()),
+            conversations: RwLock::new(HashMap::new()),
         }
     }
 
-    pub async fn debug(&mut self, code: &str, channel_id: &ChannelId) -> String {
-        // Create a new conversation if one does not exist
-        let conversation = self
-            .conversations
+    /// Sets the knowledge base the AI can draw on
+    pub fn with_knowledge(mut self, knowledge: Arc<KnowledgeBase>) -> Self {
+        self.knowledge = knowledge;
+        self
+    }
+
+    pub fn knowledge(&self) -> &KnowledgeBase {
+        &self.knowledge
+    }
+
+    /// Sets how the text in images is read when the model cannot see them
+    pub fn with_ocr(mut self, ocr: Ocr) -> Self {
+        self.ocr = ocr;
+        self
+    }
+
+    /// Sets where the usage of the AI is recorded
+    pub fn with_usage(mut self, usage: Arc<UsageTracker>) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    pub fn usage(&self) -> &UsageTracker {
+        &self.usage
+    }
+
+    /// Sets how failed requests are retried
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sets the moderator the responses are checked with before they are saved to the history
+    pub fn with_moderator(mut self, moderator: Arc<Moderator>) -> Self {
+        self.moderator = moderator;
+        self
+    }
+
+    pub fn personas(&self) -> &PersonaRegistry {
+        &self.personas
+    }
+
+    /// Returns the name of the persona used by the conversation in the given channel
+    pub async fn conversation_persona(&self, channel_id: &ChannelId) -> Option<String> {
+        let conversation = self.get_conversation(channel_id)?;
+        let conversation = conversation.lock().await;
+        Some(conversation.persona.name.clone())
+    }
+
+    /// Returns the conversation for the channel, creating one with the given persona if it does not exist
+    /// Falls back to `default_persona` if the persona is unknown
+    fn get_or_create_conversation(
+        &self,
+        channel_id: &ChannelId,
+        persona: Option<&str>,
+        default_persona: &str,
+    ) -> Arc<Mutex<Conversation>> {
+        let mut conversations = self.conversations.write().unwrap();
+        conversations
             .entry(*channel_id)
-            .or_insert_with(|| self.client.new_conversation_directed(DEBUG_DIRECTED_PROMPT));
+            .or_insert_with(|| {
+                let persona = self.resolve_persona(persona, default_persona);
+                Arc::new(Mutex::new(Conversation::new(persona)))
+            })
+            .clone()
+    }
 
-        let res = AnimeboysAI::get_message_from_stream(Role::User, conversation, code).await;
-        res
+    /// Locks a conversation of the channel for a turn
+    /// If the conversation was suspended while waiting for the lock, it is put back in the channel,
+    /// or the conversation that was resumed in the meantime is locked instead
+    async fn lock_conversation(
+        &self,
+        channel_id: &ChannelId,
+        mut conversation: Arc<Mutex<Conversation>>,
+    ) -> OwnedMutexGuard<Conversation> {
+        loop {
+            let mut guard = conversation.clone().lock_owned().await;
+            if !guard.suspended {
+                return guard;
+            }
+            let current = self
+                .conversations
+                .write()
+                .unwrap()
+                .entry(*channel_id)
+                .or_insert_with(|| conversation.clone())
+                .clone();
+            if Arc::ptr_eq(&current, &conversation) {
+                info!(
+                    "Conversation {} was suspended mid-turn, keeping it",
+                    channel_id
+                );
+                guard.suspended = false;
+                return guard;
+            }
+            conversation = current;
+        }
+    }
+
+    /// Locks the conversation in the channel, if one exists
+    async fn lock_existing(&self, channel_id: &ChannelId) -> Option<OwnedMutexGuard<Conversation>> {
+        let conversation = self.get_conversation(channel_id)?;
+        Some(self.lock_conversation(channel_id, conversation).await)
+    }
+
+    /// Returns the given persona, or `default_persona` if it is unknown
+    pub fn resolve_persona(&self, persona: Option<&str>, default_persona: &str) -> Persona {
+        persona
+            .and_then(|p| self.personas.get(p))
+            .or_else(|| self.personas.get(default_persona))
+            .expect("Default persona is missing")
+            .clone()
     }
 
-    pub async fn send_message(&mut self, message: &str, channel_id: &ChannelId) -> String {
+    /// Switches the persona of an existing conversation, keeping the history
+    /// Returns false if the conversation or persona does not exist
+    pub async fn set_persona(&self, channel_id: &ChannelId, persona: &str) -> bool {
+        let persona = match self.personas.get(persona) {
+            Some(persona) => persona.clone(),
+            None => return false,
+        };
+        let mut conversation = match self.lock_existing(channel_id).await {
+            Some(c) => c,
+            None => return false,
+        };
+
+        // Replace the system prompt with the one from the new persona
+        match conversation.history.first_mut() {
+            Some(first) if first.role == Role::System => {
+                first.content = persona.system_prompt.clone();
+            }
+            _ => conversation.history.insert(
+                0,
+                ChatMessage::new(Role::System, persona.system_prompt.clone()),
+            ),
+        }
+        conversation.persona = persona;
+        true
+    }
+
+    /// Returns whether the persona that answers in the channel can see images
+    /// That is the persona of the channel's conversation, or the one a new conversation starts
+    /// with, i.e. `persona` or else `default_persona`
+    pub async fn conversation_vision(
+        &self,
+        channel_id: &ChannelId,
+        persona: Option<&str>,
+        default_persona: &str,
+    ) -> bool {
+        match self.get_conversation(channel_id) {
+            Some(conversation) => conversation.lock().await.persona.vision,
+            None => self.resolve_persona(persona, default_persona).vision,
+        }
+    }
+
+    /// Prepares the images attached to a turn for the persona that will answer it
+    /// * `vision` - whether that persona can see images, otherwise the text in them is read with OCR
+    pub async fn read_images(&self, turn: &mut UserTurn, vision: bool) {
+        if vision || turn.images().is_empty() {
+            return;
+        }
+
+        for attachment in turn.attachments.iter_mut() {
+            let url = match &attachment.image_url {
+                Some(url) if attachment.content.is_none() && attachment.note.is_none() => url,
+                _ => continue,
+            };
+            if !self.ocr.is_enabled() {
+                attachment.note = Some("the AI cannot see images".to_string());
+                continue;
+            }
+            match self.ocr.read(url, attachment.size).await {
+                Ok(text) if text.is_empty() => {
+                    attachment.note = Some("no text could be read from it".to_string())
+                }
+                Ok(text) => attachment.content = Some(text),
+                Err(e) => {
+                    error!("Error reading image {}: {}", attachment.filename, e);
+                    attachment.note = Some(e.to_string());
+                }
+            }
+        }
+    }
+
+    /// Builds the message sent to the AI for a turn, with its images if the persona can see them
+    fn turn_message(turn: &UserTurn, persona: &Persona) -> ChatMessage {
+        let message = ChatMessage::new(Role::User, turn.to_prompt());
+        if persona.vision {
+            message.with_images(turn.images())
+        } else {
+            message
+        }
+    }
+
+    pub async fn debug(
+        &self,
+        turn: &UserTurn,
+        channel_id: &ChannelId,
+        persona: Option<&str>,
+    ) -> AiReply {
         // Create a new conversation if one does not exist
-        let mut conversation = self.conversations.entry(*channel_id).or_insert_with(|| {
-            self.client
-                .new_conversation_directed(QUESTION_DIRECTED_PROMPT)
-        });
+        let conversation =
+            self.get_or_create_conversation(channel_id, persona, DEFAULT_DEBUG_PERSONA);
+        let mut conversation = self.lock_conversation(channel_id, conversation).await;
+
+        let message = AnimeboysAI::turn_message(turn, &conversation.persona);
+        conversation.history.push(message);
+        self.complete(&mut conversation, channel_id, Some(turn.author_id))
+            .await
+    }
+
+    pub async fn send_message(&self, turn: &UserTurn, channel_id: &ChannelId) -> AiReply {
+        // Create a new conversation if one does not exist
+        let conversation = self.get_or_create_conversation(channel_id, None, DEFAULT_CHAT_PERSONA);
+        let mut conversation = self.lock_conversation(channel_id, conversation).await;
         info!("Conversation history: {:#?}", conversation.history);
+        conversation.last_turn = turn
+            .message_id
+            .map(|message_id| (message_id, conversation.history.len()));
 
-        let res =
-            AnimeboysAI::get_message_from_stream(Role::User, &mut conversation, message).await;
-        res
+        let message = AnimeboysAI::turn_message(turn, &conversation.persona);
+        conversation.history.push(message);
+        self.complete(&mut conversation, channel_id, Some(turn.author_id))
+            .await
     }
 
-    pub async fn create_conversation(&mut self, user: &str, channel_id: &ChannelId) -> String {
+    /// Starts a conversation in the channel for the user and returns the AI's greeting
+    pub async fn create_conversation(
+        &self,
+        user: &str,
+        user_id: UserId,
+        channel_id: &ChannelId,
+        persona: Option<&str>,
+    ) -> AiReply {
         // Create a new conversation if one does not exist
-        let conversation = self.conversations.entry(*channel_id).or_insert_with(|| {
-            self.client
-                .new_conversation_directed(QUESTION_DIRECTED_PROMPT)
-        });
+        let conversation =
+            self.get_or_create_conversation(channel_id, persona, DEFAULT_CHAT_PERSONA);
+        let mut conversation = self.lock_conversation(channel_id, conversation).await;
         info!("Conversation history: {:#?}", conversation.history);
 
-        let res = AnimeboysAI::get_message_from_stream(
+        self.get_message_from_stream(
             Role::User,
-            conversation,
+            &mut conversation,
+            channel_id,
             &format!(
                 "Hello bot! I am {}! I Started this thread to chat with you!",
                 user
             ),
+            user_id,
         )
-        .await;
+        .await
+    }
+
+    /// Answers a single question without starting a conversation
+    /// Nothing is kept once the answer is returned
+    pub async fn ask(
+        &self,
+        turn: &UserTurn,
+        channel_id: &ChannelId,
+        persona: Option<&str>,
+    ) -> AiReply {
+        let mut conversation =
+            Conversation::one_shot(self.resolve_persona(persona, DEFAULT_CHAT_PERSONA));
+        let message = AnimeboysAI::turn_message(turn, &conversation.persona);
+        conversation.history.push(message);
+        self.complete(&mut conversation, channel_id, Some(turn.author_id))
+            .await
+    }
+
+    /// Summarizes a part of a channel, given as the author and content of each message, oldest first
+    /// Like `ask`, the summary does not start a conversation
+    pub async fn summarize(
+        &self,
+        messages: &[(String, String)],
+        channel_id: &ChannelId,
+        user: UserId,
+    ) -> AiReply {
+        let conversation = Conversation::one_shot(self.resolve_persona(None, DEFAULT_CHAT_PERSONA));
+        self.summarize_with(
+            conversation,
+            SUMMARY_PROMPT,
+            messages,
+            channel_id,
+            Some(user),
+        )
+        .await
+    }
+
+    /// Writes the daily digest of a channel from its messages, oldest first
+    /// The knowledge base is left out, the digest is only about what was said in the channel
+    pub async fn digest(&self, messages: &[(String, String)], channel_id: &ChannelId) -> AiReply {
+        let persona = self.resolve_persona(None, DEFAULT_CHAT_PERSONA);
+        let history = vec![ChatMessage::new(Role::System, DIGEST_SYSTEM_PROMPT)];
+        let conversation = Conversation {
+            knowledge: false,
+            ..Conversation::with_history(persona, history)
+        };
+        self.summarize_with(conversation, DIGEST_PROMPT, messages, channel_id, None)
+            .await
+    }
+
+    async fn summarize_with(
+        &self,
+        mut conversation: Conversation,
+        prompt: &str,
+        messages: &[(String, String)],
+        channel_id: &ChannelId,
+        user: Option<UserId>,
+    ) -> AiReply {
+        let mut transcript = String::from(prompt);
+        for (author, content) in messages {
+            transcript.push_str(&format!("\n[{}]: {}", author, content));
+        }
+        conversation
+            .history
+            .push(ChatMessage::new(Role::User, transcript));
+        self.complete(&mut conversation, channel_id, user).await
+    }
+
+    /// Drops the last reply and asks the AI to answer the last user turn again
+    /// The usage is recorded for `user`, the user who asked for it
+    /// Returns None if there is no conversation or no user turn to answer
+    pub async fn regenerate(&self, channel_id: &ChannelId, user: UserId) -> Option<AiReply> {
+        let mut conversation = self.lock_existing(channel_id).await?;
+
+        // Pop everything after the last user turn
+        let last_turn = conversation
+            .history
+            .iter()
+            .rposition(|m| m.role == Role::User)?;
+        conversation.history.truncate(last_turn + 1);
+
+        Some(
+            self.complete(&mut conversation, channel_id, Some(user))
+                .await,
+        )
+    }
+
+    /// Asks the AI to keep going with its last reply
+    /// The usage is recorded for `user`, the user who asked for it
+    /// Returns None if there is no conversation or the AI has not replied yet
+    pub async fn continue_reply(&self, channel_id: &ChannelId, user: UserId) -> Option<AiReply> {
+        let mut conversation = self.lock_existing(channel_id).await?;
+        if conversation.history.last()?.role != Role::Assistant {
+            return None;
+        }
+
+        Some(
+            self.get_message_from_stream(
+                Role::User,
+                &mut conversation,
+                channel_id,
+                CONTINUE_PROMPT,
+                user,
+            )
+            .await,
+        )
+    }
+
+    /// Replaces the last user turn with an edited version and regenerates the reply
+    /// Returns None if the turn is not the last one sent to the conversation
+    pub async fn edit_last_turn(&self, turn: &UserTurn, channel_id: &ChannelId) -> Option<AiReply> {
+        let mut conversation = self.lock_existing(channel_id).await?;
+        let index = match (conversation.last_turn, turn.message_id) {
+            (Some((last, index)), Some(message_id)) if last == message_id => index,
+            _ => return None,
+        };
+        if index >= conversation.history.len() {
+            return None;
+        }
+        // Everything after the turn was a response to the old version
+        conversation.history.truncate(index);
+
+        let message = AnimeboysAI::turn_message(turn, &conversation.persona);
+        conversation.history.push(message);
+        Some(
+            self.complete(&mut conversation, channel_id, Some(turn.author_id))
+                .await,
+        )
+    }
+
+    /// Returns true if the Discord message is the last user turn of the conversation
+    pub async fn is_last_turn(&self, channel_id: &ChannelId, message_id: MessageId) -> bool {
+        match self.get_conversation(channel_id) {
+            Some(conversation) => matches!(
+                conversation.lock().await.last_turn,
+                Some((last, _)) if last == message_id
+            ),
+            None => false,
+        }
+    }
+
+    /// Remembers the Discord messages the last reply was sent in
+    pub async fn set_reply_messages(&self, channel_id: &ChannelId, messages: Vec<MessageId>) {
+        if let Some(mut conversation) = self.lock_existing(channel_id).await {
+            conversation.reply_messages = messages;
+        }
+    }
 
-        res
+    /// Returns the Discord messages of the last reply
+    pub async fn reply_messages(&self, channel_id: &ChannelId) -> Vec<MessageId> {
+        match self.get_conversation(channel_id) {
+            Some(conversation) => conversation.lock().await.reply_messages.clone(),
+            None => vec![],
+        }
     }
 
     pub fn does_conversation_exist(&self, channel_id: &ChannelId) -> bool {
-        self.conversations.contains_key(channel_id)
+        self.conversations.read().unwrap().contains_key(channel_id)
     }
 
-    pub async fn remove_conversation(&mut self, channel_id: &ChannelId) {
-        self.conversations.remove(channel_id);
+    /// Removes the conversation from the AI, returning it if it existed
+    pub async fn remove_conversation(
+        &self,
+        channel_id: &ChannelId,
+    ) -> Option<Arc<Mutex<Conversation>>> {
+        self.conversations.write().unwrap().remove(channel_id)
+    }
+
+    /// Adds a conversation, replacing any existing conversation in the channel
+    pub fn insert_conversation(&self, channel_id: ChannelId, conversation: Conversation) {
+        self.conversations
+            .write()
+            .unwrap()
+            .insert(channel_id, Arc::new(Mutex::new(conversation)));
+    }
+
+    /// Returns the channels of the conversations that have not been active for longer than `ttl`
+    /// Conversations waiting on the AI are never idle
+    pub fn idle_conversations(&self, ttl: Duration) -> Vec<ChannelId> {
+        self.conversations
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, conversation)| match conversation.try_lock() {
+                Ok(conversation) => conversation.last_active.elapsed() > ttl,
+                Err(_) => false,
+            })
+            .map(|(channel_id, _)| *channel_id)
+            .collect()
+    }
+
+    /// Returns the conversation in the given channel, if one exists
+    pub fn get_conversation(&self, channel_id: &ChannelId) -> Option<Arc<Mutex<Conversation>>> {
+        self.conversations.read().unwrap().get(channel_id).cloned()
     }
 
     /// Sends a message to the AI and returns the response
     /// # Arguments
+    /// * `role` - The role of the message
     /// * `conversation` - The conversation to send the message to
+    /// * `channel_id` - The channel of the conversation
     /// * `message` - The message to send to the AI
-    /// * `role` - The role of the message
     /// # Returns
     /// The response from the AI
     async fn get_message_from_stream(
+        &self,
         role: Role,
         conversation: &mut Conversation,
+        channel_id: &ChannelId,
         message: &str,
-    ) -> String {
-        let mut stream = match conversation
-            .send_role_message_streaming(role, message)
-            .await
-        {
-            Ok(stream) => stream,
-            Err(e) => {
-                error!("Error sending message: {:?}", e);
-                return "There was an error processing your request. Please try again later!"
-                    .to_string();
+        user: UserId,
+    ) -> AiReply {
+        conversation.history.push(ChatMessage::new(role, message));
+        self.complete(conversation, channel_id, Some(user)).await
+    }
+
+    /// Asks the AI to respond to the conversation as it is and saves the response
+    /// The knowledge base chunks matching the last message are given to the AI with it,
+    /// and the ones cited are listed under the response
+    /// The usage is recorded for the user the response is for, if any
+    ///
+    /// Failed requests are retried according to the retry policy. When the conversation is
+    /// too long for the model, its oldest messages are dropped and the request is sent again.
+    /// If the AI still cannot respond, a message explaining what went wrong is returned and
+    /// the history is kept as it is, so the last turn can be regenerated later
+    async fn complete(
+        &self,
+        conversation: &mut Conversation,
+        channel_id: &ChannelId,
+        user: Option<UserId>,
+    ) -> AiReply {
+        conversation.last_active = Instant::now();
+
+        let mut model = conversation.persona.model.clone();
+        let mut retries = 0;
+        let result = loop {
+            let (messages, knowledge) =
+                self.prepare_messages(&conversation.history, conversation.knowledge);
+            let mut request = conversation.persona.request(messages);
+            request.model = model.clone();
+
+            let e = match self.send_request(request, channel_id, user).await {
+                Ok(response) => break Ok((response, knowledge)),
+                Err(e) => e,
+            };
+            let kind = e.kind();
+            error!(
+                "Error getting response from {} with {} ({:?}): {}",
+                self.backend.name(),
+                model,
+                kind,
+                e
+            );
+
+            if kind == LlmErrorKind::ContextLength && conversation.trim_history() {
+                info!(
+                    "Trimmed the conversation to {} messages",
+                    conversation.history.len()
+                );
+                continue;
+            }
+            if kind.is_transient() && retries < self.retry.max_retries() {
+                let delay = self.retry.delay(retries);
+                retries += 1;
+                info!(
+                    "Retrying in {:?} ({}/{})",
+                    delay,
+                    retries,
+                    self.retry.max_retries()
+                );
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+            // Another model will not fix a bad API key
+            match self.retry.fallback_model() {
+                Some(fallback) if fallback != model && kind != LlmErrorKind::Auth => {
+                    info!("Falling back to {}", fallback);
+                    model = fallback.to_string();
+                    // The fallback model gets a single attempt
+                    retries = self.retry.max_retries();
+                }
+                _ => break Err(kind),
             }
         };
 
-        // Build output from stream
-        let mut output: Vec<ResponseChunk> = Vec::new();
-        while let Some(chunck) = stream.next().await {
-            match chunck {
-                ResponseChunk::Content {
-                    delta,
-                    response_index,
-                } => output.push(ResponseChunk::Content {
-                    delta,
-                    response_index,
-                }),
-                other => output.push(other),
-            }
+        let (response, knowledge) = match result {
+            Ok(result) => result,
+            Err(kind) => return AiReply::notice(kind.user_message()),
+        };
+
+        // Moderated once, before it is kept, so the history and the channel get the same text
+        let mut message = response.to_message();
+        let (content, incident) = self.moderator.moderate_output(message.content).await;
+        message.content = content.clone();
+        conversation.history.push(message);
+
+        let blocked = incident
+            .as_ref()
+            .is_some_and(|incident| incident.must_block);
+        let content = match KnowledgeBase::citations(&knowledge, &response.content) {
+            Some(citations) if !blocked => format!("{}\n\n{}", content, citations),
+            _ => content,
+        };
+        AiReply { content, incident }
+    }
+
+    /// Builds the messages sent to the AI from the history
+    /// Returns the messages and the knowledge base chunks included in them
+    fn prepare_messages(
+        &self,
+        history: &[ChatMessage],
+        use_knowledge: bool,
+    ) -> (Vec<ChatMessage>, Vec<KnowledgeChunk>) {
+        // Images are only sent with the turn they were posted in, Discord links to them expire
+        let mut messages = history.to_vec();
+        let last = messages.len().saturating_sub(1);
+        for message in &mut messages[..last] {
+            message.images.clear();
         }
-        let messages = ChatMessage::from_response_chunks(output);
-        let mut res = String::new();
-        for message in messages {
-            res.push_str(&message.content);
+
+        // The knowledge is only given for the current message, it is not kept in the history
+        let knowledge = match messages.last() {
+            Some(message) if use_knowledge && message.role == Role::User => {
+                self.knowledge.search(&message.content)
+            }
+            _ => vec![],
+        };
+        if !knowledge.is_empty() {
+            messages.insert(
+                last,
+                ChatMessage::new(Role::System, KnowledgeBase::to_prompt(&knowledge)),
+            );
         }
+        (messages, knowledge)
+    }
 
-        // Save the response to the conversation history
-        conversation.history.push(ChatMessage {
-            role: Role::Assistant,
-            content: res.clone(),
-            function_call: None,
-        });
+    /// Sends a single request to the backend and records its usage
+    async fn send_request(
+        &self,
+        request: ChatRequest,
+        channel_id: &ChannelId,
+        user: Option<UserId>,
+    ) -> Result<ChatResponse, LlmError> {
+        let model = request.model.clone();
+        // Not every backend reports the usage, it is estimated from the text when it does not
+        let prompt_tokens = request
+            .messages
+            .iter()
+            .map(|m| estimate_tokens(&m.content))
+            .sum();
+        let started = Instant::now();
+        let result = tokio::time::timeout(self.retry.timeout(), self.backend.chat(request))
+            .await
+            .unwrap_or(Err(LlmError::Timeout));
 
-        res
+        let (outcome, usage) = match &result {
+            Ok(response) => (
+                match response.finish_reason.as_deref() {
+                    Some("length") => Outcome::Truncated,
+                    _ => Outcome::Success,
+                },
+                response.usage.unwrap_or(Usage {
+                    prompt_tokens,
+                    completion_tokens: estimate_tokens(&response.content),
+                }),
+            ),
+            Err(_) => (
+                Outcome::Error,
+                Usage {
+                    prompt_tokens,
+                    completion_tokens: 0,
+                },
+            ),
+        };
+        self.usage.record(UsageRecord {
+            model,
+            user,
+            channel: *channel_id,
+            usage,
+            latency: started.elapsed(),
+            outcome,
+        });
+        result
     }
 }
+
+/// Roughly estimates the number of tokens in a text, about 4 characters per token
+fn estimate_tokens(text: &str) -> u32 {
+    (text.chars().count() as u32).div_ceil(4)
+}
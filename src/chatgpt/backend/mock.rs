@@ -0,0 +1,120 @@
+use std::{collections::VecDeque, sync::Mutex, time::Duration};
+
+use serenity::{
+    async_trait,
+    futures::{stream, StreamExt},
+};
+
+use super::{ChatChunk, ChatRequest, ChatStream, LlmBackend, LlmError, Role, Usage};
+
+/// A scripted response of the mock backend
+pub enum MockReply {
+    Content(String),
+    FunctionCall { name: String, arguments: String },
+    Error(LlmError),
+}
+
+/// A deterministic backend for tests
+/// Scripted replies are returned in order. Once they run out, the backend
+/// echoes the last user message as `mock reply to: <message>`
+#[derive(Default)]
+pub struct MockBackend {
+    replies: Mutex<VecDeque<MockReply>>,
+    requests: Mutex<Vec<ChatRequest>>,
+    /// How long each request takes before the response starts streaming
+    delay: Duration,
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes every request take the given time
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn push_reply(&self, content: &str) {
+        self.push(MockReply::Content(content.to_string()));
+    }
+
+    pub fn push_function_call(&self, name: &str, arguments: &str) {
+        self.push(MockReply::FunctionCall {
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        });
+    }
+
+    pub fn push_error(&self, error: LlmError) {
+        self.push(MockReply::Error(error));
+    }
+
+    pub fn push(&self, reply: MockReply) {
+        self.replies.lock().unwrap().push_back(reply);
+    }
+
+    /// All the requests the backend has received, in order
+    pub fn requests(&self) -> Vec<ChatRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl LlmBackend for MockBackend {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn stream_chat(&self, request: ChatRequest) -> Result<ChatStream, LlmError> {
+        // Words stand in for tokens
+        let prompt_tokens = request
+            .messages
+            .iter()
+            .map(|m| m.content.split_whitespace().count() as u32)
+            .sum();
+        let last_user_message = request
+            .messages
+            .iter()
+            .rev()
+            .find(|m| m.role == Role::User)
+            .map(|m| m.content.clone())
+            .unwrap_or_default();
+        self.requests.lock().unwrap().push(request);
+
+        if !self.delay.is_zero() {
+            tokio::time::sleep(self.delay).await;
+        }
+
+        let reply =
+            self.replies.lock().unwrap().pop_front().unwrap_or_else(|| {
+                MockReply::Content(format!("mock reply to: {}", last_user_message))
+            });
+
+        let chunks = match reply {
+            MockReply::Content(content) => {
+                // Stream the reply word by word like a real backend would
+                let mut chunks = content
+                    .split_inclusive(' ')
+                    .map(|word| Ok(ChatChunk::Content(word.to_string())))
+                    .collect::<Vec<_>>();
+                chunks.push(Ok(ChatChunk::Finish("stop".to_string())));
+                chunks.push(Ok(ChatChunk::Usage(Usage {
+                    prompt_tokens,
+                    completion_tokens: content.split_whitespace().count() as u32,
+                })));
+                chunks
+            }
+            MockReply::FunctionCall { name, arguments } => vec![
+                Ok(ChatChunk::FunctionCall {
+                    name: Some(name),
+                    arguments,
+                }),
+                Ok(ChatChunk::Finish("function_call".to_string())),
+            ],
+            MockReply::Error(e) => return Err(e),
+        };
+        Ok(stream::iter(chunks).boxed())
+    }
+}
@@ -0,0 +1,308 @@
+mod mock;
+mod openai;
+
+use std::{
+    fmt::{Display, Formatter},
+    pin::Pin,
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use serenity::{
+    async_trait,
+    futures::{Stream, StreamExt},
+};
+
+use crate::config::env_var;
+
+pub use mock::{MockBackend, MockReply};
+pub use openai::OpenAiBackend;
+
+/// The role of the sender of a chat message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+    Function,
+}
+
+/// A function the model asked to call
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct FunctionCall {
+    pub name: String,
+    /// The arguments as a JSON encoded string
+    pub arguments: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+    /// The name of the function, for `Role::Function` messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<FunctionCall>,
+    /// URLs of images sent along with the content, for models that can see images
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+}
+
+impl ChatMessage {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            name: None,
+            function_call: None,
+            images: vec![],
+        }
+    }
+
+    /// Sends the images along with the message
+    pub fn with_images(mut self, images: Vec<String>) -> Self {
+        self.images = images;
+        self
+    }
+}
+
+/// A chat completion request
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Vec<ChatMessage>,
+    pub temperature: f32,
+    pub max_tokens: Option<u32>,
+}
+
+/// A piece of a streamed chat completion
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatChunk {
+    /// A piece of the message content
+    Content(String),
+    /// A piece of a function call. The name is only sent with the first piece
+    FunctionCall {
+        name: Option<String>,
+        arguments: String,
+    },
+    /// The model finished the response, e.g. `stop` or `length`
+    Finish(String),
+    /// The number of tokens used, sent once at the end of the stream
+    Usage(Usage),
+}
+
+/// The tokens used by a completion
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+/// A complete chat completion, built from the streamed chunks
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChatResponse {
+    pub content: String,
+    pub function_call: Option<FunctionCall>,
+    pub finish_reason: Option<String>,
+    /// None if the backend did not report the usage
+    pub usage: Option<Usage>,
+}
+
+impl ChatResponse {
+    /// Adds a streamed chunk to the response
+    pub fn push(&mut self, chunk: ChatChunk) {
+        match chunk {
+            ChatChunk::Content(delta) => self.content.push_str(&delta),
+            ChatChunk::FunctionCall { name, arguments } => {
+                let call = self.function_call.get_or_insert_with(FunctionCall::default);
+                if let Some(name) = name {
+                    call.name.push_str(&name);
+                }
+                call.arguments.push_str(&arguments);
+            }
+            ChatChunk::Finish(reason) => self.finish_reason = Some(reason),
+            ChatChunk::Usage(usage) => self.usage = Some(usage),
+        }
+    }
+
+    /// Converts the response to the assistant message that is saved in the history
+    pub fn to_message(&self) -> ChatMessage {
+        ChatMessage {
+            role: Role::Assistant,
+            content: self.content.clone(),
+            name: None,
+            function_call: self.function_call.clone(),
+            images: vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LlmError {
+    /// The request could not be sent or the response could not be read
+    Http(reqwest::Error),
+    /// The API responded with an error
+    Api {
+        status: u16,
+        message: String,
+        code: Option<String>,
+    },
+    /// The streamed response was malformed
+    Stream(String),
+    /// The backend took too long to respond
+    Timeout,
+}
+
+/// What went wrong with a request, deciding how it is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LlmErrorKind {
+    RateLimit,
+    /// The conversation is longer than the model's context
+    ContextLength,
+    Timeout,
+    /// The API key is missing, invalid or out of quota
+    Auth,
+    /// The backend failed or could not be reached
+    Server,
+    /// The request was rejected for any other reason
+    Invalid,
+}
+
+impl LlmErrorKind {
+    /// Whether the same request may succeed if it is sent again
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            LlmErrorKind::RateLimit | LlmErrorKind::Timeout | LlmErrorKind::Server
+        )
+    }
+
+    /// The message shown to the user when the request could not be completed
+    pub fn user_message(&self) -> &'static str {
+        match self {
+            LlmErrorKind::RateLimit => {
+                "The AI is getting too many requests right now. Please try again in a minute!"
+            }
+            LlmErrorKind::ContextLength => {
+                "This conversation has grown too long for the AI. Start a new one with `$ai chat`!"
+            }
+            LlmErrorKind::Timeout => "The AI took too long to respond. Please try again!",
+            LlmErrorKind::Auth => {
+                "The bot could not sign in to the AI service. Please let an admin know!"
+            }
+            LlmErrorKind::Server => {
+                "The AI service is having problems right now. Please try again later!"
+            }
+            LlmErrorKind::Invalid => {
+                "There was an error processing your request. Please try again later!"
+            }
+        }
+    }
+}
+
+impl LlmError {
+    pub fn kind(&self) -> LlmErrorKind {
+        match self {
+            LlmError::Http(e) if e.is_timeout() => LlmErrorKind::Timeout,
+            LlmError::Http(_) | LlmError::Stream(_) => LlmErrorKind::Server,
+            LlmError::Timeout => LlmErrorKind::Timeout,
+            LlmError::Api {
+                status,
+                message,
+                code,
+            } => {
+                let code = code.as_deref().unwrap_or_default();
+                if code == "context_length_exceeded" || message.contains("maximum context length") {
+                    return LlmErrorKind::ContextLength;
+                }
+                match status {
+                    // Running out of quota is reported as a rate limit, but waiting will not help
+                    429 if code == "insufficient_quota" => LlmErrorKind::Auth,
+                    429 => LlmErrorKind::RateLimit,
+                    401 | 403 => LlmErrorKind::Auth,
+                    408 | 504 => LlmErrorKind::Timeout,
+                    500..=599 => LlmErrorKind::Server,
+                    _ => LlmErrorKind::Invalid,
+                }
+            }
+        }
+    }
+}
+
+impl Display for LlmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LlmError::Http(e) => write!(f, "HTTP error: {}", e),
+            LlmError::Api {
+                status,
+                message,
+                code,
+            } => write!(
+                f,
+                "API error ({}{}): {}",
+                status,
+                code.as_ref()
+                    .map(|c| format!(", {}", c))
+                    .unwrap_or_default(),
+                message
+            ),
+            LlmError::Stream(message) => write!(f, "Stream error: {}", message),
+            LlmError::Timeout => write!(f, "Timed out"),
+        }
+    }
+}
+
+impl std::error::Error for LlmError {}
+
+impl From<reqwest::Error> for LlmError {
+    fn from(e: reqwest::Error) -> Self {
+        LlmError::Http(e)
+    }
+}
+
+pub type ChatStream = Pin<Box<dyn Stream<Item = Result<ChatChunk, LlmError>> + Send>>;
+
+/// A service that can complete chat conversations
+#[async_trait]
+pub trait LlmBackend: Send + Sync {
+    /// The name of the backend, used in logs
+    fn name(&self) -> &str;
+
+    /// Starts a streaming chat completion
+    async fn stream_chat(&self, request: ChatRequest) -> Result<ChatStream, LlmError>;
+
+    /// Runs a chat completion and collects the streamed response
+    async fn chat(&self, request: ChatRequest) -> Result<ChatResponse, LlmError> {
+        let mut stream = self.stream_chat(request).await?;
+        let mut response = ChatResponse::default();
+        while let Some(chunk) = stream.next().await {
+            response.push(chunk?);
+        }
+        Ok(response)
+    }
+}
+
+/// Creates the backend configured in the environment
+/// `AI_BASE_URL` points the bot at any OpenAI compatible server (e.g. llama.cpp or Ollama),
+/// otherwise the OpenAI API is used with `OPENAI_API_KEY`.
+/// `AI_STREAM_USAGE=true` asks a compatible server for token usage, for servers that support it
+pub fn backend_from_env() -> Arc<dyn LlmBackend> {
+    match env_var("AI_BASE_URL") {
+        Some(base_url) => Arc::new(
+            OpenAiBackend::compatible(
+                &base_url,
+                env_var("AI_API_KEY").or_else(|| env_var("OPENAI_API_KEY")),
+            )
+            .with_usage_reporting(
+                env_var("AI_STREAM_USAGE")
+                    .map(|v| v == "true")
+                    .unwrap_or(false),
+            ),
+        ),
+        None => Arc::new(OpenAiBackend::new(
+            &env_var("OPENAI_API_KEY").expect("OPENAI_API_KEY is required"),
+        )),
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use serenity::{
+    async_trait,
+    futures::{stream, StreamExt},
+};
+use tracing::error;
+
+use super::{
+    ChatChunk, ChatMessage, ChatRequest, ChatStream, FunctionCall, LlmBackend, LlmError, Role,
+    Usage,
+};
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// A backend for the OpenAI chat completions API, or any server that implements it
+pub struct OpenAiBackend {
+    client: reqwest::Client,
+    name: String,
+    /// The URL the `/chat/completions` path is appended to
+    base_url: String,
+    api_key: Option<String>,
+    /// Ask for the token usage at the end of the stream with `stream_options`
+    /// Some compatible servers reject the field, so it is only sent to OpenAI by default
+    include_usage: bool,
+}
+
+impl OpenAiBackend {
+    /// Creates a backend for the OpenAI API
+    pub fn new(api_key: &str) -> Self {
+        Self::with_base_url("openai", OPENAI_BASE_URL, Some(api_key.to_string()))
+            .with_usage_reporting(true)
+    }
+
+    /// Creates a backend for an OpenAI compatible server, e.g. `http://localhost:8080/v1` for llama.cpp
+    pub fn compatible(base_url: &str, api_key: Option<String>) -> Self {
+        Self::with_base_url(base_url, base_url, api_key)
+    }
+
+    fn with_base_url(name: &str, base_url: &str, api_key: Option<String>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(120))
+            .build()
+            .expect("Failed to create HTTP client");
+        Self {
+            client,
+            name: name.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            include_usage: false,
+        }
+    }
+
+    /// Enables or disables asking the server for the token usage of each request
+    pub fn with_usage_reporting(mut self, include_usage: bool) -> Self {
+        self.include_usage = include_usage;
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct CompletionRequest<'a> {
+    model: &'a str,
+    messages: Vec<WireMessage<'a>>,
+    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Serialize)]
+struct StreamOptions {
+    /// Asks for the token usage to be sent at the end of the stream
+    include_usage: bool,
+}
+
+/// A message as the API expects it, images are sent as content parts
+#[derive(Serialize)]
+struct WireMessage<'a> {
+    role: Role,
+    content: WireContent<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function_call: Option<&'a FunctionCall>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum WireContent<'a> {
+    Text(&'a str),
+    Parts(Vec<ContentPart<'a>>),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentPart<'a> {
+    Text { text: &'a str },
+    ImageUrl { image_url: ImageUrl<'a> },
+}
+
+#[derive(Serialize)]
+struct ImageUrl<'a> {
+    url: &'a str,
+}
+
+impl<'a> From<&'a ChatMessage> for WireMessage<'a> {
+    fn from(message: &'a ChatMessage) -> Self {
+        let content = if message.images.is_empty() {
+            WireContent::Text(&message.content)
+        } else {
+            let mut parts = vec![ContentPart::Text {
+                text: &message.content,
+            }];
+            parts.extend(message.images.iter().map(|url| ContentPart::ImageUrl {
+                image_url: ImageUrl { url },
+            }));
+            WireContent::Parts(parts)
+        };
+        Self {
+            role: message.role,
+            content,
+            name: message.name.as_deref(),
+            function_call: message.function_call.as_ref(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorBody,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    message: String,
+    code: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct StreamResponse {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct StreamChoice {
+    #[serde(default)]
+    delta: StreamDelta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct StreamDelta {
+    content: Option<String>,
+    function_call: Option<StreamFunctionCall>,
+}
+
+#[derive(Deserialize)]
+struct StreamFunctionCall {
+    name: Option<String>,
+    #[serde(default)]
+    arguments: String,
+}
+
+#[async_trait]
+impl LlmBackend for OpenAiBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn stream_chat(&self, request: ChatRequest) -> Result<ChatStream, LlmError> {
+        let mut builder = self
+            .client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&CompletionRequest {
+                model: &request.model,
+                messages: request.messages.iter().map(WireMessage::from).collect(),
+                temperature: request.temperature,
+                max_tokens: request.max_tokens,
+                stream: true,
+                stream_options: self.include_usage.then_some(StreamOptions {
+                    include_usage: true,
+                }),
+            });
+        if let Some(api_key) = &self.api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+        let response = builder.send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let (message, code) = match serde_json::from_str::<ErrorResponse>(&body) {
+                Ok(e) => (
+                    e.error.message,
+                    e.error.code.map(|c| match c {
+                        serde_json::Value::String(s) => s,
+                        other => other.to_string(),
+                    }),
+                ),
+                Err(_) => (body, None),
+            };
+            return Err(LlmError::Api {
+                status: status.as_u16(),
+                message,
+                code,
+            });
+        }
+
+        let state = StreamState {
+            bytes: response
+                .bytes_stream()
+                .map(|bytes| bytes.map(|b| b.to_vec()))
+                .boxed(),
+            parser: SseParser::default(),
+            pending: VecDeque::new(),
+            done: false,
+        };
+        let stream = stream::unfold(state, |mut state| async move {
+            loop {
+                if let Some(item) = state.pending.pop_front() {
+                    return Some((item, state));
+                }
+                if state.done {
+                    return None;
+                }
+                match state.bytes.next().await {
+                    Some(Ok(bytes)) => {
+                        for data in state.parser.push(&bytes) {
+                            if data == "[DONE]" {
+                                state.done = true;
+                                break;
+                            }
+                            match parse_chunk(&data) {
+                                Ok(chunks) => state.pending.extend(chunks.into_iter().map(Ok)),
+                                Err(e) => state.pending.push_back(Err(e)),
+                            }
+                        }
+                    }
+                    Some(Err(e)) => {
+                        state.pending.push_back(Err(e.into()));
+                        state.done = true;
+                    }
+                    None => state.done = true,
+                }
+            }
+        });
+
+        Ok(stream.boxed())
+    }
+}
+
+struct StreamState {
+    bytes: stream::BoxStream<'static, reqwest::Result<Vec<u8>>>,
+    parser: SseParser,
+    pending: VecDeque<Result<ChatChunk, LlmError>>,
+    done: bool,
+}
+
+/// Converts a single streamed event into chunks
+fn parse_chunk(data: &str) -> Result<Vec<ChatChunk>, LlmError> {
+    let response = serde_json::from_str::<StreamResponse>(data).map_err(|e| {
+        error!("Invalid streamed response: {}", data);
+        LlmError::Stream(e.to_string())
+    })?;
+
+    let mut chunks = Vec::new();
+    // Only a single reply is requested, so only the first choice is used
+    if let Some(choice) = response.choices.into_iter().next() {
+        if let Some(content) = choice.delta.content {
+            if !content.is_empty() {
+                chunks.push(ChatChunk::Content(content));
+            }
+        }
+        if let Some(call) = choice.delta.function_call {
+            chunks.push(ChatChunk::FunctionCall {
+                name: call.name,
+                arguments: call.arguments,
+            });
+        }
+        if let Some(reason) = choice.finish_reason {
+            chunks.push(ChatChunk::Finish(reason));
+        }
+    }
+    if let Some(usage) = response.usage {
+        chunks.push(ChatChunk::Usage(usage));
+    }
+    Ok(chunks)
+}
+
+/// Splits a server sent event stream into the data of each event
+#[derive(Default)]
+struct SseParser {
+    buffer: Vec<u8>,
+}
+
+impl SseParser {
+    /// Adds bytes to the buffer and returns the data of every complete line
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(bytes);
+        let mut data = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line = self.buffer.drain(..=pos).collect::<Vec<u8>>();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if let Some(payload) = line.strip_prefix("data:") {
+                data.push(payload.trim_start().to_string());
+            }
+        }
+        data
+    }
+}
@@ -3,17 +3,33 @@ use serenity::{
         macros::{check, command, group},
         Args, CommandError, CommandOptions, CommandResult, Reason,
     },
-    model::prelude::{Channel, ChannelType, Message},
+    model::prelude::{AttachmentType, Channel, ChannelType, Message, UserId},
     prelude::Context,
 };
+use tracing::error;
 
-use crate::{bot, chatgpt::animeboys_ai::AnimeboysAI};
+use crate::{
+    bot,
+    chatgpt::{
+        animeboys_ai::AnimeboysAI,
+        context::UserTurn,
+        digest::DailyDigest,
+        export::{self, ExportFormat},
+        lifecycle::ConversationLifecycle,
+        moderation::Moderator,
+        persona::{DEFAULT_CHAT_PERSONA, DEFAULT_DEBUG_PERSONA},
+        sandbox::{CodeBlock, Sandbox},
+        usage::UsageTotals,
+    },
+};
 
 #[group("AI Commands")]
 #[prefixes("ai")]
 #[description("Commands for using the AI")]
 #[summary("Commands for using the AI")]
-#[commands(debug, chat, help, stop)]
+#[commands(
+    debug, chat, ask, tldr, personas, export, help, stop, ban, unban, reload, stats, digest
+)]
 #[default_command(chat)]
 struct AICommands;
 
@@ -24,20 +40,25 @@ struct AICommands;
 /// If the conversation is in a thread, then the thread will be deleted
 /// If the conversation is in a DM, then the conversation will be deleted
 async fn stop(ctx: &Context, msg: &Message) -> CommandResult {
-    let mut data = ctx.data.write().await;
-    let ai = data.get_mut::<AnimeboysAI>().unwrap();
+    let (ai, lifecycle) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<AnimeboysAI>().unwrap().clone(),
+            data.get::<ConversationLifecycle>().unwrap().clone(),
+        )
+    };
 
     // Check to see if the message was sent in an existing conversation
     let channel = msg.channel_id.to_channel(&ctx.http).await?;
-    if !ai.does_conversation_exist(&channel.id()) {
+    if !lifecycle.resume(&ai, &channel.id()).await {
         msg.channel_id
             .say(&ctx.http, "No conversation exists")
             .await?;
         return Ok(());
     }
 
-    // Remove the conversation from the ai struct
-    ai.remove_conversation(&channel.id()).await;
+    // Remove the conversation from the ai struct and any saved history
+    lifecycle.end(&ai, &channel.id()).await;
 
     // Check to see if the conversation is in a thread
     // If it is, then delete the thread
@@ -65,10 +86,21 @@ async fn stop(ctx: &Context, msg: &Message) -> CommandResult {
 async fn help(ctx: &Context, msg: &Message) -> CommandResult {
     let help = "
     >>> **AI Commands**
-    `$ai debug <code block>` - Debugs the given code
-    `$ai chat` - Starts a new conversation with the AI
+    `$ai debug [--persona <name>] [--run] <code block>` - Debugs the given code, `--run` runs it first
+    `$ai chat [--persona <name>]` - Starts a new conversation with the AI
+    `$ai ask [--persona <name>] <question>` - Answers a single question right here, without a thread
+    `$ai tldr [n]` - Summarizes the last n messages of the channel, 50 by default
+    `$ai personas` - Lists the personas the AI can take on
+    `$ai export [markdown|json]` - Uploads the current conversation as a file
     `$ai stop` - Stops the current conversation
+    React with 🔄 on the AI's last reply to regenerate it, or ⏩ to have it continue
+    Editing your last message in a conversation resends it to the AI
     `$ai help` - Displays this help message
+    `$ai ban <user>` - Bans a user from the AI features (admin only)
+    `$ai unban <user>` - Lifts a ban from the AI features (admin only)
+    `$ai reload` - Reloads the AI's knowledge base of FAQ files and pinned messages (admin only)
+    `$ai stats [days]` - Shows the AI's token usage and cost, the last 7 days by default (admin only)
+    `$ai digest [on|off|now]` - Opts the channel in or out of the daily digest, or posts it now (admin only)
     ";
     msg.channel_id.say(&ctx.http, help).await?;
     Ok(())
@@ -76,30 +108,293 @@ async fn help(ctx: &Context, msg: &Message) -> CommandResult {
 
 #[command]
 #[description("Chat with the AI")]
-#[usage("chat")]
+#[usage("chat [--persona <name>]")]
+#[example("chat --persona debug")]
+#[checks(AiAllowed)]
 /// Chat creates a new thread with the AI where you can chat with it
-async fn chat(ctx: &Context, msg: &Message, _args: Args) -> CommandResult {
-    let mut data = ctx.data.write().await;
-    let ai = data.get_mut::<AnimeboysAI>().unwrap();
+/// If used within an existing conversation with `--persona`, the persona of the conversation is switched
+async fn chat(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    // Only hold the data lock long enough to get the shared state
+    let (ai, moderator) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<AnimeboysAI>().unwrap().clone(),
+            data.get::<Moderator>().unwrap().clone(),
+        )
+    };
+    let persona = parse_flags(&mut args).persona;
+    if !check_persona(ctx, msg, &ai, persona.as_deref()).await? {
+        return Ok(());
+    }
+    if let Some(persona) = &persona {
+        // Switch the persona of an existing conversation
+        if ai.set_persona(&msg.channel_id, persona).await {
+            msg.channel_id
+                .say(&ctx.http, format!("Switched to the `{}` persona", persona))
+                .await?;
+            return Ok(());
+        }
+    }
 
-    let channel = check_for_conversation(ai, ctx, msg).await?;
+    let channel = check_for_conversation(&ai, ctx, msg).await?;
 
     // Save thread
     let res = ai
-        .create_conversation(&msg.author.name, &channel.id())
+        .create_conversation(
+            &msg.author.name,
+            msg.author.id,
+            &channel.id(),
+            persona.as_deref(),
+        )
         .await;
 
     // Start Typing
     let typing = ctx.http.start_typing(channel.id().0)?;
 
     // Send intro message, that the ai is ready
-    bot::send_message_in_streams(ctx, channel, res).await?;
+    bot::send_ai_response(ctx, &ai, &moderator, channel, res).await?;
 
     // Stop Typing
     drop(typing);
     Ok(())
 }
 
+#[command]
+#[description("Answers a single question without starting a conversation")]
+#[usage("ask [--persona <name>] <question>")]
+#[example("ask what is the difference between a thread and a process?")]
+#[min_args(1)]
+#[checks(AiAllowed)]
+/// Ask replies to the question in the channel it was asked in
+/// The question and answer are not kept, follow up questions need `$ai chat`
+async fn ask(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    // Only hold the data lock long enough to get the shared state
+    let (ai, moderator) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<AnimeboysAI>().unwrap().clone(),
+            data.get::<Moderator>().unwrap().clone(),
+        )
+    };
+    let flags = parse_flags(&mut args);
+    if !check_persona(ctx, msg, &ai, flags.persona.as_deref()).await? {
+        return Ok(());
+    }
+
+    let turn = UserTurn::from_message(ctx, msg).await;
+    let mut turn = turn.with_content(args.rest());
+    // One-shot answers never use the channel's conversation
+    let vision = ai
+        .resolve_persona(flags.persona.as_deref(), DEFAULT_CHAT_PERSONA)
+        .vision;
+    ai.read_images(&mut turn, vision).await;
+    if !bot::moderate_ai_input(ctx, &moderator, msg, &mut turn).await {
+        return Ok(());
+    }
+
+    let typing = ctx.http.start_typing(msg.channel_id.0)?;
+    let res = ai
+        .ask(&turn, &msg.channel_id, flags.persona.as_deref())
+        .await;
+    moderator
+        .log_output_incident(&ctx.http, msg.channel_id, res.incident.as_ref())
+        .await;
+    bot::send_text_in_chunks(&ctx.http, msg.channel_id, &res.content).await?;
+    drop(typing);
+    Ok(())
+}
+
+#[command]
+#[description("Summarizes the last messages of the channel")]
+#[usage("tldr [n]")]
+#[example("tldr 100")]
+#[min_args(0)]
+#[max_args(1)]
+#[checks(AiAllowed)]
+/// Tldr summarizes the last n messages sent before the command, at most 100
+/// Commands and messages without text are left out
+async fn tldr(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (ai, moderator) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<AnimeboysAI>().unwrap().clone(),
+            data.get::<Moderator>().unwrap().clone(),
+        )
+    };
+    let count = args.single::<u64>().unwrap_or(50).clamp(1, 100);
+
+    let history = msg
+        .channel_id
+        .messages(&ctx.http, |m| m.before(msg.id).limit(count))
+        .await?;
+    // Messages are returned newest first
+    let messages = history
+        .iter()
+        .rev()
+        .filter(|m| !m.content.trim().is_empty() && !m.content.starts_with('$'))
+        .map(|m| (m.author.name.clone(), m.content.clone()))
+        .collect::<Vec<_>>();
+    if messages.is_empty() {
+        msg.channel_id
+            .say(&ctx.http, "There is nothing to summarize")
+            .await?;
+        return Ok(());
+    }
+
+    let typing = ctx.http.start_typing(msg.channel_id.0)?;
+    let res = ai
+        .summarize(&messages, &msg.channel_id, msg.author.id)
+        .await;
+    moderator
+        .log_output_incident(&ctx.http, msg.channel_id, res.incident.as_ref())
+        .await;
+    bot::send_text_in_chunks(&ctx.http, msg.channel_id, &res.content).await?;
+    drop(typing);
+    Ok(())
+}
+
+#[command]
+#[description("Lists the personas the AI can take on")]
+#[min_args(0)]
+#[max_args(0)]
+async fn personas(ctx: &Context, msg: &Message) -> CommandResult {
+    let ai = {
+        let data = ctx.data.read().await;
+        data.get::<AnimeboysAI>().unwrap().clone()
+    };
+
+    let mut personas = String::from(">>> **AI Personas**\n");
+    for persona in ai.personas().all() {
+        personas.push_str(&format!(
+            "`{}` ({}) - {}\n",
+            persona.name, persona.model, persona.description
+        ));
+    }
+    if let Some(current) = ai.conversation_persona(&msg.channel_id).await {
+        personas.push_str(&format!("\nThis conversation is using `{}`", current));
+    }
+    msg.channel_id.say(&ctx.http, personas).await?;
+    Ok(())
+}
+
+#[command]
+#[description("Exports the current conversation as a file")]
+#[usage("export [markdown|json]")]
+#[example("export json")]
+#[min_args(0)]
+#[max_args(1)]
+/// Export uploads the conversation of the current thread or DM as a Markdown or JSON file
+/// The system prompt is left out and every message is labelled with who sent it
+async fn export(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let format = match args.single::<String>() {
+        Ok(format) => match format.parse::<ExportFormat>() {
+            Ok(format) => format,
+            Err(e) => {
+                msg.channel_id.say(&ctx.http, e).await?;
+                return Ok(());
+            }
+        },
+        Err(_) => ExportFormat::default(),
+    };
+
+    let (ai, lifecycle) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<AnimeboysAI>().unwrap().clone(),
+            data.get::<ConversationLifecycle>().unwrap().clone(),
+        )
+    };
+    if !lifecycle.resume(&ai, &msg.channel_id).await {
+        msg.channel_id
+            .say(&ctx.http, "No conversation exists")
+            .await?;
+        return Ok(());
+    }
+    let history = match ai.get_conversation(&msg.channel_id) {
+        Some(conversation) => conversation.lock().await.history.clone(),
+        None => return Ok(()),
+    };
+
+    let title = match msg.channel_id.to_channel(&ctx.http).await? {
+        Channel::Guild(channel) => channel.name,
+        _ => format!("Conversation with {}", msg.author.name),
+    };
+    let transcript = export::render(&title, &history, format);
+    let filename = format!("conversation-{}.{}", msg.channel_id.0, format.extension());
+
+    msg.channel_id
+        .send_message(&ctx.http, |m| {
+            m.content("Here is the conversation so far")
+                .add_file(AttachmentType::Bytes {
+                    data: transcript.into_bytes().into(),
+                    filename,
+                })
+        })
+        .await?;
+    Ok(())
+}
+
+/// Flags that can be given before the rest of the arguments of an AI command
+#[derive(Default)]
+struct AiFlags {
+    /// `--persona <name>`
+    persona: Option<String>,
+    /// `--run`
+    run: bool,
+}
+
+/// Parses the leading flags from the arguments
+fn parse_flags(args: &mut Args) -> AiFlags {
+    let mut flags = AiFlags::default();
+    loop {
+        match args.current() {
+            Some("--persona") => {
+                args.advance();
+                flags.persona = args.single::<String>().ok();
+            }
+            Some("--run") => {
+                args.advance();
+                flags.run = true;
+            }
+            _ => break,
+        }
+    }
+    flags
+}
+
+/// Checks that the persona given with `--persona` exists
+/// If it does not, the user is told which personas there are and false is returned
+async fn check_persona(
+    ctx: &Context,
+    msg: &Message,
+    ai: &AnimeboysAI,
+    persona: Option<&str>,
+) -> Result<bool, CommandError> {
+    let persona = match persona {
+        Some(persona) => persona,
+        None => return Ok(true),
+    };
+    if ai.personas().get(persona).is_some() {
+        return Ok(true);
+    }
+    let names = ai
+        .personas()
+        .all()
+        .map(|p| format!("`{}`", p.name))
+        .collect::<Vec<_>>()
+        .join(", ");
+    msg.channel_id
+        .say(
+            &ctx.http,
+            format!(
+                "Unknown persona `{}`. Available personas: {}",
+                persona, names
+            ),
+        )
+        .await?;
+    Ok(false)
+}
+
 /// Checks to see if a conversation exists within the ai struct
 /// If it does not exist, then it creates a new thread
 /// If it does exist, then it returns the channel
@@ -135,34 +430,292 @@ async fn check_for_conversation(
 }
 
 #[command]
-#[usage("debug <code block>")]
-#[example("debug ```print('Hello World!')```")]
-#[checks(CodeBlock)]
+#[usage("debug [--persona <name>] [--run] <code block>")]
+#[example("debug --run ```py\nprint('Hello World!')```")]
+#[checks(AiAllowed, CodeBlock)]
 /// Debug creates a thread (if within a server) and debugs the given code
 /// The code must be in a code block
 /// After the thread is created (if within a server) you can continue to converse with
 /// the AI in the thread or DM without having to use the $ai command
-async fn debug(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
-    let mut data = ctx.data.write().await;
-    let ai = data.get_mut::<AnimeboysAI>().unwrap();
+/// With `--run` the code is first run in the sandbox and the output is given to the AI
+async fn debug(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    // Only hold the data lock long enough to get the shared state
+    let (ai, moderator) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<AnimeboysAI>().unwrap().clone(),
+            data.get::<Moderator>().unwrap().clone(),
+        )
+    };
+    let flags = parse_flags(&mut args);
+    let code = args.rest();
+
+    if !check_persona(ctx, msg, &ai, flags.persona.as_deref()).await? {
+        return Ok(());
+    }
+
+    // Moderate the message before the code is run, blocked messages never reach the sandbox
+    let turn = UserTurn::from_message(ctx, msg).await;
+    let mut turn = turn.with_content(code);
+    let vision = ai
+        .conversation_vision(
+            &msg.channel_id,
+            flags.persona.as_deref(),
+            DEFAULT_DEBUG_PERSONA,
+        )
+        .await;
+    ai.read_images(&mut turn, vision).await;
+    if !bot::moderate_ai_input(ctx, &moderator, msg, &mut turn).await {
+        return Ok(());
+    }
+
+    // Run the code before asking the AI so it can see the output
+    let mut run_summary = None;
+    if flags.run {
+        let sandbox = {
+            let data = ctx.data.read().await;
+            data.get::<Sandbox>().cloned().unwrap_or_default()
+        };
+        let block = CodeBlock::extract(code).ok_or("Invalid code block")?;
+        match sandbox.run(&block).await {
+            Ok(result) => {
+                turn.content.push_str("\n\n");
+                turn.content.push_str(&result.to_prompt());
+                run_summary = Some(result.summary());
+            }
+            Err(e) => run_summary = Some(e.to_string()),
+        }
+    }
 
     // Check to see if the message was sent in an existing conversation
-    let channel = check_for_conversation(ai, ctx, msg).await?;
+    let channel = check_for_conversation(&ai, ctx, msg).await?;
+    if let Some(run_summary) = run_summary {
+        channel.id().say(&ctx.http, run_summary).await?;
+    }
     // Start Typing
     let typing = ctx.http.start_typing(channel.id().0)?;
 
-    let code = args.rest();
-
-    let res = ai.debug(&code, &channel.id()).await;
+    let res = ai
+        .debug(&turn, &channel.id(), flags.persona.as_deref())
+        .await;
 
     // if the response is too long, then send it in multiple messages
-    bot::send_message_in_streams(ctx, channel, res).await?;
+    bot::send_ai_response(ctx, &ai, &moderator, channel, res).await?;
 
     // Stop typing
     drop(typing);
     Ok(())
 }
 
+#[command]
+#[description("Bans a user from the AI features")]
+#[usage("ban <user>")]
+#[example("ban @user")]
+#[min_args(1)]
+#[max_args(1)]
+#[only_in(guilds)]
+#[required_permissions("ADMINISTRATOR")]
+async fn ban(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let user_id = match args.single::<UserId>() {
+        Ok(user_id) => user_id,
+        Err(_) => {
+            msg.channel_id
+                .say(&ctx.http, "Invalid user, mention them or use their id")
+                .await?;
+            return Ok(());
+        }
+    };
+    let moderator = {
+        let data = ctx.data.read().await;
+        data.get::<Moderator>().unwrap().clone()
+    };
+
+    let reply = if moderator.ban(user_id).await {
+        format!("<@{}> is now banned from the AI features", user_id.0)
+    } else {
+        format!("<@{}> is already banned from the AI features", user_id.0)
+    };
+    msg.channel_id.say(&ctx.http, reply).await?;
+    Ok(())
+}
+
+#[command]
+#[description("Lifts a ban from the AI features")]
+#[usage("unban <user>")]
+#[example("unban @user")]
+#[min_args(1)]
+#[max_args(1)]
+#[only_in(guilds)]
+#[required_permissions("ADMINISTRATOR")]
+async fn unban(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let user_id = match args.single::<UserId>() {
+        Ok(user_id) => user_id,
+        Err(_) => {
+            msg.channel_id
+                .say(&ctx.http, "Invalid user, mention them or use their id")
+                .await?;
+            return Ok(());
+        }
+    };
+    let moderator = {
+        let data = ctx.data.read().await;
+        data.get::<Moderator>().unwrap().clone()
+    };
+
+    let reply = if moderator.unban(&user_id).await {
+        format!("<@{}> can use the AI features again", user_id.0)
+    } else {
+        format!("<@{}> is not banned from the AI features", user_id.0)
+    };
+    msg.channel_id.say(&ctx.http, reply).await?;
+    Ok(())
+}
+
+#[command]
+#[description("Reloads the AI's knowledge base of FAQ files and pinned messages")]
+#[min_args(0)]
+#[max_args(0)]
+#[only_in(guilds)]
+#[required_permissions("ADMINISTRATOR")]
+async fn reload(ctx: &Context, msg: &Message) -> CommandResult {
+    let ai = {
+        let data = ctx.data.read().await;
+        data.get::<AnimeboysAI>().unwrap().clone()
+    };
+    let count = ai.knowledge().reload(&ctx.http).await;
+    msg.channel_id
+        .say(
+            &ctx.http,
+            format!("Loaded {} entries into the knowledge base", count),
+        )
+        .await?;
+    Ok(())
+}
+
+#[command]
+#[description("Shows the AI's token usage and estimated cost")]
+#[usage("stats [days]")]
+#[example("stats 30")]
+#[min_args(0)]
+#[max_args(1)]
+#[only_in(guilds)]
+#[required_permissions("ADMINISTRATOR")]
+async fn stats(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let days = args.single::<u64>().unwrap_or(7).max(1);
+    let ai = {
+        let data = ctx.data.read().await;
+        data.get::<AnimeboysAI>().unwrap().clone()
+    };
+    let report = ai.usage().report(days);
+    if report.total.requests == 0 {
+        msg.channel_id
+            .say(
+                &ctx.http,
+                format!("The AI has not been used in the last {} days", days),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let line = |name: String, totals: &UsageTotals| {
+        format!(
+            "{} - {} requests, {} errors, {} prompt + {} completion tokens, {}ms avg, ${:.4}\n",
+            name,
+            totals.requests,
+            totals.errors,
+            totals.prompt_tokens,
+            totals.completion_tokens,
+            totals.average_latency_ms(),
+            totals.cost_usd
+        )
+    };
+    let mut stats = format!(">>> **AI usage over the last {} days**\n", days);
+    stats.push_str(&line("**Total**".to_string(), &report.total));
+    stats.push_str("**Models**\n");
+    for (model, totals) in &report.models {
+        stats.push_str(&line(format!("`{}`", model), totals));
+    }
+    stats.push_str("**Top users**\n");
+    for (user, totals) in report.users.iter().take(5) {
+        stats.push_str(&line(format!("<@{}>", user.0), totals));
+    }
+    stats.push_str("**Top channels**\n");
+    for (channel, totals) in report.channels.iter().take(5) {
+        stats.push_str(&line(format!("<#{}>", channel.0), totals));
+    }
+    msg.channel_id.say(&ctx.http, stats).await?;
+    Ok(())
+}
+
+#[command]
+#[description("Opts the channel in or out of the daily digest, or posts it now")]
+#[usage("digest [on|off|now]")]
+#[example("digest off")]
+#[min_args(0)]
+#[max_args(1)]
+#[only_in(guilds)]
+#[required_permissions("ADMINISTRATOR")]
+async fn digest(ctx: &Context, msg: &Message, mut args: Args) -> CommandResult {
+    let (ai, moderator, digest) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<AnimeboysAI>().unwrap().clone(),
+            data.get::<Moderator>().unwrap().clone(),
+            data.get::<DailyDigest>().unwrap().clone(),
+        )
+    };
+    let action = args.single::<String>().unwrap_or_default();
+    let reply = match action.as_str() {
+        "on" | "off" => match digest.set_opted_out(msg.channel_id, action == "off") {
+            true if action == "on" => "This channel will get a daily digest",
+            true => "This channel will no longer get a daily digest",
+            false => "This channel is not one of the digest channels, add it to `AI_DIGEST_CHANNEL_IDS` first",
+        },
+        "now" if !digest.is_subscribed(&msg.channel_id) => {
+            "This channel does not get a daily digest, it can't be posted"
+        }
+        "now" => {
+            let typing = ctx.http.start_typing(msg.channel_id.0)?;
+            let posted = digest.post(&ai, &moderator, &ctx.http, msg.channel_id).await;
+            drop(typing);
+            match posted {
+                true => return Ok(()),
+                false => "There is nothing to digest in this channel",
+            }
+        }
+        _ if digest.is_subscribed(&msg.channel_id) => "This channel gets a daily digest",
+        _ => "This channel does not get a daily digest",
+    };
+    msg.channel_id.say(&ctx.http, reply).await?;
+    Ok(())
+}
+
+#[check]
+#[name = "AiAllowed"]
+async fn is_not_banned(
+    ctx: &Context,
+    msg: &Message,
+    _: &mut Args,
+    _: &CommandOptions,
+) -> Result<(), Reason> {
+    let banned = {
+        let data = ctx.data.read().await;
+        data.get::<Moderator>().unwrap().is_banned(&msg.author.id)
+    };
+    if banned {
+        if let Err(e) = msg
+            .reply(ctx, "You are banned from using the AI features")
+            .await
+        {
+            error!("Error sending message: {:?}", e);
+        }
+        return Err(Reason::User(
+            "You are banned from using the AI features".into(),
+        ));
+    }
+    Ok(())
+}
+
 #[check]
 #[name = "CodeBlock"]
 async fn has_code_block(
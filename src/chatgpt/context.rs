@@ -0,0 +1,209 @@
+use serenity::{
+    model::prelude::{Attachment, Message, MessageId, UserId},
+    prelude::Context,
+};
+use tracing::error;
+
+use super::vision::IMAGE_EXTENSIONS;
+
+/// Files larger than this are not included in the turn
+const MAX_ATTACHMENT_BYTES: u64 = 32 * 1024;
+/// The maximum number of characters quoted from the message being replied to
+const MAX_REPLY_CHARS: usize = 500;
+/// Extensions of files that are treated as text even without a text content type
+const TEXT_EXTENSIONS: [&str; 28] = [
+    "txt", "log", "md", "rs", "py", "js", "ts", "jsx", "tsx", "java", "kt", "c", "h", "cpp", "hpp",
+    "cs", "go", "rb", "php", "sh", "json", "yaml", "yml", "toml", "xml", "html", "css", "sql",
+];
+
+/// A file attached to a message
+pub struct TurnAttachment {
+    pub filename: String,
+    /// The text content of the file, or None if it could not be included
+    pub content: Option<String>,
+    /// Why the content was not included
+    pub note: Option<String>,
+    /// The URL of the attachment if it is an image
+    /// Images are sent to models that can see them, otherwise `content` holds the text read from them
+    pub image_url: Option<String>,
+    /// The size of the file in bytes, as Discord reports it
+    pub size: u64,
+}
+
+/// A single user turn in a conversation, annotated with the Discord context
+/// the AI needs to follow a conversation with multiple participants
+pub struct UserTurn {
+    /// The Discord message the turn was built from
+    pub message_id: Option<MessageId>,
+    pub author_id: UserId,
+    /// The display name of the author
+    pub author: String,
+    pub content: String,
+    /// The author and content of the message being replied to
+    pub reply_to: Option<(String, String)>,
+    pub attachments: Vec<TurnAttachment>,
+}
+
+impl UserTurn {
+    /// Builds a turn from a Discord message, downloading any text attachments
+    pub async fn from_message(ctx: &Context, msg: &Message) -> Self {
+        let author = msg
+            .author_nick(&ctx.http)
+            .await
+            .unwrap_or_else(|| msg.author.name.clone());
+
+        let reply_to = match &msg.referenced_message {
+            Some(referenced) => {
+                let reply_author = referenced
+                    .author_nick(&ctx.http)
+                    .await
+                    .unwrap_or_else(|| referenced.author.name.clone());
+                Some((reply_author, referenced.content.clone()))
+            }
+            None => None,
+        };
+
+        let mut attachments = Vec::new();
+        for attachment in &msg.attachments {
+            attachments.push(read_attachment(attachment).await);
+        }
+
+        Self {
+            message_id: Some(msg.id),
+            author_id: msg.author.id,
+            author,
+            content: msg.content.clone(),
+            reply_to,
+            attachments,
+        }
+    }
+
+    /// Returns the URLs of the images that have not been read and can be sent to the AI
+    pub fn images(&self) -> Vec<String> {
+        self.attachments
+            .iter()
+            .filter(|a| a.content.is_none() && a.note.is_none())
+            .filter_map(|a| a.image_url.clone())
+            .collect()
+    }
+
+    /// Replaces the content of the turn, keeping the rest of the context
+    pub fn with_content(mut self, content: &str) -> Self {
+        self.content = content.to_string();
+        self
+    }
+
+    /// Renders the turn as the message that is sent to the AI
+    pub fn to_prompt(&self) -> String {
+        let mut prompt = format!("[{}]: ", self.author);
+
+        if let Some((reply_author, reply_content)) = &self.reply_to {
+            let quoted = truncate(reply_content, MAX_REPLY_CHARS);
+            prompt.push_str(&format!("(replying to {}: \"{}\")\n", reply_author, quoted));
+        }
+        prompt.push_str(&self.content);
+
+        for attachment in &self.attachments {
+            let kind = match attachment.image_url {
+                Some(_) => "image",
+                None => "file",
+            };
+            match (&attachment.content, &attachment.note) {
+                (Some(content), _) if attachment.image_url.is_some() => prompt.push_str(&format!(
+                    "\n\nAttached image `{}`, the text read from it:\n```\n{}\n```",
+                    attachment.filename, content
+                )),
+                (Some(content), _) => prompt.push_str(&format!(
+                    "\n\nAttached file `{}`:\n```\n{}\n```",
+                    attachment.filename, content
+                )),
+                (None, Some(note)) => prompt.push_str(&format!(
+                    "\n\nAttached {} `{}` ({})",
+                    kind, attachment.filename, note
+                )),
+                (None, None) if attachment.image_url.is_some() => {
+                    prompt.push_str(&format!("\n\nAttached image `{}`", attachment.filename))
+                }
+                (None, None) => {}
+            }
+        }
+
+        prompt
+    }
+}
+
+/// Downloads the attachment if it is a text file within the size limit
+pub async fn read_attachment(attachment: &Attachment) -> TurnAttachment {
+    let mut turn_attachment = TurnAttachment {
+        filename: attachment.filename.clone(),
+        content: None,
+        note: None,
+        image_url: None,
+        size: attachment.size,
+    };
+
+    // Images are handled by the AI, depending on whether the model can see them
+    if is_image(attachment) {
+        turn_attachment.image_url = Some(attachment.url.clone());
+        return turn_attachment;
+    }
+    if !is_text_file(attachment) {
+        turn_attachment.note = Some("not a text file, content not included".to_string());
+        return turn_attachment;
+    }
+    if attachment.size > MAX_ATTACHMENT_BYTES {
+        turn_attachment.note = Some(format!(
+            "{} bytes, larger than the {} byte limit, content not included",
+            attachment.size, MAX_ATTACHMENT_BYTES
+        ));
+        return turn_attachment;
+    }
+
+    match attachment.download().await {
+        Ok(bytes) => {
+            turn_attachment.content = Some(String::from_utf8_lossy(&bytes).into_owned());
+        }
+        Err(e) => {
+            error!(
+                "Error downloading attachment {}: {:?}",
+                attachment.filename, e
+            );
+            turn_attachment.note = Some("could not be downloaded".to_string());
+        }
+    }
+    turn_attachment
+}
+
+fn is_image(attachment: &Attachment) -> bool {
+    if let Some(content_type) = &attachment.content_type {
+        return content_type.starts_with("image/");
+    }
+    attachment
+        .filename
+        .rsplit_once('.')
+        .map(|(_, ext)| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+fn is_text_file(attachment: &Attachment) -> bool {
+    if let Some(content_type) = &attachment.content_type {
+        if content_type.starts_with("text/") || content_type.starts_with("application/json") {
+            return true;
+        }
+    }
+    attachment
+        .filename
+        .rsplit_once('.')
+        .map(|(_, ext)| TEXT_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Truncates a string to at most `max` characters, adding an ellipsis if it was cut
+fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+    let mut truncated = s.chars().take(max).collect::<String>();
+    truncated.push_str("...");
+    truncated
+}
@@ -0,0 +1,257 @@
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serenity::{
+    http::Http,
+    model::prelude::{Channel, ChannelId, Message, MessageId},
+    prelude::TypeMapKey,
+};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::{bot, config::env_var, storage};
+
+use super::{animeboys_ai::AnimeboysAI, moderation::Moderator};
+
+const OPT_OUT_FILE: &str = "ai_digest_opt_out.json";
+/// The most messages a digest is written from
+const MAX_MESSAGES: usize = 1000;
+/// Long messages are cut down to this many characters
+const MAX_MESSAGE_CHARS: usize = 300;
+/// The most characters of messages given to the AI, the oldest messages are dropped past it
+const MAX_TRANSCRIPT_CHARS: usize = 24_000;
+
+/// Posts a digest of the previous day's messages of some channels every morning
+///
+/// Digests are opt in: only the channels in `AI_DIGEST_CHANNEL_IDS` are summarized, and
+/// admins can opt a channel out with `$ai digest off`. NSFW channels are never summarized
+pub struct DailyDigest {
+    channels: Vec<ChannelId>,
+    /// The channel digests are posted to, otherwise each digest is posted in its own channel
+    post_channel: Option<ChannelId>,
+    /// The hour of the day, in UTC, the digests are posted at
+    hour_utc: u64,
+    opted_out: RwLock<BTreeSet<ChannelId>>,
+}
+
+impl TypeMapKey for DailyDigest {
+    type Value = Arc<DailyDigest>;
+}
+
+impl Default for DailyDigest {
+    fn default() -> Self {
+        Self {
+            channels: vec![],
+            post_channel: None,
+            hour_utc: 8,
+            opted_out: RwLock::new(BTreeSet::new()),
+        }
+    }
+}
+
+impl DailyDigest {
+    /// Reads the digest configuration from the environment
+    /// * `AI_DIGEST_CHANNEL_IDS` - comma separated channels that get a digest, none by default
+    /// * `AI_DIGEST_POST_CHANNEL_ID` - the channel all digests are posted to
+    /// * `AI_DIGEST_HOUR_UTC` - the hour the digests are posted at, defaults to 8
+    pub fn from_env() -> Self {
+        let mut digest = Self {
+            channels: env_var("AI_DIGEST_CHANNEL_IDS")
+                .map(|ids| {
+                    ids.split(',')
+                        .filter_map(|id| id.trim().parse::<u64>().ok())
+                        .map(ChannelId)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            post_channel: env_var("AI_DIGEST_POST_CHANNEL_ID")
+                .and_then(|id| id.parse().ok())
+                .map(ChannelId),
+            opted_out: RwLock::new(storage::load_json(OPT_OUT_FILE)),
+            ..Default::default()
+        };
+        if let Some(hour) = env_var("AI_DIGEST_HOUR_UTC").and_then(|v| v.parse::<u64>().ok()) {
+            digest.hour_utc = hour % 24;
+        }
+        digest
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.channels.is_empty()
+    }
+
+    /// Returns true if the channel gets a digest
+    pub fn is_subscribed(&self, channel_id: &ChannelId) -> bool {
+        self.channels.contains(channel_id) && !self.opted_out.read().unwrap().contains(channel_id)
+    }
+
+    /// Opts a channel in or out of the digest
+    /// Returns false if the channel is not one of the configured digest channels
+    pub fn set_opted_out(&self, channel_id: ChannelId, opted_out: bool) -> bool {
+        if !self.channels.contains(&channel_id) {
+            return false;
+        }
+        let mut channels = self.opted_out.write().unwrap();
+        if opted_out {
+            channels.insert(channel_id);
+        } else {
+            channels.remove(&channel_id);
+        }
+        if let Err(e) = storage::save_json(OPT_OUT_FILE, &*channels) {
+            error!("Error saving digest opt outs: {:?}", e);
+        }
+        true
+    }
+
+    /// Starts a task that posts the digests every day at `hour_utc`
+    pub fn spawn_scheduler(
+        self: Arc<Self>,
+        ai: Arc<AnimeboysAI>,
+        moderator: Arc<Moderator>,
+        http: Arc<Http>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let delay = delay_until_hour(SystemTime::now(), self.hour_utc);
+                info!("Posting the next digest in {:?}", delay);
+                tokio::time::sleep(delay).await;
+                for channel_id in &self.channels {
+                    self.post(&ai, &moderator, &http, *channel_id).await;
+                }
+            }
+        })
+    }
+
+    /// Writes and posts the digest of the last day of a channel, moderating it like any AI reply
+    /// Returns false if nothing was posted, or if the channel doesn't get a digest
+    pub async fn post(
+        &self,
+        ai: &AnimeboysAI,
+        moderator: &Moderator,
+        http: &Http,
+        channel_id: ChannelId,
+    ) -> bool {
+        if !self.is_subscribed(&channel_id) {
+            return false;
+        }
+        let channel = match channel_id.to_channel(http).await {
+            Ok(Channel::Guild(channel)) => channel,
+            Ok(_) => return false,
+            Err(e) => {
+                error!("Error getting digest channel {}: {:?}", channel_id, e);
+                return false;
+            }
+        };
+        if channel.is_nsfw() {
+            info!("Skipping the digest of NSFW channel {}", channel_id);
+            return false;
+        }
+
+        let since = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+        let messages = match fetch_since(http, channel_id, since).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                error!("Error fetching messages of {}: {:?}", channel_id, e);
+                return false;
+            }
+        };
+        // Bots, commands and messages without text are left out
+        let messages = messages
+            .into_iter()
+            .filter(|m| {
+                !m.author.bot && !m.content.trim().is_empty() && !m.content.starts_with('$')
+            })
+            .map(|m| (m.author.name, m.content))
+            .collect();
+        let transcript = fit_transcript(messages);
+        if transcript.is_empty() {
+            info!("No messages to digest in {}", channel_id);
+            return false;
+        }
+
+        let digest = ai.digest(&transcript, &channel_id).await;
+        let post_channel = self.post_channel.unwrap_or(channel_id);
+        moderator
+            .log_output_incident(http, post_channel, digest.incident.as_ref())
+            .await;
+        let content = format!("**Daily digest of <#{}>**\n{}", channel_id, digest.content);
+        match bot::send_text_in_chunks(http, post_channel, &content).await {
+            Ok(_) => true,
+            Err(e) => {
+                error!("Error posting the digest of {}: {:?}", channel_id, e);
+                false
+            }
+        }
+    }
+}
+
+/// Fetches the messages of a channel sent after `since`, oldest first
+async fn fetch_since(
+    http: &Http,
+    channel_id: ChannelId,
+    since: SystemTime,
+) -> serenity::Result<Vec<Message>> {
+    let since = since
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let mut messages = vec![];
+    let mut before: Option<MessageId> = None;
+    while messages.len() < MAX_MESSAGES {
+        // Messages are returned newest first
+        let page = channel_id
+            .messages(http, |m| match before {
+                Some(before) => m.before(before).limit(100),
+                None => m.limit(100),
+            })
+            .await?;
+        let done = page.len() < 100;
+        before = page.last().map(|m| m.id);
+        let mut reached_since = false;
+        for message in page {
+            if message.timestamp.unix_timestamp() < since {
+                reached_since = true;
+                break;
+            }
+            messages.push(message);
+        }
+        if done || reached_since {
+            break;
+        }
+    }
+    messages.reverse();
+    Ok(messages)
+}
+
+/// Fits the author and content of the messages, oldest first, into what is given to the AI
+/// Long messages are cut short, and when there are too many the oldest ones are dropped
+pub fn fit_transcript(messages: Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut transcript = vec![];
+    let mut chars = 0;
+    for (author, content) in messages.into_iter().rev() {
+        let content = content.trim();
+        let content = match content.char_indices().nth(MAX_MESSAGE_CHARS) {
+            Some((end, _)) => format!("{}...", &content[..end]),
+            None => content.to_string(),
+        };
+        chars += content.len();
+        if chars > MAX_TRANSCRIPT_CHARS {
+            break;
+        }
+        transcript.push((author, content));
+    }
+    transcript.reverse();
+    transcript
+}
+
+/// Returns how long to wait from `now` until the next time it is `hour` o'clock in UTC
+pub fn delay_until_hour(now: SystemTime, hour: u64) -> Duration {
+    let seconds_of_day = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() % 86400;
+    let target = hour * 60 * 60;
+    let delay = (target + 86400 - seconds_of_day) % 86400;
+    // Right on the hour means the digest was just posted, wait for the next day
+    Duration::from_secs(if delay == 0 { 86400 } else { delay })
+}
@@ -0,0 +1,108 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use super::backend::{ChatMessage, Role};
+
+/// The name used for the AI's messages in a transcript
+const BOT_NAME: &str = "Animeboys Bot";
+
+/// The formats a conversation can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    #[default]
+    Markdown,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "markdown" | "md" => Ok(ExportFormat::Markdown),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!("Unknown format `{}`, use `markdown` or `json`", s)),
+        }
+    }
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+/// A single message of a transcript, labelled with who sent it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TranscriptEntry {
+    pub author: String,
+    pub role: Role,
+    pub content: String,
+}
+
+/// Labels the messages of a conversation, leaving out the system prompt
+/// User messages start with `[Name]: `, which is turned into the author of the entry.
+/// User messages without an author are the bot's own prompts, e.g. the greeting or asking
+/// the AI to continue, and are left out as well
+pub fn transcript(history: &[ChatMessage]) -> Vec<TranscriptEntry> {
+    history
+        .iter()
+        .filter(|m| m.role != Role::System)
+        .filter_map(|m| {
+            let (author, content) = match m.role {
+                Role::User => split_author(&m.content)?,
+                Role::Assistant => (BOT_NAME.to_string(), m.content.clone()),
+                _ => (
+                    m.name.clone().unwrap_or_else(|| BOT_NAME.to_string()),
+                    m.content.clone(),
+                ),
+            };
+            // Function calls have no content, show the call instead
+            let content = match (&m.function_call, content.is_empty()) {
+                (Some(call), true) => format!(
+                    "Called `{}` with:\n```json\n{}\n```",
+                    call.name, call.arguments
+                ),
+                _ => content,
+            };
+            Some(TranscriptEntry {
+                author,
+                role: m.role,
+                content,
+            })
+        })
+        .collect()
+}
+
+/// Returns the author and the message of a user turn, or None if it has no author
+fn split_author(content: &str) -> Option<(String, String)> {
+    let (author, message) = content.strip_prefix('[')?.split_once("]: ")?;
+    if author.contains('\n') {
+        return None;
+    }
+    Some((author.to_string(), message.to_string()))
+}
+
+/// Renders the conversation in the given format
+/// Message content is kept as is, so code blocks are preserved
+pub fn render(title: &str, history: &[ChatMessage], format: ExportFormat) -> String {
+    let entries = transcript(history);
+    match format {
+        ExportFormat::Markdown => {
+            let mut markdown = format!("# {}\n", title);
+            for entry in entries {
+                markdown.push_str(&format!("\n### {}\n\n{}\n", entry.author, entry.content));
+            }
+            markdown
+        }
+        ExportFormat::Json => serde_json::to_string_pretty(&serde_json::json!({
+            "title": title,
+            "messages": entries,
+        }))
+        .expect("Transcript is always valid JSON"),
+    }
+}
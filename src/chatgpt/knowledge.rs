@@ -0,0 +1,355 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+use serenity::{
+    http::Http,
+    model::prelude::{Channel, ChannelId},
+};
+use tracing::{error, info};
+
+use crate::config::env_var;
+
+/// Chunks are split on paragraphs once they grow past this many characters
+const MAX_CHUNK_CHARS: usize = 800;
+/// BM25 parameters
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+/// Words too common to say anything about a chunk
+const STOP_WORDS: [&str; 32] = [
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "can", "do", "for", "how", "i", "if",
+    "in", "is", "it", "me", "my", "of", "on", "or", "so", "the", "to", "we", "what", "when",
+    "with", "you", "your",
+];
+
+/// A piece of the knowledge base that can be given to the AI
+#[derive(Debug, Clone, PartialEq)]
+pub struct KnowledgeChunk {
+    /// Where the chunk came from, e.g. `rules.md > Minecraft` or `#faq pins`
+    pub source: String,
+    pub text: String,
+}
+
+/// A BM25 index over the chunks
+#[derive(Default)]
+struct Index {
+    chunks: Vec<KnowledgeChunk>,
+    /// The term frequencies of every chunk
+    terms: Vec<HashMap<String, usize>>,
+    /// The number of chunks every term appears in
+    document_frequency: HashMap<String, usize>,
+    average_length: f64,
+}
+
+impl Index {
+    fn new(chunks: Vec<KnowledgeChunk>) -> Self {
+        let mut terms = Vec::with_capacity(chunks.len());
+        let mut document_frequency = HashMap::new();
+        let mut total_length = 0;
+        for chunk in &chunks {
+            let mut frequencies = HashMap::new();
+            for token in tokenize(&format!("{} {}", chunk.source, chunk.text)) {
+                *frequencies.entry(token).or_insert(0) += 1;
+                total_length += 1;
+            }
+            for term in frequencies.keys() {
+                *document_frequency.entry(term.clone()).or_insert(0) += 1;
+            }
+            terms.push(frequencies);
+        }
+        let average_length = if chunks.is_empty() {
+            0.0
+        } else {
+            total_length as f64 / chunks.len() as f64
+        };
+        Self {
+            chunks,
+            terms,
+            document_frequency,
+            average_length,
+        }
+    }
+
+    fn search(&self, query: &str, limit: usize) -> Vec<KnowledgeChunk> {
+        let query = tokenize(query);
+        let count = self.chunks.len() as f64;
+        let mut scores = self
+            .terms
+            .iter()
+            .enumerate()
+            .map(|(i, frequencies)| {
+                let length = frequencies.values().sum::<usize>() as f64;
+                let score = query
+                    .iter()
+                    .filter_map(|term| {
+                        let frequency = *frequencies.get(term)? as f64;
+                        let df = self.document_frequency[term] as f64;
+                        let idf = ((count - df + 0.5) / (df + 0.5) + 1.0).ln();
+                        Some(
+                            idf * frequency * (K1 + 1.0)
+                                / (frequency + K1 * (1.0 - B + B * length / self.average_length)),
+                        )
+                    })
+                    .sum::<f64>();
+                (i, score)
+            })
+            .filter(|(_, score)| *score > 0.0)
+            .collect::<Vec<_>>();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scores
+            .into_iter()
+            .take(limit)
+            .map(|(i, _)| self.chunks[i].clone())
+            .collect()
+    }
+}
+
+/// Returns the Markdown text with its code blocks and inline code removed
+fn outside_code(markdown: &str) -> String {
+    markdown
+        .split("```")
+        .step_by(2)
+        .flat_map(|text| text.split('`').step_by(2))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .filter(|word| !STOP_WORDS.contains(&word.as_str()))
+        .collect()
+}
+
+/// Splits a Markdown document into chunks, one per section
+/// Sections are named after the file and their heading, e.g. `rules.md > Minecraft`
+/// Sections longer than `MAX_CHUNK_CHARS` are split on paragraphs
+pub fn chunk_markdown(name: &str, markdown: &str) -> Vec<KnowledgeChunk> {
+    let mut sections: Vec<(String, String)> = vec![(name.to_string(), String::new())];
+    let mut in_code_block = false;
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+        }
+        // Comments in code blocks are not headings
+        let heading = match in_code_block {
+            true => None,
+            false => line.strip_prefix('#'),
+        };
+        if let Some(heading) = heading {
+            let heading = heading.trim_start_matches('#').trim();
+            sections.push((format!("{} > {}", name, heading), String::new()));
+        } else {
+            let (_, text) = sections.last_mut().unwrap();
+            text.push_str(line);
+            text.push('\n');
+        }
+    }
+
+    let mut chunks = vec![];
+    for (source, text) in sections {
+        for text in split_paragraphs(&text) {
+            chunks.push(KnowledgeChunk {
+                source: source.clone(),
+                text,
+            });
+        }
+    }
+    chunks
+}
+
+/// Groups paragraphs into pieces of at most `MAX_CHUNK_CHARS`, a longer paragraph is kept whole
+fn split_paragraphs(text: &str) -> Vec<String> {
+    let mut pieces = vec![];
+    let mut current = String::new();
+    for paragraph in text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+        if !current.is_empty() && current.len() + paragraph.len() > MAX_CHUNK_CHARS {
+            pieces.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push_str("\n\n");
+        }
+        current.push_str(paragraph);
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// The server's own knowledge, made of Markdown files and the pinned messages of some channels
+/// The chunks that best match a user's message are given to the AI with the message
+pub struct KnowledgeBase {
+    /// The directory the Markdown files are read from
+    dir: Option<PathBuf>,
+    /// The channels whose pinned messages are included
+    channels: Vec<ChannelId>,
+    /// How many chunks are given to the AI
+    top_k: usize,
+    index: RwLock<Index>,
+}
+
+impl Default for KnowledgeBase {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            channels: vec![],
+            top_k: 3,
+            index: RwLock::new(Index::default()),
+        }
+    }
+}
+
+impl KnowledgeBase {
+    /// Reads the knowledge base configuration from the environment and loads the Markdown files
+    /// Pinned messages are only loaded by `reload`, once the bot can reach Discord
+    /// * `AI_KNOWLEDGE_DIR` - directory with the Markdown files
+    /// * `AI_KNOWLEDGE_CHANNEL_IDS` - comma separated channels whose pinned messages are included
+    /// * `AI_KNOWLEDGE_TOP_K` - how many chunks are given to the AI, defaults to 3
+    pub fn from_env() -> Self {
+        let mut knowledge = Self {
+            dir: env_var("AI_KNOWLEDGE_DIR").map(PathBuf::from),
+            channels: env_var("AI_KNOWLEDGE_CHANNEL_IDS")
+                .map(|ids| {
+                    ids.split(',')
+                        .filter_map(|id| id.trim().parse::<u64>().ok())
+                        .map(ChannelId)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        if let Some(top_k) = env_var("AI_KNOWLEDGE_TOP_K").and_then(|v| v.parse().ok()) {
+            knowledge.top_k = top_k;
+        }
+        knowledge.set_chunks(knowledge.load_files());
+        knowledge
+    }
+
+    /// Creates a knowledge base from chunks that are already loaded
+    pub fn from_chunks(chunks: Vec<KnowledgeChunk>) -> Self {
+        let knowledge = Self::default();
+        knowledge.set_chunks(chunks);
+        knowledge
+    }
+
+    fn set_chunks(&self, chunks: Vec<KnowledgeChunk>) {
+        *self.index.write().unwrap() = Index::new(chunks);
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.read().unwrap().chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Renders the chunks as the context given to the AI, numbered so they can be cited
+    pub fn to_prompt(chunks: &[KnowledgeChunk]) -> String {
+        let mut prompt = String::from(
+            "Information from the Animeboys server's knowledge base that may help with the next message. \
+            Only use it if it is relevant. When you use it, cite it by its number, e.g. [1].\n",
+        );
+        for (i, chunk) in chunks.iter().enumerate() {
+            prompt.push_str(&format!(
+                "\n[{}] ({})\n{}\n",
+                i + 1,
+                chunk.source,
+                chunk.text
+            ));
+        }
+        prompt
+    }
+
+    /// Lists the sources of the chunks cited in a reply, or None if none were cited
+    /// Markers in code, e.g. `items[1]`, are not citations
+    pub fn citations(chunks: &[KnowledgeChunk], reply: &str) -> Option<String> {
+        let prose = outside_code(reply);
+        let cited = chunks
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| prose.contains(&format!("[{}]", i + 1)))
+            .map(|(i, chunk)| format!("[{}] {}", i + 1, chunk.source))
+            .collect::<Vec<_>>();
+        if cited.is_empty() {
+            return None;
+        }
+        Some(format!("Sources: {}", cited.join(", ")))
+    }
+
+    /// Returns the chunks that best match the query, best first
+    pub fn search(&self, query: &str) -> Vec<KnowledgeChunk> {
+        self.index.read().unwrap().search(query, self.top_k)
+    }
+
+    /// Reloads the Markdown files and the pinned messages
+    /// Returns the number of chunks in the knowledge base
+    pub async fn reload(&self, http: &Http) -> usize {
+        let mut chunks = self.load_files();
+        for channel in &self.channels {
+            chunks.extend(load_pins(http, *channel).await);
+        }
+        let count = chunks.len();
+        self.set_chunks(chunks);
+        info!("Loaded {} knowledge base chunks", count);
+        count
+    }
+
+    fn load_files(&self) -> Vec<KnowledgeChunk> {
+        match &self.dir {
+            Some(dir) => load_markdown_dir(dir),
+            None => vec![],
+        }
+    }
+}
+
+fn load_markdown_dir(dir: &Path) -> Vec<KnowledgeChunk> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Error reading knowledge base dir {:?}: {:?}", dir, e);
+            return vec![];
+        }
+    };
+    let mut paths = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map(|ext| ext == "md").unwrap_or(false))
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut chunks = vec![];
+    for path in paths {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        match std::fs::read_to_string(&path) {
+            Ok(markdown) => chunks.extend(chunk_markdown(&name, &markdown)),
+            Err(e) => error!("Error reading {:?}: {:?}", path, e),
+        }
+    }
+    chunks
+}
+
+async fn load_pins(http: &Http, channel: ChannelId) -> Vec<KnowledgeChunk> {
+    let name = match channel.to_channel(http).await {
+        Ok(Channel::Guild(guild_channel)) => format!("#{} pins", guild_channel.name),
+        _ => format!("<#{}> pins", channel.0),
+    };
+    match channel.pins(http).await {
+        Ok(pins) => pins
+            .into_iter()
+            .filter(|pin| !pin.content.trim().is_empty())
+            .flat_map(|pin| split_paragraphs(&pin.content))
+            .map(|text| KnowledgeChunk {
+                source: name.clone(),
+                text,
+            })
+            .collect(),
+        Err(e) => {
+            error!("Error loading the pins of {}: {:?}", channel, e);
+            vec![]
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use serenity::{
+    model::prelude::{ChannelId, GuildChannel},
+    prelude::TypeMapKey,
+};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::{config::env_var, storage};
+
+use super::{
+    animeboys_ai::{AnimeboysAI, Conversation},
+    backend::ChatMessage,
+    persona::Persona,
+};
+
+/// A conversation saved to the data directory so it can be resumed later
+#[derive(Serialize, Deserialize, Default)]
+struct PersistedConversation {
+    persona: Option<Persona>,
+    history: Vec<ChatMessage>,
+}
+
+const CONVERSATIONS_DIR: &str = "conversations";
+
+fn conversation_file(channel_id: &ChannelId) -> String {
+    format!("{}/{}.json", CONVERSATIONS_DIR, channel_id.0)
+}
+
+/// Returns the channel a saved conversation file is for
+fn channel_of_file(name: &str) -> Option<ChannelId> {
+    name.strip_suffix(".json")?.parse().ok().map(ChannelId)
+}
+
+/// Decides when conversations are dropped from memory and when they can be resumed
+///
+/// Idle conversations are expired after `idle_ttl`. Archived threads are dropped as well.
+/// With persistence enabled, the history of an expired or archived conversation is saved
+/// and the conversation is resumed when the thread is unarchived or someone talks in it again.
+/// Saved conversations that are not resumed within `retention` are deleted
+#[derive(Debug, Clone)]
+pub struct ConversationLifecycle {
+    /// How long a conversation can go without a message before it is expired
+    idle_ttl: Duration,
+    /// How often idle conversations are looked for
+    sweep_interval: Duration,
+    /// Save conversations when they are expired or archived
+    persist: bool,
+    /// How long a saved conversation is kept
+    retention: Duration,
+    /// The channels with a saved conversation, so messages in other channels never touch the disk
+    suspended: Arc<Mutex<BTreeSet<ChannelId>>>,
+    /// The channels whose conversation was dropped without being saved,
+    /// so the next message in them is told the conversation expired
+    expired: Arc<Mutex<BTreeSet<ChannelId>>>,
+}
+
+impl TypeMapKey for ConversationLifecycle {
+    type Value = ConversationLifecycle;
+}
+
+impl Default for ConversationLifecycle {
+    fn default() -> Self {
+        Self {
+            idle_ttl: Duration::from_secs(2 * 60 * 60),
+            sweep_interval: Duration::from_secs(5 * 60),
+            persist: false,
+            retention: Duration::from_secs(30 * 24 * 60 * 60),
+            suspended: Arc::new(Mutex::new(BTreeSet::new())),
+            expired: Arc::new(Mutex::new(BTreeSet::new())),
+        }
+    }
+}
+
+impl ConversationLifecycle {
+    /// Reads the lifecycle configuration from the environment
+    /// * `AI_CONVERSATION_TTL_MINS` - minutes without a message before a conversation expires
+    /// * `AI_PERSIST_CONVERSATIONS` - `true` to save expired and archived conversations
+    /// * `AI_CONVERSATION_RETENTION_DAYS` - days a saved conversation is kept, defaults to 30
+    pub fn from_env() -> Self {
+        let mut lifecycle = Self::default();
+
+        if let Some(ttl) = env_var("AI_CONVERSATION_TTL_MINS").and_then(|v| v.parse::<u64>().ok()) {
+            lifecycle.idle_ttl = Duration::from_secs(ttl * 60);
+            // Look for idle conversations often enough that they do not outlive the ttl by much
+            lifecycle.sweep_interval = lifecycle
+                .sweep_interval
+                .min(lifecycle.idle_ttl / 4)
+                .max(Duration::from_secs(1));
+        }
+        if let Some(days) =
+            env_var("AI_CONVERSATION_RETENTION_DAYS").and_then(|v| v.parse::<u64>().ok())
+        {
+            lifecycle.retention = Duration::from_secs(days.max(1) * 24 * 60 * 60);
+        }
+        let persist = env_var("AI_PERSIST_CONVERSATIONS")
+            .map(|v| v == "true")
+            .unwrap_or(false);
+        lifecycle.with_persistence(persist)
+    }
+
+    /// Overrides the idle ttl and how often it is checked
+    pub fn with_idle_ttl(mut self, idle_ttl: Duration, sweep_interval: Duration) -> Self {
+        self.idle_ttl = idle_ttl;
+        self.sweep_interval = sweep_interval;
+        self
+    }
+
+    /// Enables or disables saving conversations
+    /// When enabled, the conversations already saved to the data directory can be resumed
+    pub fn with_persistence(mut self, persist: bool) -> Self {
+        self.persist = persist;
+        let saved = match persist {
+            true => saved_channels(),
+            false => BTreeSet::new(),
+        };
+        *self.suspended.lock().unwrap() = saved;
+        self
+    }
+
+    /// Overrides how long saved conversations are kept
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Starts a task that expires idle conversations
+    pub fn spawn_sweeper(&self, ai: Arc<AnimeboysAI>) -> JoinHandle<()> {
+        let lifecycle = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(lifecycle.sweep_interval);
+            loop {
+                interval.tick().await;
+                let expired = lifecycle.expire_idle(&ai).await;
+                if expired > 0 {
+                    info!("Expired {} idle conversations", expired);
+                }
+                let pruned = lifecycle.prune_saved().await;
+                if pruned > 0 {
+                    info!("Deleted {} stale saved conversations", pruned);
+                }
+            }
+        })
+    }
+
+    /// Expires every idle conversation
+    /// Returns the number of conversations that were expired
+    pub async fn expire_idle(&self, ai: &AnimeboysAI) -> usize {
+        let idle = ai.idle_conversations(self.idle_ttl);
+        for channel_id in &idle {
+            self.suspend(ai, channel_id).await;
+        }
+        idle.len()
+    }
+
+    /// Handles a thread being updated
+    /// Locked threads end the conversation, archived threads are suspended
+    /// and unarchived threads are resumed
+    pub async fn thread_updated(&self, ai: &AnimeboysAI, thread: &GuildChannel) {
+        // Updates to threads always have metadata, but other channels do not
+        let metadata = match &thread.thread_metadata {
+            Some(metadata) => metadata,
+            None => return,
+        };
+
+        if metadata.locked {
+            info!("Thread {} was locked, ending the conversation", thread.id);
+            self.end(ai, &thread.id).await;
+        } else if metadata.archived {
+            info!("Thread {} was archived", thread.id);
+            self.suspend(ai, &thread.id).await;
+        } else if self.resume(ai, &thread.id).await {
+            info!(
+                "Thread {} was unarchived, resumed the conversation",
+                thread.id
+            );
+        }
+    }
+
+    /// Drops a conversation from memory, saving it first if persistence is enabled
+    /// A conversation that can't be saved is kept in memory, so it is tried again on the next sweep
+    pub async fn suspend(&self, ai: &AnimeboysAI, channel_id: &ChannelId) {
+        let conversation = match ai.get_conversation(channel_id) {
+            Some(conversation) => conversation,
+            None => return,
+        };
+
+        // Hold the conversation until it is dropped, so no turn is added that the saved copy misses.
+        // Handlers already waiting for it see it was suspended and put it back
+        let mut conversation = conversation.lock().await;
+        if self.persist {
+            let persisted = PersistedConversation {
+                persona: Some(conversation.persona.clone()),
+                history: conversation.history.clone(),
+            };
+            if let Err(e) =
+                storage::save_json_async(&conversation_file(channel_id), persisted).await
+            {
+                error!(
+                    "Error saving conversation {}, keeping it in memory: {:?}",
+                    channel_id, e
+                );
+                return;
+            }
+            // Marked as saved before it leaves memory, so a message in between resumes it
+            self.suspended.lock().unwrap().insert(*channel_id);
+        } else {
+            self.expired.lock().unwrap().insert(*channel_id);
+        }
+        conversation.suspended = true;
+        ai.remove_conversation(channel_id).await;
+    }
+
+    /// Returns true the first time it is called for a channel whose conversation expired
+    /// without being saved, so the user can be told once instead of the bot going quiet
+    pub fn take_expired(&self, channel_id: &ChannelId) -> bool {
+        self.expired.lock().unwrap().remove(channel_id)
+    }
+
+    /// Loads a saved conversation back into memory
+    /// Returns true if the conversation exists, whether or not it had to be resumed
+    pub async fn resume(&self, ai: &AnimeboysAI, channel_id: &ChannelId) -> bool {
+        if ai.does_conversation_exist(channel_id) {
+            return true;
+        }
+        if !self.persist || !self.suspended.lock().unwrap().contains(channel_id) {
+            return false;
+        }
+
+        let persisted: PersistedConversation =
+            storage::load_json_async(&conversation_file(channel_id)).await;
+        let persona = match persisted.persona {
+            Some(persona) if !persisted.history.is_empty() => persona,
+            _ => return false,
+        };
+        info!("Resuming conversation {}", channel_id);
+        ai.insert_conversation(
+            *channel_id,
+            Conversation::with_history(persona, persisted.history),
+        );
+        true
+    }
+
+    /// Ends a conversation for good, removing it from memory and deleting any saved history
+    pub async fn end(&self, ai: &AnimeboysAI, channel_id: &ChannelId) {
+        ai.remove_conversation(channel_id).await;
+        self.suspended.lock().unwrap().remove(channel_id);
+        self.expired.lock().unwrap().remove(channel_id);
+
+        let path = storage::data_path(&conversation_file(channel_id));
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => error!("Error deleting conversation {:?}: {:?}", path, e),
+        }
+    }
+
+    /// Deletes the saved conversations that were not resumed within the retention
+    /// Returns the number of conversations that were deleted
+    pub async fn prune_saved(&self) -> usize {
+        if !self.persist {
+            return 0;
+        }
+        let mut entries = match tokio::fs::read_dir(storage::data_path(CONVERSATIONS_DIR)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return 0,
+            Err(e) => {
+                error!("Error reading saved conversations: {:?}", e);
+                return 0;
+            }
+        };
+        let mut pruned = 0;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let channel_id = match channel_of_file(&entry.file_name().to_string_lossy()) {
+                Some(channel_id) => channel_id,
+                None => continue,
+            };
+            let age = entry
+                .metadata()
+                .await
+                .and_then(|m| m.modified())
+                .map(|modified| {
+                    SystemTime::now()
+                        .duration_since(modified)
+                        .unwrap_or_default()
+                });
+            match age {
+                Ok(age) if age > self.retention => {}
+                _ => continue,
+            }
+            match tokio::fs::remove_file(entry.path()).await {
+                Ok(()) => {
+                    self.suspended.lock().unwrap().remove(&channel_id);
+                    pruned += 1;
+                }
+                Err(e) => error!("Error deleting conversation {:?}: {:?}", entry.path(), e),
+            }
+        }
+        pruned
+    }
+}
+
+/// Returns the channels that have a conversation saved in the data directory
+fn saved_channels() -> BTreeSet<ChannelId> {
+    match std::fs::read_dir(storage::data_path(CONVERSATIONS_DIR)) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| channel_of_file(&entry.file_name().to_string_lossy()))
+            .collect(),
+        Err(_) => BTreeSet::new(),
+    }
+}
@@ -1,2 +1,15 @@
+pub mod actions;
 pub mod animeboys_ai;
+pub mod backend;
 pub mod command;
+pub mod context;
+pub mod digest;
+pub mod export;
+pub mod knowledge;
+pub mod lifecycle;
+pub mod moderation;
+pub mod persona;
+pub mod retry;
+pub mod sandbox;
+pub mod usage;
+pub mod vision;
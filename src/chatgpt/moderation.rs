@@ -0,0 +1,366 @@
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, RwLock},
+};
+
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use serenity::{
+    http::Http,
+    model::prelude::{ChannelId, UserId},
+    prelude::TypeMapKey,
+};
+use tracing::{error, info};
+
+use crate::{config::env_var, storage};
+
+use super::context::UserTurn;
+
+const BANS_FILE: &str = "ai_bans.json";
+const REDACTED: &str = "[redacted]";
+/// Sent instead of a response from the AI that was blocked
+const BLOCKED_OUTPUT: &str = "The AI's response was blocked by moderation.";
+
+/// What happens to content that is flagged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationAction {
+    /// The whole message is dropped
+    Block,
+    /// Matches from the local list are replaced, anything flagged by the endpoint is still blocked
+    Redact,
+}
+
+/// The outcome of checking a piece of text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModerationResult {
+    pub flagged: bool,
+    /// Why the text was flagged, e.g. the endpoint categories or the matched patterns
+    pub reasons: Vec<String>,
+    /// The text with any local matches redacted
+    pub redacted: String,
+    /// True if the text cannot be redacted and must be blocked
+    pub must_block: bool,
+}
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResponseResult>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResponseResult {
+    flagged: bool,
+    #[serde(default)]
+    categories: std::collections::BTreeMap<String, bool>,
+}
+
+/// Checks the input sent to and the output received from the AI, and keeps
+/// track of the users that are banned from the AI features
+pub struct Moderator {
+    http: reqwest::Client,
+    /// An OpenAI compatible `/moderations` endpoint
+    endpoint: Option<String>,
+    api_key: Option<String>,
+    /// Words and regexes from the local list
+    patterns: Vec<Regex>,
+    action: ModerationAction,
+    /// The channel incidents are logged to
+    mod_channel: Option<ChannelId>,
+    bans: RwLock<BTreeSet<UserId>>,
+    /// Held while the bans are written, so an older copy never overwrites a newer one
+    saving_bans: tokio::sync::Mutex<()>,
+}
+
+impl TypeMapKey for Moderator {
+    type Value = Arc<Moderator>;
+}
+
+impl Default for Moderator {
+    fn default() -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            endpoint: None,
+            api_key: None,
+            patterns: vec![],
+            action: ModerationAction::Redact,
+            mod_channel: None,
+            bans: RwLock::new(BTreeSet::new()),
+            saving_bans: tokio::sync::Mutex::new(()),
+        }
+    }
+}
+
+impl Moderator {
+    /// Reads the moderation configuration from the environment and loads the persisted bans
+    /// * `AI_MODERATION_URL` - moderation endpoint, e.g. `https://api.openai.com/v1/moderations`
+    /// * `AI_MODERATION_WORDS_PATH` - file with one word per line, lines starting with `re:` are regexes
+    /// * `AI_MODERATION_ACTION` - `block` or `redact` (default)
+    /// * `AI_MOD_CHANNEL_ID` - channel incidents are logged to
+    pub fn from_env() -> Self {
+        let mut moderator = Self {
+            endpoint: env_var("AI_MODERATION_URL"),
+            api_key: env_var("AI_API_KEY").or_else(|| env_var("OPENAI_API_KEY")),
+            mod_channel: env_var("AI_MOD_CHANNEL_ID")
+                .and_then(|id| id.parse::<u64>().ok())
+                .map(ChannelId),
+            bans: RwLock::new(storage::load_json(BANS_FILE)),
+            ..Default::default()
+        };
+        if env_var("AI_MODERATION_ACTION").as_deref() == Some("block") {
+            moderator.action = ModerationAction::Block;
+        }
+        if let Some(path) = env_var("AI_MODERATION_WORDS_PATH") {
+            match std::fs::read_to_string(&path) {
+                Ok(list) => moderator.patterns = parse_word_list(&list),
+                Err(e) => error!("Failed to load moderation list from {}: {:?}", path, e),
+            }
+        }
+        info!(
+            "Moderation: {} local patterns, endpoint {}",
+            moderator.patterns.len(),
+            moderator.endpoint.as_deref().unwrap_or("disabled")
+        );
+        moderator
+    }
+
+    /// Sets the local list, see [`parse_word_list`]
+    pub fn with_word_list(mut self, list: &str) -> Self {
+        self.patterns = parse_word_list(list);
+        self
+    }
+
+    pub fn with_action(mut self, action: ModerationAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Checks the text against the local list and the moderation endpoint
+    pub async fn check(&self, text: &str) -> ModerationResult {
+        let mut result = ModerationResult {
+            flagged: false,
+            reasons: vec![],
+            redacted: text.to_string(),
+            must_block: self.action == ModerationAction::Block,
+        };
+
+        for pattern in &self.patterns {
+            if pattern.is_match(&result.redacted) {
+                result.flagged = true;
+                result
+                    .reasons
+                    .push(format!("matched `{}`", pattern.as_str()));
+                result.redacted = pattern.replace_all(&result.redacted, REDACTED).into_owned();
+            }
+        }
+
+        if let Some(categories) = self.check_endpoint(text).await {
+            result.flagged = true;
+            result.must_block = true;
+            result.reasons.extend(categories);
+        }
+
+        result
+    }
+
+    /// Returns the flagged categories if the endpoint flagged the text
+    async fn check_endpoint(&self, text: &str) -> Option<Vec<String>> {
+        let endpoint = self.endpoint.as_ref()?;
+        let mut request = self
+            .http
+            .post(endpoint)
+            .json(&serde_json::json!({ "input": text }));
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+
+        // Moderation should never stop the bot from working, errors are only logged
+        let response = match request.send().await {
+            Ok(response) => response.json::<ModerationResponse>().await,
+            Err(e) => Err(e),
+        };
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Error calling moderation endpoint: {:?}", e);
+                return None;
+            }
+        };
+
+        let result = response.results.into_iter().find(|r| r.flagged)?;
+        Some(
+            result
+                .categories
+                .into_iter()
+                .filter(|(_, flagged)| *flagged)
+                .map(|(category, _)| category)
+                .collect(),
+        )
+    }
+
+    /// Checks a user's turn before it is sent to the AI, redacting it in place
+    /// The message being replied to is checked as well, it is quoted to the AI
+    /// Returns false if the turn must be blocked
+    pub async fn moderate_turn(
+        &self,
+        http: &Http,
+        channel_id: ChannelId,
+        turn: &mut UserTurn,
+    ) -> bool {
+        let author_id = turn.author_id;
+        let mut allowed = true;
+        let mut texts = vec![&mut turn.content];
+        texts.extend(turn.reply_to.as_mut().map(|(_, content)| content));
+        texts.extend(
+            turn.attachments
+                .iter_mut()
+                .filter_map(|a| a.content.as_mut()),
+        );
+
+        for text in texts {
+            let result = self.check(text).await;
+            if !result.flagged {
+                continue;
+            }
+            self.log_incident(
+                http,
+                channel_id,
+                &format!("Input from <@{}>", author_id.0),
+                &result,
+            )
+            .await;
+            if result.must_block {
+                allowed = false;
+            } else {
+                *text = result.redacted;
+            }
+        }
+        allowed
+    }
+
+    /// Checks the AI's response once, before it is kept in the history or sent to the channel
+    /// Returns the text as it may be shown, and the result if it was flagged,
+    /// so the incident can be logged with [`Moderator::log_output_incident`] once the channel is known
+    pub async fn moderate_output(&self, text: String) -> (String, Option<ModerationResult>) {
+        let result = self.check(&text).await;
+        if !result.flagged {
+            return (text, None);
+        }
+        (Self::moderated_output(&result), Some(result))
+    }
+
+    /// Logs the incident of an AI response changed by [`Moderator::moderate_output`], if any
+    pub async fn log_output_incident(
+        &self,
+        http: &Http,
+        channel_id: ChannelId,
+        incident: Option<&ModerationResult>,
+    ) {
+        if let Some(incident) = incident {
+            self.log_incident(http, channel_id, "AI output", incident)
+                .await;
+        }
+    }
+
+    fn moderated_output(result: &ModerationResult) -> String {
+        if result.must_block {
+            BLOCKED_OUTPUT.to_string()
+        } else {
+            result.redacted.clone()
+        }
+    }
+
+    /// Logs a moderation incident to the mod channel
+    async fn log_incident(
+        &self,
+        http: &Http,
+        channel_id: ChannelId,
+        source: &str,
+        result: &ModerationResult,
+    ) {
+        info!(
+            "Moderation incident in {}: {} ({})",
+            channel_id,
+            source,
+            result.reasons.join(", ")
+        );
+        let mod_channel = match self.mod_channel {
+            Some(mod_channel) => mod_channel,
+            None => return,
+        };
+        let action = if result.must_block {
+            "blocked"
+        } else {
+            "redacted"
+        };
+        if let Err(e) = mod_channel
+            .say(
+                http,
+                format!(
+                    "**AI moderation**: {} in <#{}> was {}. Reasons: {}",
+                    source,
+                    channel_id.0,
+                    action,
+                    result.reasons.join(", ")
+                ),
+            )
+            .await
+        {
+            error!("Error logging moderation incident: {:?}", e);
+        }
+    }
+
+    pub fn is_banned(&self, user_id: &UserId) -> bool {
+        self.bans.read().unwrap().contains(user_id)
+    }
+
+    /// Bans a user from the AI features
+    /// Returns false if the user was already banned
+    pub async fn ban(&self, user_id: UserId) -> bool {
+        let added = self.bans.write().unwrap().insert(user_id);
+        if added {
+            self.save_bans().await;
+        }
+        added
+    }
+
+    /// Lifts a ban
+    /// Returns false if the user was not banned
+    pub async fn unban(&self, user_id: &UserId) -> bool {
+        let removed = self.bans.write().unwrap().remove(user_id);
+        if removed {
+            self.save_bans().await;
+        }
+        removed
+    }
+
+    /// Writes the bans to the data directory, without holding the bans lock while writing
+    async fn save_bans(&self) {
+        let _saving = self.saving_bans.lock().await;
+        // Copied once it is this save's turn, so the last write has the latest bans
+        let bans = self.bans.read().unwrap().clone();
+        if let Err(e) = storage::save_json_async(BANS_FILE, bans).await {
+            error!("Error saving AI bans: {:?}", e);
+        }
+    }
+}
+
+/// Parses a word list, one entry per line
+/// Words match case insensitively on word boundaries, lines starting with `re:` are regexes
+/// Empty lines and lines starting with `#` are ignored
+pub fn parse_word_list(list: &str) -> Vec<Regex> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let pattern = match line.strip_prefix("re:") {
+                Some(regex) => regex.trim().to_string(),
+                None => format!(r"\b{}\b", regex::escape(line)),
+            };
+            RegexBuilder::new(&pattern)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| error!("Invalid moderation pattern {}: {:?}", line, e))
+                .ok()
+        })
+        .collect()
+}
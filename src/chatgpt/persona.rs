@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::config::env_var;
+
+use super::backend::{ChatMessage, ChatRequest};
+
+/// The persona used by `$ai chat` when no `--persona` is given
+pub const DEFAULT_CHAT_PERSONA: &str = "default";
+/// The persona used by `$ai debug` when no `--persona` is given
+pub const DEFAULT_DEBUG_PERSONA: &str = "debug";
+
+const DEBUG_DIRECTED_PROMPT: &str = "
+You are the Animeboys Bot. Your main purpose it to help members of the Animeboys Discord server debug their code.
+The conversation will start with a user requesting help with their code. You will then respond with a message that will help the user debug their code.
+After this, you will be placed in a thread with the user where you can continue to help them with their code.
+When you respond, always end your message with 'Thank you for using the Animeboys Bot! Is there anything else I can assist with?'.
+If the user responds with 'Yes', then you will continue to help them with their code. If the user responds with 'No', then you will end the conversation.
+Several members may talk in the same thread. Each of their messages starts with their name in brackets, e.g. `[Name]: `.
+";
+
+const QUESTION_DIRECTED_PROMPT: &str = "
+You are the Animeboys Bot. Your main purpose it to help members of the Animeboys Discord server.
+In this conversation you will help members by answering their questions. After every couple of messages, please
+remind the user that they can end the conversation by sending `$ai stop`.
+Several members may talk in the same thread. Each of their messages starts with their name in brackets, e.g. `[Name]: `.
+";
+
+/// A named combination of system prompt and model settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Persona {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub system_prompt: String,
+    #[serde(default = "default_model")]
+    pub model: String,
+    #[serde(default = "default_temperature")]
+    pub temperature: f32,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    /// Whether the model can see images, otherwise the text in images is read with OCR
+    #[serde(default)]
+    pub vision: bool,
+}
+
+fn default_model() -> String {
+    "gpt-3.5-turbo".to_string()
+}
+
+fn default_temperature() -> f32 {
+    0.5
+}
+
+impl Persona {
+    /// Builds the completion request for the given messages with this persona's model settings
+    pub fn request(&self, messages: Vec<ChatMessage>) -> ChatRequest {
+        ChatRequest {
+            model: self.model.clone(),
+            messages,
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+        }
+    }
+}
+
+/// All of the personas the AI can take on, keyed by name
+pub struct PersonaRegistry {
+    personas: BTreeMap<String, Persona>,
+}
+
+impl Default for PersonaRegistry {
+    fn default() -> Self {
+        Self::from_personas(vec![
+            Persona {
+                name: DEFAULT_CHAT_PERSONA.to_string(),
+                description: "Casual chat and general questions".to_string(),
+                system_prompt: QUESTION_DIRECTED_PROMPT.to_string(),
+                model: "gpt-3.5-turbo".to_string(),
+                temperature: default_temperature(),
+                max_tokens: None,
+                vision: false,
+            },
+            Persona {
+                name: DEFAULT_DEBUG_PERSONA.to_string(),
+                description: "Helps debug code".to_string(),
+                system_prompt: DEBUG_DIRECTED_PROMPT.to_string(),
+                model: "gpt-4".to_string(),
+                temperature: 0.2,
+                max_tokens: None,
+                vision: false,
+            },
+        ])
+    }
+}
+
+impl PersonaRegistry {
+    pub fn from_personas(personas: Vec<Persona>) -> Self {
+        Self {
+            personas: personas
+                .into_iter()
+                .map(|p| (p.name.to_lowercase(), p))
+                .collect(),
+        }
+    }
+
+    /// Loads the personas from the JSON file at `AI_PERSONAS_PATH`
+    /// The file must contain an array of personas. Any persona missing from the
+    /// file that is required by the bot (`default` and `debug`) falls back to the built in one
+    pub fn from_env() -> Self {
+        let mut registry = Self::default();
+        let path = match env_var("AI_PERSONAS_PATH") {
+            Some(path) => path,
+            None => return registry,
+        };
+
+        let personas = std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|s| serde_json::from_str::<Vec<Persona>>(&s).map_err(anyhow::Error::from));
+        match personas {
+            Ok(personas) => {
+                info!("Loaded {} personas from {}", personas.len(), path);
+                for persona in personas {
+                    registry
+                        .personas
+                        .insert(persona.name.to_lowercase(), persona);
+                }
+            }
+            Err(e) => error!("Failed to load personas from {}: {:?}", path, e),
+        }
+        registry
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Persona> {
+        self.personas.get(&name.to_lowercase())
+    }
+
+    pub fn all(&self) -> impl Iterator<Item = &Persona> {
+        self.personas.values()
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use crate::config::env_var;
+
+/// Decides how failed requests to the AI are retried
+///
+/// Transient errors (rate limits, timeouts and server errors) are retried with exponential
+/// backoff and full jitter. When the retries run out, or the error cannot be retried,
+/// the request is sent once more to the fallback model if one is configured
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// How many times a transient error is retried
+    max_retries: u32,
+    /// The delay before the first retry, doubled for every retry after it
+    base_delay: Duration,
+    max_delay: Duration,
+    /// How long a single request can take before it is given up on
+    timeout: Duration,
+    /// The model used when the persona's model keeps failing
+    fallback_model: Option<String>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(8),
+            timeout: Duration::from_secs(90),
+            fallback_model: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Reads the retry configuration from the environment
+    /// * `AI_MAX_RETRIES` - how many times transient errors are retried, defaults to 2
+    /// * `AI_RETRY_BASE_MS` - the delay before the first retry, defaults to 500
+    /// * `AI_REQUEST_TIMEOUT_SECS` - how long a request can take, defaults to 90
+    /// * `AI_FALLBACK_MODEL` - the model used when the persona's model fails, e.g. `gpt-3.5-turbo`
+    pub fn from_env() -> Self {
+        let mut policy = Self::default();
+
+        if let Some(retries) = env_var("AI_MAX_RETRIES").and_then(|v| v.parse().ok()) {
+            policy.max_retries = retries;
+        }
+        if let Some(ms) = env_var("AI_RETRY_BASE_MS").and_then(|v| v.parse().ok()) {
+            policy.base_delay = Duration::from_millis(ms);
+        }
+        if let Some(secs) = env_var("AI_REQUEST_TIMEOUT_SECS").and_then(|v| v.parse().ok()) {
+            policy.timeout = Duration::from_secs(secs);
+        }
+        policy.fallback_model = env_var("AI_FALLBACK_MODEL");
+        policy
+    }
+
+    pub fn with_retries(mut self, max_retries: u32, base_delay: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.base_delay = base_delay;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_fallback_model(mut self, model: impl Into<String>) -> Self {
+        self.fallback_model = Some(model.into());
+        self
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn fallback_model(&self) -> Option<&str> {
+        self.fallback_model.as_deref()
+    }
+
+    /// Returns how long to wait before the given retry, starting at 0
+    /// The delay is picked at random up to the exponential backoff, so that
+    /// conversations hitting the same rate limit do not all retry at once
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        let random = RandomState::new().build_hasher().finish();
+        backoff.mul_f64((random % 1000) as f64 / 1000.0)
+    }
+}
@@ -0,0 +1,481 @@
+use std::{
+    fmt::{Display, Formatter},
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::Stdio,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use serenity::prelude::TypeMapKey;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    process::Command,
+};
+use tracing::{error, info};
+
+use crate::{config::env_var, storage};
+
+/// Used to give every run its own working directory
+static RUN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// The wrapper used for `AI_SANDBOX_ISOLATION=namespaces`
+/// The code gets its own user, network, pid and mount namespaces, so it has no network and
+/// can't see the bot's processes or their environment. The data directory is hidden behind an
+/// empty mount, but the rest of the filesystem stays readable, use `AI_SANDBOX_WRAPPER` with a
+/// container or chroot if the code must not see the host's files
+const NAMESPACE_WRAPPER: [&str; 9] = [
+    "unshare",
+    "--user",
+    "--map-root-user",
+    "--net",
+    "--pid",
+    "--fork",
+    "--mount-proc",
+    "--ipc",
+    "--uts",
+];
+
+/// A fenced code block from a Discord message
+#[derive(Debug, Clone)]
+pub struct CodeBlock {
+    /// The language from the fence tag, e.g. `py` in ```` ```py ````
+    pub language: Option<String>,
+    pub code: String,
+}
+
+impl CodeBlock {
+    /// Extracts the first fenced code block from the text
+    pub fn extract(text: &str) -> Option<Self> {
+        let start = text.find("```")? + 3;
+        let rest = &text[start..];
+        let end = rest.find("```")?;
+        let block = &rest[..end];
+
+        // The fence tag is everything on the first line, if the block spans multiple lines
+        match block.split_once('\n') {
+            Some((tag, code)) if !tag.trim().is_empty() && !tag.trim().contains(' ') => {
+                Some(Self {
+                    language: Some(tag.trim().to_lowercase()),
+                    code: code.to_string(),
+                })
+            }
+            _ => Some(Self {
+                language: None,
+                code: block.trim_start_matches('\n').to_string(),
+            }),
+        }
+    }
+}
+
+/// How a language is run inside the sandbox
+struct Runner {
+    /// The name shown to users and the AI
+    name: &'static str,
+    /// The file the code is written to
+    file: &'static str,
+    /// The command used to compile the code, if any
+    compile: Option<&'static str>,
+    /// The command used to run the code
+    run: &'static str,
+}
+
+fn runner_for(language: &str) -> Option<Runner> {
+    let runner = match language {
+        "py" | "python" | "python3" => Runner {
+            name: "Python",
+            file: "main.py",
+            compile: None,
+            run: "python3 main.py",
+        },
+        "js" | "javascript" | "node" => Runner {
+            name: "JavaScript",
+            file: "main.js",
+            compile: None,
+            run: "node main.js",
+        },
+        "sh" | "bash" | "shell" => Runner {
+            name: "Bash",
+            file: "main.sh",
+            compile: None,
+            run: "bash main.sh",
+        },
+        "rs" | "rust" => Runner {
+            name: "Rust",
+            file: "main.rs",
+            compile: Some("rustc --edition 2021 -o main main.rs"),
+            run: "./main",
+        },
+        "c" => Runner {
+            name: "C",
+            file: "main.c",
+            compile: Some("cc -o main main.c"),
+            run: "./main",
+        },
+        _ => return None,
+    };
+    Some(runner)
+}
+
+/// The outcome of running a code block
+#[derive(Debug, Clone)]
+pub struct ExecutionResult {
+    pub language: String,
+    /// None if the process was killed by a signal or timed out
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub timed_out: bool,
+}
+
+impl ExecutionResult {
+    /// A one line summary for the user
+    pub fn summary(&self) -> String {
+        if self.timed_out {
+            return format!("Ran the {} code: timed out", self.language);
+        }
+        match self.exit_code {
+            Some(code) => format!("Ran the {} code: exited with code {}", self.language, code),
+            None => format!("Ran the {} code: killed by a signal", self.language),
+        }
+    }
+
+    /// Renders the result so it can be given to the AI along with the code
+    pub fn to_prompt(&self) -> String {
+        let status = if self.timed_out {
+            "The program timed out and was killed".to_string()
+        } else {
+            match self.exit_code {
+                Some(code) => format!("Exit code: {}", code),
+                None => "The program was killed by a signal (likely a resource limit)".to_string(),
+            }
+        };
+        format!(
+            "I ran this {} code. {}\nstdout:\n```\n{}\n```\nstderr:\n```\n{}\n```",
+            self.language, status, self.stdout, self.stderr
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum SandboxError {
+    Disabled,
+    UnsupportedLanguage(Option<String>),
+    Io(std::io::Error),
+}
+
+impl Display for SandboxError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SandboxError::Disabled => write!(f, "Code execution is disabled"),
+            SandboxError::UnsupportedLanguage(Some(language)) => {
+                write!(f, "Running `{}` code is not supported", language)
+            }
+            SandboxError::UnsupportedLanguage(None) => write!(
+                f,
+                "Add a language to the code block (e.g. ```py) so it can be run"
+            ),
+            SandboxError::Io(e) => write!(f, "Failed to run the code: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for SandboxError {}
+
+impl From<std::io::Error> for SandboxError {
+    fn from(e: std::io::Error) -> Self {
+        SandboxError::Io(e)
+    }
+}
+
+/// Runs code blocks in a resource limited subprocess
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    enabled: bool,
+    /// Wall clock limit for compiling and running
+    timeout: Duration,
+    /// CPU time limit in seconds
+    cpu_seconds: u64,
+    /// Virtual memory limit for the program in KiB
+    memory_kb: u64,
+    /// The maximum number of processes, so fork bombs can't take down the host
+    max_processes: u64,
+    /// The maximum number of bytes kept from stdout and stderr
+    max_output: usize,
+    /// A command the sandbox is run under, e.g. `firejail --net=none --quiet`
+    /// Code is never run without one, the resource limits alone don't isolate it from the bot
+    wrapper: Vec<String>,
+    /// Directories covered with an empty tmpfs before the code runs, so it can't read them
+    /// Needs a wrapper that gives the code its own mount namespace
+    hidden_paths: Vec<PathBuf>,
+}
+
+impl TypeMapKey for Sandbox {
+    type Value = Sandbox;
+}
+
+impl Default for Sandbox {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout: Duration::from_secs(10),
+            cpu_seconds: 5,
+            memory_kb: 512 * 1024,
+            max_processes: 32,
+            max_output: 4000,
+            wrapper: vec![],
+            hidden_paths: vec![],
+        }
+    }
+}
+
+impl Sandbox {
+    /// Reads the sandbox configuration from the environment
+    /// Execution is only enabled when `AI_SANDBOX_ENABLED` is `true` and the code is isolated, either
+    /// by a wrapper in `AI_SANDBOX_WRAPPER` or by namespaces with `AI_SANDBOX_ISOLATION=namespaces`
+    pub fn from_env() -> Self {
+        let mut sandbox = Self {
+            enabled: env_var("AI_SANDBOX_ENABLED")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+            ..Self::default()
+        };
+        if let Some(timeout) = env_var("AI_SANDBOX_TIMEOUT_SECS").and_then(|v| v.parse().ok()) {
+            sandbox.timeout = Duration::from_secs(timeout);
+        }
+        if let Some(cpu) = env_var("AI_SANDBOX_CPU_SECS").and_then(|v| v.parse().ok()) {
+            sandbox.cpu_seconds = cpu;
+        }
+        if let Some(memory) = env_var("AI_SANDBOX_MEMORY_MB").and_then(|v| v.parse::<u64>().ok()) {
+            sandbox.memory_kb = memory * 1024;
+        }
+        if let Some(processes) = env_var("AI_SANDBOX_MAX_PROCESSES").and_then(|v| v.parse().ok()) {
+            sandbox.max_processes = processes;
+        }
+        if let Some(wrapper) = env_var("AI_SANDBOX_WRAPPER") {
+            sandbox.wrapper = wrapper.split_whitespace().map(String::from).collect();
+        } else if env_var("AI_SANDBOX_ISOLATION").as_deref() == Some("namespaces") {
+            sandbox.wrapper = NAMESPACE_WRAPPER.iter().map(|s| s.to_string()).collect();
+            // The data directory holds every user's conversations and the AI bans
+            let data_dir = storage::data_path("");
+            match std::env::current_dir() {
+                Ok(cwd) => sandbox.hidden_paths.push(cwd.join(data_dir)),
+                Err(e) => {
+                    error!(
+                        "Error resolving the data directory, code execution stays disabled: {:?}",
+                        e
+                    );
+                    sandbox.enabled = false;
+                }
+            }
+        }
+        if sandbox.enabled && sandbox.wrapper.is_empty() {
+            error!(
+                "AI_SANDBOX_ENABLED is set without AI_SANDBOX_WRAPPER or AI_SANDBOX_ISOLATION, code execution stays disabled"
+            );
+            sandbox.enabled = false;
+        }
+        sandbox
+    }
+
+    /// Enables execution, running the code under the wrapper
+    pub fn with_wrapper(mut self, wrapper: &[&str]) -> Self {
+        self.wrapper = wrapper.iter().map(|s| s.to_string()).collect();
+        self.enabled = true;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Hides the directory from the code, the wrapper must give the code its own mount namespace
+    pub fn with_hidden_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.hidden_paths.push(path.into());
+        self
+    }
+
+    pub fn with_max_output(mut self, max_output: usize) -> Self {
+        self.max_output = max_output;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled && !self.wrapper.is_empty()
+    }
+
+    /// Runs the code block and captures its output
+    pub async fn run(&self, block: &CodeBlock) -> Result<ExecutionResult, SandboxError> {
+        if !self.is_enabled() {
+            return Err(SandboxError::Disabled);
+        }
+        let runner = block
+            .language
+            .as_deref()
+            .and_then(runner_for)
+            .ok_or_else(|| SandboxError::UnsupportedLanguage(block.language.clone()))?;
+
+        let dir = std::env::temp_dir().join(format!(
+            "animeboys-sandbox-{}-{}",
+            std::process::id(),
+            RUN_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::create_dir_all(&dir).await?;
+        tokio::fs::write(dir.join(runner.file), &block.code).await?;
+
+        let result = self.run_in(&dir, &runner).await;
+
+        if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+            error!("Error removing sandbox dir {:?}: {:?}", dir, e);
+        }
+        result
+    }
+
+    async fn run_in(
+        &self,
+        dir: &PathBuf,
+        runner: &Runner,
+    ) -> Result<ExecutionResult, SandboxError> {
+        // The limits are applied by the shell before the program is started, and nothing runs
+        // if one can't be applied.
+        // The memory limit is only applied to the program, compilers need more address space
+        let mut script = String::new();
+        for path in &self.hidden_paths {
+            let path = shell_quote(&path.to_string_lossy());
+            script.push_str(&format!(
+                "if [ -e {0} ]; then mount -t tmpfs -o size=1k,mode=000 tmpfs {0} || exit 102; fi; ",
+                path
+            ));
+        }
+        script.push_str(&format!(
+            "ulimit -t {} && ulimit -f 10240 && ulimit -u {} || exit 102; ",
+            self.cpu_seconds, self.max_processes
+        ));
+        if let Some(compile) = runner.compile {
+            script.push_str(&format!("{} || exit 101; ", compile));
+        }
+        script.push_str(&format!(
+            "ulimit -v {} || exit 102; exec {}",
+            self.memory_kb, runner.run
+        ));
+
+        let (program, args) = self.wrapper.split_first().ok_or(SandboxError::Disabled)?;
+        let mut command = std::process::Command::new(program);
+        command
+            .args(args)
+            .arg("bash")
+            .arg("-c")
+            .arg(script)
+            .current_dir(dir)
+            .env_clear()
+            .env("PATH", std::env::var("PATH").unwrap_or_default())
+            .env("HOME", dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Everything the code starts is in the group, so it can all be killed at once
+            .process_group(0);
+        let mut command = Command::from(command);
+        command.kill_on_drop(true);
+
+        info!("Running {} code in {:?}", runner.name, dir);
+        let mut child = command.spawn()?;
+        let group = child.id().map(|id| id as i32);
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+        // `ulimit -f` doesn't apply to pipes, so at most `max_output` bytes are read from each
+        // and the program is killed as soon as it writes more
+        let output = tokio::time::timeout(self.timeout, async {
+            let (stdout, stderr) = tokio::join!(
+                read_capped(stdout, self.max_output, group),
+                read_capped(stderr, self.max_output, group)
+            );
+            if let Some(pid) = group {
+                let _ = tokio::task::spawn_blocking(move || wait_without_reaping(pid)).await;
+            }
+            (stdout, stderr)
+        })
+        .await;
+        // Kill anything left running in the background, even when the program exited.
+        // The program is only reaped afterwards, until then its group id can't be reused
+        kill_group(group);
+        let status = child.wait().await;
+
+        match output {
+            Ok((stdout, stderr)) => Ok(ExecutionResult {
+                language: runner.name.to_string(),
+                exit_code: status?.code(),
+                stdout: self.truncate_output(&stdout?),
+                stderr: self.truncate_output(&stderr?),
+                timed_out: false,
+            }),
+            Err(_) => Ok(ExecutionResult {
+                language: runner.name.to_string(),
+                exit_code: None,
+                stdout: String::new(),
+                stderr: String::new(),
+                timed_out: true,
+            }),
+        }
+    }
+
+    fn truncate_output(&self, output: &[u8]) -> String {
+        let output = String::from_utf8_lossy(output);
+        if output.len() <= self.max_output {
+            return output.into_owned();
+        }
+        let mut end = self.max_output;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}\n... (output truncated)", &output[..end])
+    }
+}
+
+/// Reads a pipe until it closes or more than `max` bytes were read
+/// The process group is killed once the limit is passed, so the program can't keep writing
+async fn read_capped<R: AsyncRead + Unpin>(
+    pipe: Option<R>,
+    max: usize,
+    group: Option<i32>,
+) -> std::io::Result<Vec<u8>> {
+    let mut output = vec![];
+    if let Some(pipe) = pipe {
+        pipe.take(max as u64 + 1).read_to_end(&mut output).await?;
+    }
+    if output.len() > max {
+        kill_group(group);
+    }
+    Ok(output)
+}
+
+fn kill_group(group: Option<i32>) {
+    if let Some(group) = group {
+        // SAFETY: killpg only sends a signal, the group was created for this run
+        unsafe {
+            libc::killpg(group, libc::SIGKILL);
+        }
+    }
+}
+
+/// Blocks until the process exits, leaving it a zombie so its pid and group are not reused
+fn wait_without_reaping(pid: i32) {
+    // SAFETY: waitid only writes to the siginfo it is given
+    unsafe {
+        let mut info: libc::siginfo_t = std::mem::zeroed();
+        while libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            &mut info,
+            libc::WEXITED | libc::WNOWAIT,
+        ) == -1
+            && std::io::Error::last_os_error().kind() == std::io::ErrorKind::Interrupted
+        {}
+    }
+}
+
+/// Quotes a string so bash reads it as a single word
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
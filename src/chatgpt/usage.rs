@@ -0,0 +1,495 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use serenity::model::prelude::{ChannelId, UserId};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    task::JoinHandle,
+};
+use tracing::{error, info};
+
+use crate::{config::env_var, storage};
+
+use super::backend::Usage;
+
+const USAGE_FILE: &str = "ai_usage.json";
+/// How often recorded usage is written to the data directory
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How a request to the AI ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    /// The response was cut off by the token limit
+    Truncated,
+    Error,
+}
+
+impl Outcome {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Truncated => "truncated",
+            Outcome::Error => "error",
+        }
+    }
+}
+
+/// A single request to the AI
+#[derive(Debug, Clone)]
+pub struct UsageRecord {
+    pub model: String,
+    /// None if the request was not made on behalf of a user, e.g. the intro of a conversation
+    pub user: Option<UserId>,
+    pub channel: ChannelId,
+    pub usage: Usage,
+    pub latency: Duration,
+    pub outcome: Outcome,
+}
+
+/// The price of a model in USD per 1000 tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
+}
+
+fn default_prices() -> BTreeMap<String, ModelPrice> {
+    [
+        ("gpt-3.5-turbo", 0.0005, 0.0015),
+        ("gpt-4", 0.03, 0.06),
+        ("gpt-4-turbo", 0.01, 0.03),
+        ("gpt-4o", 0.005, 0.015),
+        ("gpt-4o-mini", 0.00015, 0.0006),
+    ]
+    .into_iter()
+    .map(|(model, prompt, completion)| (model.to_string(), ModelPrice { prompt, completion }))
+    .collect()
+}
+
+/// Aggregated usage
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub errors: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub latency_ms: u64,
+    pub cost_usd: f64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, record: &UsageRecord, cost: f64) {
+        self.requests += 1;
+        if record.outcome == Outcome::Error {
+            self.errors += 1;
+        }
+        self.prompt_tokens += record.usage.prompt_tokens as u64;
+        self.completion_tokens += record.usage.completion_tokens as u64;
+        self.latency_ms += record.latency.as_millis() as u64;
+        self.cost_usd += cost;
+    }
+
+    fn merge(&mut self, other: &UsageTotals) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.latency_ms += other.latency_ms;
+        self.cost_usd += other.cost_usd;
+    }
+
+    pub fn average_latency_ms(&self) -> u64 {
+        self.latency_ms.checked_div(self.requests).unwrap_or(0)
+    }
+}
+
+/// The usage of a single day
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct DayUsage {
+    total: UsageTotals,
+    models: BTreeMap<String, UsageTotals>,
+    users: BTreeMap<u64, UsageTotals>,
+    channels: BTreeMap<u64, UsageTotals>,
+}
+
+/// The usage over a number of days, each breakdown sorted by cost
+#[derive(Debug, Clone, Default)]
+pub struct UsageReport {
+    pub total: UsageTotals,
+    pub models: Vec<(String, UsageTotals)>,
+    pub users: Vec<(UserId, UsageTotals)>,
+    pub channels: Vec<(ChannelId, UsageTotals)>,
+}
+
+fn sorted<K, T>(map: BTreeMap<K, UsageTotals>, key: impl Fn(K) -> T) -> Vec<(T, UsageTotals)> {
+    let mut entries = map
+        .into_iter()
+        .map(|(k, totals)| (key(k), totals))
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| {
+        b.1.cost_usd
+            .total_cmp(&a.1.cost_usd)
+            .then(b.1.requests.cmp(&a.1.requests))
+    });
+    entries
+}
+
+/// The recorded usage, as saved to the data directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct UsageState {
+    /// Usage per day, keyed by `YYYY-MM-DD` in UTC, kept for the retention window
+    days: BTreeMap<String, DayUsage>,
+    /// Usage per model since it was first recorded, never pruned so the metrics only go up
+    models: BTreeMap<String, UsageTotals>,
+    /// Requests per model and outcome since they were first recorded
+    outcomes: BTreeMap<String, BTreeMap<String, u64>>,
+}
+
+impl UsageState {
+    /// Drops the days older than `retention_days`
+    fn prune(&mut self, retention_days: u64) {
+        let oldest = date_from_days(days_since_epoch().saturating_sub(retention_days));
+        self.days = self.days.split_off(&oldest);
+    }
+}
+
+/// Records every request made to the AI, aggregated per day, model, user and channel
+pub struct UsageTracker {
+    state: Mutex<UsageState>,
+    prices: BTreeMap<String, ModelPrice>,
+    /// How many days of usage are kept for `$ai stats`
+    retention_days: u64,
+    /// Whether the usage is saved to the data directory
+    persist: bool,
+    /// Set when usage was recorded since the last save
+    dirty: AtomicBool,
+}
+
+impl Default for UsageTracker {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(UsageState::default()),
+            prices: default_prices(),
+            retention_days: 90,
+            persist: false,
+            dirty: AtomicBool::new(false),
+        }
+    }
+}
+
+impl UsageTracker {
+    /// Loads the recorded usage and reads the prices from the environment
+    /// * `AI_MODEL_PRICES_PATH` - JSON object of model to `{ "prompt": .., "completion": .. }`
+    ///   in USD per 1000 tokens, overriding the built in prices
+    /// * `AI_USAGE_RETENTION_DAYS` - days of usage kept for `$ai stats`, defaults to 90
+    pub fn from_env() -> Self {
+        let mut tracker = Self {
+            state: Mutex::new(storage::load_json(USAGE_FILE)),
+            persist: true,
+            ..Default::default()
+        };
+        if let Some(days) = env_var("AI_USAGE_RETENTION_DAYS").and_then(|v| v.parse::<u64>().ok()) {
+            tracker.retention_days = days.max(1);
+        }
+        if let Some(path) = env_var("AI_MODEL_PRICES_PATH") {
+            let prices = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|json| Ok(serde_json::from_str::<BTreeMap<String, ModelPrice>>(&json)?));
+            match prices {
+                Ok(prices) => tracker.prices.extend(prices),
+                Err(e) => error!("Failed to load model prices from {}: {:?}", path, e),
+            }
+        }
+        tracker.state.lock().unwrap().prune(tracker.retention_days);
+        tracker
+    }
+
+    /// Starts a task that saves the recorded usage every minute, if any was recorded
+    /// Saving is batched so recording a request never waits on the disk
+    pub fn spawn_saver(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SAVE_INTERVAL);
+            loop {
+                interval.tick().await;
+                self.save().await;
+            }
+        })
+    }
+
+    /// Saves the usage if any was recorded since the last save, dropping the days past the retention window
+    pub async fn save(&self) {
+        if !self.persist || !self.dirty.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let state = {
+            let mut state = self.state.lock().unwrap();
+            state.prune(self.retention_days);
+            state.clone()
+        };
+        if let Err(e) = storage::save_json_async(USAGE_FILE, state).await {
+            error!("Error saving AI usage: {:?}", e);
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Returns the cost of the usage in USD
+    /// Models are matched on the longest known prefix, e.g. `gpt-4-0613` is priced as `gpt-4`
+    pub fn cost(&self, model: &str, usage: &Usage) -> f64 {
+        let price = self
+            .prices
+            .iter()
+            .filter(|(name, _)| model.starts_with(name.as_str()))
+            .max_by_key(|(name, _)| name.len())
+            .map(|(_, price)| *price);
+        match price {
+            Some(price) => {
+                (usage.prompt_tokens as f64 * price.prompt
+                    + usage.completion_tokens as f64 * price.completion)
+                    / 1000.0
+            }
+            None => 0.0,
+        }
+    }
+
+    pub fn record(&self, record: UsageRecord) {
+        let cost = self.cost(&record.model, &record.usage);
+        info!(
+            "AI request: model {}, {} prompt tokens, {} completion tokens, {}ms, {}",
+            record.model,
+            record.usage.prompt_tokens,
+            record.usage.completion_tokens,
+            record.latency.as_millis(),
+            record.outcome.label()
+        );
+
+        let mut state = self.state.lock().unwrap();
+        *state
+            .outcomes
+            .entry(record.model.clone())
+            .or_default()
+            .entry(record.outcome.label().to_string())
+            .or_insert(0) += 1;
+        state
+            .models
+            .entry(record.model.clone())
+            .or_default()
+            .add(&record, cost);
+
+        let day = state.days.entry(today()).or_default();
+        day.total.add(&record, cost);
+        day.models
+            .entry(record.model.clone())
+            .or_default()
+            .add(&record, cost);
+        if let Some(user) = record.user {
+            day.users.entry(user.0).or_default().add(&record, cost);
+        }
+        day.channels
+            .entry(record.channel.0)
+            .or_default()
+            .add(&record, cost);
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Aggregates the usage of the last `days` days, including today
+    pub fn report(&self, days: u64) -> UsageReport {
+        let since = date_from_days(days_since_epoch().saturating_sub(days.saturating_sub(1)));
+        let mut total = UsageTotals::default();
+        let mut models = BTreeMap::new();
+        let mut users = BTreeMap::new();
+        let mut channels = BTreeMap::new();
+
+        for (_, day) in self.state.lock().unwrap().days.range(since..) {
+            total.merge(&day.total);
+            for (model, totals) in &day.models {
+                models
+                    .entry(model.clone())
+                    .or_insert_with(UsageTotals::default)
+                    .merge(totals);
+            }
+            for (user, totals) in &day.users {
+                users
+                    .entry(*user)
+                    .or_insert_with(UsageTotals::default)
+                    .merge(totals);
+            }
+            for (channel, totals) in &day.channels {
+                channels
+                    .entry(*channel)
+                    .or_insert_with(UsageTotals::default)
+                    .merge(totals);
+            }
+        }
+
+        UsageReport {
+            total,
+            models: sorted(models, |model| model),
+            users: sorted(users, UserId),
+            channels: sorted(channels, ChannelId),
+        }
+    }
+
+    /// Renders the usage of every model in the Prometheus text format
+    /// Every metric counts from when usage was first recorded, so they survive restarts
+    pub fn to_prometheus(&self) -> String {
+        let state = self.state.lock().unwrap().clone();
+
+        let mut metrics = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            let _ = writeln!(metrics, "# HELP {} {}", name, help);
+            let _ = writeln!(metrics, "# TYPE {} {}", name, kind);
+            for (sample, value) in samples {
+                let _ = writeln!(metrics, "{} {}", sample, value);
+            }
+        };
+        let per_model = |name: &str, value: &dyn Fn(&UsageTotals) -> String| {
+            state
+                .models
+                .iter()
+                .map(|(model, totals)| {
+                    (
+                        format!("{}{{model=\"{}\"}}", name, escape_label(model)),
+                        value(totals),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        metric(
+            "animeboys_ai_requests_total",
+            "counter",
+            "Requests made to the AI",
+            state
+                .outcomes
+                .iter()
+                .flat_map(|(model, outcomes)| {
+                    outcomes.iter().map(move |(outcome, count)| {
+                        (
+                            format!(
+                                "animeboys_ai_requests_total{{model=\"{}\",outcome=\"{}\"}}",
+                                escape_label(model),
+                                escape_label(outcome)
+                            ),
+                            count.to_string(),
+                        )
+                    })
+                })
+                .collect(),
+        );
+        metric(
+            "animeboys_ai_prompt_tokens_total",
+            "counter",
+            "Prompt tokens used",
+            per_model("animeboys_ai_prompt_tokens_total", &|t| {
+                t.prompt_tokens.to_string()
+            }),
+        );
+        metric(
+            "animeboys_ai_completion_tokens_total",
+            "counter",
+            "Completion tokens used",
+            per_model("animeboys_ai_completion_tokens_total", &|t| {
+                t.completion_tokens.to_string()
+            }),
+        );
+        metric(
+            "animeboys_ai_cost_usd_total",
+            "counter",
+            "Estimated cost in USD",
+            per_model("animeboys_ai_cost_usd_total", &|t| {
+                format!("{:.6}", t.cost_usd)
+            }),
+        );
+        // The latency is a summary without quantiles, its sum and count are samples of one metric
+        let mut latency = per_model("animeboys_ai_latency_seconds_sum", &|t| {
+            format!("{:.3}", t.latency_ms as f64 / 1000.0)
+        });
+        latency.extend(per_model("animeboys_ai_latency_seconds_count", &|t| {
+            t.requests.to_string()
+        }));
+        metric(
+            "animeboys_ai_latency_seconds",
+            "summary",
+            "Time spent waiting on the AI",
+            latency,
+        );
+        metrics
+    }
+
+    /// Serves the metrics over HTTP for Prometheus to scrape
+    pub async fn serve_metrics(self: Arc<Self>, addr: &str) -> std::io::Result<JoinHandle<()>> {
+        let listener = TcpListener::bind(addr).await?;
+        info!("Serving AI metrics on {}", listener.local_addr()?);
+        Ok(tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = match listener.accept().await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        error!("Error accepting metrics connection: {:?}", e);
+                        continue;
+                    }
+                };
+                let tracker = self.clone();
+                tokio::spawn(async move {
+                    // Every path serves the metrics, so the request itself is not needed
+                    let mut buf = [0; 1024];
+                    let _ = socket.read(&mut buf).await;
+                    let body = tracker.to_prometheus();
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: text/plain; version=0.0.4\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    if let Err(e) = socket.write_all(response.as_bytes()).await {
+                        error!("Error serving metrics: {:?}", e);
+                    }
+                });
+            }
+        }))
+    }
+}
+
+/// Escapes a label value as the Prometheus text format requires
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn days_since_epoch() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86400
+}
+
+fn today() -> String {
+    date_from_days(days_since_epoch())
+}
+
+/// Converts days since the unix epoch to a `YYYY-MM-DD` date
+fn date_from_days(days: u64) -> String {
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
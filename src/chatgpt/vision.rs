@@ -0,0 +1,157 @@
+use std::{
+    fmt::{Display, Formatter},
+    process::Stdio,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use tokio::process::Command;
+use tracing::{error, info};
+
+use crate::config::env_var;
+
+/// Used to give every image its own file
+static IMAGE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Extensions of files that are treated as images even without an image content type
+pub const IMAGE_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "gif", "webp", "bmp"];
+
+#[derive(Debug)]
+pub enum OcrError {
+    Disabled,
+    TooLarge(u64),
+    Http(reqwest::Error),
+    Io(std::io::Error),
+    /// The OCR command exited with an error
+    Failed(String),
+    TimedOut,
+}
+
+impl Display for OcrError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OcrError::Disabled => write!(f, "OCR is disabled"),
+            OcrError::TooLarge(size) => write!(f, "the image is too large ({} bytes)", size),
+            OcrError::Http(e) => write!(f, "the image could not be downloaded: {}", e),
+            OcrError::Io(e) => write!(f, "OCR could not be run: {}", e),
+            OcrError::Failed(stderr) => write!(f, "OCR failed: {}", stderr),
+            OcrError::TimedOut => write!(f, "OCR timed out"),
+        }
+    }
+}
+
+impl std::error::Error for OcrError {}
+
+impl From<reqwest::Error> for OcrError {
+    fn from(e: reqwest::Error) -> Self {
+        OcrError::Http(e)
+    }
+}
+
+impl From<std::io::Error> for OcrError {
+    fn from(e: std::io::Error) -> Self {
+        OcrError::Io(e)
+    }
+}
+
+/// Reads the text in images with a local OCR command, for models that cannot see images
+#[derive(Debug, Clone)]
+pub struct Ocr {
+    http: reqwest::Client,
+    /// The command the image file is passed to, e.g. `tesseract`
+    /// It is run as `<command> <file> stdout`, the way tesseract is
+    command: Vec<String>,
+    timeout: Duration,
+    /// Images larger than this are not read
+    max_bytes: u64,
+}
+
+impl Default for Ocr {
+    fn default() -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            command: vec![],
+            timeout: Duration::from_secs(20),
+            max_bytes: 8 * 1024 * 1024,
+        }
+    }
+}
+
+impl Ocr {
+    /// Reads the OCR configuration from the environment
+    /// OCR is only enabled when `AI_OCR_COMMAND` is set, e.g. to `tesseract`
+    pub fn from_env() -> Self {
+        match env_var("AI_OCR_COMMAND") {
+            Some(command) => Self::default().with_command(&command),
+            None => Self::default(),
+        }
+    }
+
+    /// Sets the OCR command, e.g. `tesseract`
+    pub fn with_command(mut self, command: &str) -> Self {
+        self.command = command.split_whitespace().map(String::from).collect();
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.command.is_empty()
+    }
+
+    /// Downloads the image and returns the text read from it
+    /// * `size` - the size Discord reports for the image, so large images are never downloaded
+    pub async fn read(&self, url: &str, size: u64) -> Result<String, OcrError> {
+        let (program, args) = self.command.split_first().ok_or(OcrError::Disabled)?;
+        if size > self.max_bytes {
+            return Err(OcrError::TooLarge(size));
+        }
+
+        let bytes = self
+            .http
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        // The reported size can't be trusted for every URL, so the download is checked as well
+        if bytes.len() as u64 > self.max_bytes {
+            return Err(OcrError::TooLarge(bytes.len() as u64));
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "animeboys-ocr-{}-{}",
+            std::process::id(),
+            IMAGE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        tokio::fs::write(&path, &bytes).await?;
+
+        info!("Reading text from {} with {}", url, program);
+        let child = Command::new(program)
+            .args(args)
+            .arg(&path)
+            .arg("stdout")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn();
+        let result = match child {
+            Ok(child) => match tokio::time::timeout(self.timeout, child.wait_with_output()).await {
+                Ok(Ok(output)) if output.status.success() => {
+                    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+                }
+                Ok(Ok(output)) => Err(OcrError::Failed(
+                    String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                )),
+                Ok(Err(e)) => Err(e.into()),
+                Err(_) => Err(OcrError::TimedOut),
+            },
+            Err(e) => Err(e.into()),
+        };
+
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            error!("Error removing OCR image {:?}: {:?}", path, e);
+        }
+        result
+    }
+}
@@ -0,0 +1,4 @@
+/// Reads an environment variable, treating an empty value the same as an unset one
+pub fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.is_empty())
+}
@@ -1,4 +1,6 @@
 pub mod aws;
 pub mod bot;
 pub mod chatgpt;
+pub mod config;
+pub mod storage;
 pub mod wz;
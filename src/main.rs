@@ -13,10 +13,10 @@ async fn main() {
         .init();
     let token = std::env::var("DISCORD_TOKEN").expect("DISCORD_TOKEN is required");
     let instance_id = std::env::var("INSTANCE_ID").expect("INSTANCE_ID is required");
-    let api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY is required");
+    let backend = animeboys_bot::chatgpt::backend::backend_from_env();
     let intents = GatewayIntents::all();
 
-    let mut client = animeboys_bot::bot::create_bot(token, intents, api_key, instance_id).await;
+    let mut client = animeboys_bot::bot::create_bot(token, intents, backend, instance_id).await;
 
     if let Err(why) = client.start().await {
         println!("Error starting client: {:?}", why);
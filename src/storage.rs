@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::error;
+
+use crate::config::env_var;
+
+/// Returns the path of a file in the data directory
+/// The directory is set with `DATA_DIR` and defaults to `data`
+pub fn data_path(name: &str) -> PathBuf {
+    let dir = env_var("DATA_DIR").unwrap_or_else(|| "data".to_string());
+    PathBuf::from(dir).join(name)
+}
+
+/// Loads a JSON file from the data directory
+/// Returns the default value if the file does not exist or cannot be parsed
+pub fn load_json<T: DeserializeOwned + Default>(name: &str) -> T {
+    let path = data_path(name);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return T::default(),
+        Err(e) => {
+            error!("Error reading {:?}: {:?}", path, e);
+            return T::default();
+        }
+    };
+    serde_json::from_str(&contents).unwrap_or_else(|e| {
+        error!("Error parsing {:?}: {:?}", path, e);
+        T::default()
+    })
+}
+
+/// Saves a value as a JSON file in the data directory
+/// The file is written to a temporary file first so a crash never leaves it half written
+pub fn save_json<T: Serialize>(name: &str, value: &T) -> Result<(), anyhow::Error> {
+    let path = data_path(name);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec_pretty(value)?)?;
+    std::fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+/// Loads a JSON file from the data directory on the blocking thread pool
+pub async fn load_json_async<T: DeserializeOwned + Default + Send + 'static>(name: &str) -> T {
+    let name = name.to_string();
+    tokio::task::spawn_blocking(move || load_json(&name))
+        .await
+        .unwrap_or_default()
+}
+
+/// Saves a value as a JSON file in the data directory on the blocking thread pool
+pub async fn save_json_async<T: Serialize + Send + 'static>(
+    name: &str,
+    value: T,
+) -> Result<(), anyhow::Error> {
+    let name = name.to_string();
+    tokio::task::spawn_blocking(move || save_json(&name, &value)).await?
+}
@@ -0,0 +1,284 @@
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+use anyhow::anyhow;
+use tiny_skia::{Color, Paint, PathBuilder, Pixmap, PremultipliedColorU8, Rect, Transform};
+
+use super::{embed::tier_colour, types::Weapon};
+
+const REGULAR_FONT: &[u8] = include_bytes!("fonts/DejaVuSans.ttf");
+const BOLD_FONT: &[u8] = include_bytes!("fonts/DejaVuSans-Bold.ttf");
+
+const WIDTH: u32 = 800;
+const PADDING: f32 = 24.0;
+const HEADER_HEIGHT: f32 = 96.0;
+const ROW_HEIGHT: f32 = 56.0;
+/// Where the tuning bars of each row start
+const BARS_X: f32 = 470.0;
+const BAR_WIDTH: f32 = 120.0;
+const BAR_GAP: f32 = 32.0;
+
+const BACKGROUND: [u8; 3] = [0x1E, 0x1F, 0x24];
+const ROW_BACKGROUND: [u8; 3] = [0x26, 0x28, 0x2E];
+const TRACK: [u8; 3] = [0x3A, 0x3D, 0x45];
+const TEXT: [u8; 3] = [0xF2, 0xF3, 0xF5];
+const MUTED: [u8; 3] = [0x9A, 0x9E, 0xA8];
+const POSITIVE: [u8; 3] = [0x2E, 0xCC, 0x71];
+const NEGATIVE: [u8; 3] = [0xE7, 0x4C, 0x3C];
+const UNRANKED: u32 = 0x5D6069;
+
+/// Draws a build card as a PNG: the weapon name and playstyle, a badge with its tier, and a row
+/// for every attachment with its name and horizontal and vertical tuning bars
+///
+/// Rendering is done on the CPU with bundled fonts, so it works on a headless host
+/// * `name` - the display name of the weapon
+/// * `tier` - the tier the weapon is in on the tier list, if it is ranked
+pub fn render_build_card(
+    weapon: &Weapon,
+    name: &str,
+    tier: Option<&str>,
+) -> Result<Vec<u8>, anyhow::Error> {
+    let regular = FontRef::try_from_slice(REGULAR_FONT)?;
+    let bold = FontRef::try_from_slice(BOLD_FONT)?;
+    let attachments = weapon.get_loadout_attachments();
+
+    let rows = attachments.len().max(1);
+    let height = HEADER_HEIGHT + rows as f32 * ROW_HEIGHT + PADDING;
+    let mut pixmap = Pixmap::new(WIDTH, height as u32).ok_or(anyhow!("Invalid card size"))?;
+    pixmap.fill(Color::from_rgba8(
+        BACKGROUND[0],
+        BACKGROUND[1],
+        BACKGROUND[2],
+        255,
+    ));
+
+    // Header
+    let badge = tier.unwrap_or("Unranked");
+    let badge_colour = rgb(tier.and_then(tier_colour).unwrap_or(UNRANKED));
+    let badge_width = text_width(&bold, 20.0, badge) + 32.0;
+    let badge_x = WIDTH as f32 - PADDING - badge_width;
+    fill_pill(&mut pixmap, badge_x, 28.0, badge_width, 40.0, badge_colour);
+    draw_text(&mut pixmap, &bold, 20.0, badge_x + 16.0, 55.0, TEXT, badge);
+
+    let name = fit_text(&bold, 34.0, name, badge_x - PADDING * 2.0);
+    draw_text(&mut pixmap, &bold, 34.0, PADDING, 52.0, TEXT, &name);
+    let mut subtitle = weapon.get_playstyle_name();
+    if !weapon.author_display_name.is_empty() {
+        subtitle = match subtitle.is_empty() {
+            true => format!("by {}", weapon.author_display_name),
+            false => format!("{} · by {}", subtitle, weapon.author_display_name),
+        };
+    }
+    let subtitle = fit_text(&regular, 16.0, &subtitle, badge_x - PADDING * 2.0);
+    draw_text(&mut pixmap, &regular, 16.0, PADDING, 80.0, MUTED, &subtitle);
+
+    // Tuning bars are scaled to the largest tuning on the card
+    let scale = attachments
+        .iter()
+        .filter_map(|(_, a)| a.get_tuning())
+        .map(|(hz, vt)| hz.abs().max(vt.abs()))
+        .fold(1.0, f64::max) as f32;
+
+    if attachments.is_empty() {
+        let y = HEADER_HEIGHT;
+        fill_rect(
+            &mut pixmap,
+            PADDING,
+            y,
+            WIDTH as f32 - PADDING * 2.0,
+            ROW_HEIGHT - 8.0,
+            ROW_BACKGROUND,
+        );
+        draw_text(
+            &mut pixmap,
+            &regular,
+            18.0,
+            PADDING + 16.0,
+            y + 31.0,
+            MUTED,
+            "No attachments",
+        );
+    }
+    for (i, (slot, attachment)) in attachments.iter().enumerate() {
+        let y = HEADER_HEIGHT + i as f32 * ROW_HEIGHT;
+        fill_rect(
+            &mut pixmap,
+            PADDING,
+            y,
+            WIDTH as f32 - PADDING * 2.0,
+            ROW_HEIGHT - 8.0,
+            ROW_BACKGROUND,
+        );
+        draw_text(
+            &mut pixmap,
+            &regular,
+            12.0,
+            PADDING + 16.0,
+            y + 17.0,
+            MUTED,
+            &slot.to_string().to_uppercase(),
+        );
+        let attachment_name = fit_text(&bold, 18.0, &attachment.name, BARS_X - PADDING * 3.0);
+        draw_text(
+            &mut pixmap,
+            &bold,
+            18.0,
+            PADDING + 16.0,
+            y + 39.0,
+            TEXT,
+            &attachment_name,
+        );
+
+        // Tunings the bot can't read are left out rather than shown as 0
+        let (hz, vt) = match attachment.get_tuning() {
+            Some(tuning) => tuning,
+            None => continue,
+        };
+        for (j, (label, value)) in [("H", hz), ("V", vt)].into_iter().enumerate() {
+            let x = BARS_X + j as f32 * (BAR_WIDTH + BAR_GAP);
+            draw_text(
+                &mut pixmap,
+                &regular,
+                12.0,
+                x,
+                y + 17.0,
+                MUTED,
+                &format!("{} {}", label, value),
+            );
+            draw_tuning_bar(&mut pixmap, x, y + 26.0, value as f32 / scale);
+        }
+    }
+
+    Ok(pixmap.encode_png()?)
+}
+
+/// Draws a bar centred on zero, filled to the left for negative tunings and to the right for positive ones
+/// * `amount` - the tuning from -1 to 1
+fn draw_tuning_bar(pixmap: &mut Pixmap, x: f32, y: f32, amount: f32) {
+    let centre = x + BAR_WIDTH / 2.0;
+    fill_rect(pixmap, x, y, BAR_WIDTH, 8.0, TRACK);
+    let fill = amount.clamp(-1.0, 1.0) * BAR_WIDTH / 2.0;
+    if fill > 0.0 {
+        fill_rect(pixmap, centre, y, fill, 8.0, POSITIVE);
+    } else if fill < 0.0 {
+        fill_rect(pixmap, centre + fill, y, -fill, 8.0, NEGATIVE);
+    }
+    fill_rect(pixmap, centre - 1.0, y - 2.0, 2.0, 12.0, MUTED);
+}
+
+fn paint(colour: [u8; 3]) -> Paint<'static> {
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(colour[0], colour[1], colour[2], 255);
+    paint.anti_alias = true;
+    paint
+}
+
+fn rgb(colour: u32) -> [u8; 3] {
+    [(colour >> 16) as u8, (colour >> 8) as u8, colour as u8]
+}
+
+fn fill_rect(pixmap: &mut Pixmap, x: f32, y: f32, width: f32, height: f32, colour: [u8; 3]) {
+    if let Some(rect) = Rect::from_xywh(x, y, width, height) {
+        pixmap.fill_rect(rect, &paint(colour), Transform::identity(), None);
+    }
+}
+
+/// Fills a rectangle with fully rounded ends
+fn fill_pill(pixmap: &mut Pixmap, x: f32, y: f32, width: f32, height: f32, colour: [u8; 3]) {
+    let radius = height / 2.0;
+    let mut path = PathBuilder::new();
+    path.push_circle(x + radius, y + radius, radius);
+    path.push_circle(x + width - radius, y + radius, radius);
+    if let Some(rect) = Rect::from_xywh(x + radius, y, width - height, height) {
+        path.push_rect(rect);
+    }
+    if let Some(path) = path.finish() {
+        pixmap.fill_path(
+            &path,
+            &paint(colour),
+            tiny_skia::FillRule::Winding,
+            Transform::identity(),
+            None,
+        );
+    }
+}
+
+fn text_width(font: &FontRef, size: f32, text: &str) -> f32 {
+    let font = font.as_scaled(PxScale::from(size));
+    let mut width = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, id);
+        }
+        width += font.h_advance(id);
+        previous = Some(id);
+    }
+    width
+}
+
+/// Cuts the text with `…` so it is at most `max_width` wide
+fn fit_text(font: &FontRef, size: f32, text: &str, max_width: f32) -> String {
+    if text_width(font, size, text) <= max_width {
+        return text.to_string();
+    }
+    let mut chars = text.chars().collect::<Vec<_>>();
+    while !chars.is_empty() {
+        chars.pop();
+        let fitted = format!("{}…", chars.iter().collect::<String>().trim_end());
+        if text_width(font, size, &fitted) <= max_width {
+            return fitted;
+        }
+    }
+    String::new()
+}
+
+/// Draws the text with its baseline at `y`, blending each glyph's coverage onto the card
+fn draw_text(
+    pixmap: &mut Pixmap,
+    font: &FontRef,
+    size: f32,
+    x: f32,
+    y: f32,
+    colour: [u8; 3],
+    text: &str,
+) {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let (width, height) = (pixmap.width() as i32, pixmap.height() as i32);
+    let pixels = pixmap.pixels_mut();
+    let mut caret = x;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        let glyph = id.with_scale_and_position(size, ab_glyph::point(caret, y));
+        caret += scaled.h_advance(id);
+        previous = Some(id);
+
+        let outlined = match font.outline_glyph(glyph) {
+            Some(outlined) => outlined,
+            None => continue,
+        };
+        let bounds = outlined.px_bounds();
+        outlined.draw(|gx, gy, coverage| {
+            let px = bounds.min.x as i32 + gx as i32;
+            let py = bounds.min.y as i32 + gy as i32;
+            if px < 0 || py < 0 || px >= width || py >= height {
+                return;
+            }
+            let pixel = &mut pixels[(py * width + px) as usize];
+            let blend = |src: u8, dst: u8| {
+                (src as f32 * coverage + dst as f32 * (1.0 - coverage)).round() as u8
+            };
+            if let Some(blended) = PremultipliedColorU8::from_rgba(
+                blend(colour[0], pixel.red()),
+                blend(colour[1], pixel.green()),
+                blend(colour[2], pixel.blue()),
+                255,
+            ) {
+                *pixel = blended;
+            }
+        });
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Display, Formatter},
+    str::FromStr,
+    sync::RwLock,
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::config::env_var;
+
+use super::types::{TierListMap, TierListResponse};
+
+/// The weapons known when the bot was built, used until wzstats has been reached
+const BUNDLED_WEAPONS: &str = include_str!("weapons.json");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum WeaponCategory {
+    AssaultRifle,
+    BattleRifle,
+    Smg,
+    Shotgun,
+    Lmg,
+    MarksmanRifle,
+    SniperRifle,
+    Handgun,
+    Launcher,
+    Melee,
+    #[default]
+    #[serde(other)]
+    Other,
+}
+
+impl Display for WeaponCategory {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            WeaponCategory::AssaultRifle => "Assault Rifles",
+            WeaponCategory::BattleRifle => "Battle Rifles",
+            WeaponCategory::Smg => "SMGs",
+            WeaponCategory::Shotgun => "Shotguns",
+            WeaponCategory::Lmg => "LMGs",
+            WeaponCategory::MarksmanRifle => "Marksman Rifles",
+            WeaponCategory::SniperRifle => "Sniper Rifles",
+            WeaponCategory::Handgun => "Handguns",
+            WeaponCategory::Launcher => "Launchers",
+            WeaponCategory::Melee => "Melee",
+            WeaponCategory::Other => "Other",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for WeaponCategory {
+    type Err = String;
+
+    /// Parses the category names wzstats uses, e.g. `assault-rifle`, `ar` or `Sniper Rifles`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect::<String>();
+        let normalized = normalized.trim_end_matches('s');
+        let category = match normalized {
+            "ar" | "assault" | "assaultrifle" => WeaponCategory::AssaultRifle,
+            "br" | "battlerifle" => WeaponCategory::BattleRifle,
+            "smg" | "submachinegun" => WeaponCategory::Smg,
+            "shotgun" | "sg" => WeaponCategory::Shotgun,
+            "lmg" | "lightmachinegun" => WeaponCategory::Lmg,
+            "marksman" | "marksmanrifle" | "dmr" => WeaponCategory::MarksmanRifle,
+            "sniper" | "sniperrifle" => WeaponCategory::SniperRifle,
+            "handgun" | "pistol" => WeaponCategory::Handgun,
+            "launcher" => WeaponCategory::Launcher,
+            "melee" => WeaponCategory::Melee,
+            _ => return Err(format!("Unknown weapon category `{}`", s)),
+        };
+        Ok(category)
+    }
+}
+
+/// A weapon that builds can be looked up for
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CatalogWeapon {
+    /// The wzstats id, e.g. `kastov-762`
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub category: WeaponCategory,
+}
+
+impl CatalogWeapon {
+    /// Creates a weapon that is only known by its id, naming it after the id
+    pub fn from_id(id: &str) -> Self {
+        let name = id
+            .split('-')
+            .map(|word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first.to_uppercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+        Self {
+            id: id.to_string(),
+            name,
+            category: WeaponCategory::Other,
+        }
+    }
+}
+
+/// Every weapon the bot knows about
+///
+/// The catalog starts with the weapons bundled with the bot, or the ones in `WZ_WEAPONS_PATH`,
+/// and learns new weapons whenever the tier list is fetched from wzstats, so weapons added in
+/// a new season work without a new release
+pub struct WeaponCatalog {
+    weapons: RwLock<Vec<CatalogWeapon>>,
+}
+
+impl Default for WeaponCatalog {
+    fn default() -> Self {
+        let weapons = serde_json::from_str(BUNDLED_WEAPONS).expect("Invalid bundled weapons");
+        Self::from_weapons(weapons)
+    }
+}
+
+impl WeaponCatalog {
+    /// Loads the catalog from the file in `WZ_WEAPONS_PATH` if it is set, otherwise the bundled weapons
+    /// The file is a JSON list of `{ "id": .., "name": .., "category": .. }`
+    pub fn from_env() -> Self {
+        let path = match env_var("WZ_WEAPONS_PATH") {
+            Some(path) => path,
+            None => return Self::default(),
+        };
+        let weapons = std::fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(serde_json::from_str::<Vec<CatalogWeapon>>(&json)?));
+        match weapons {
+            Ok(weapons) => {
+                info!("Loaded {} weapons from {}", weapons.len(), path);
+                Self::from_weapons(weapons)
+            }
+            Err(e) => {
+                error!("Error loading weapons from {}: {:?}", path, e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn from_weapons(weapons: Vec<CatalogWeapon>) -> Self {
+        let catalog = Self {
+            weapons: RwLock::new(vec![]),
+        };
+        catalog.update(weapons);
+        catalog
+    }
+
+    /// Returns every weapon, sorted by id
+    pub fn all(&self) -> Vec<CatalogWeapon> {
+        self.weapons.read().unwrap().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.weapons.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, id: &str) -> Option<CatalogWeapon> {
+        self.weapons
+            .read()
+            .unwrap()
+            .iter()
+            .find(|w| w.id == id)
+            .cloned()
+    }
+
+    /// Returns the name of the weapon, or a name made from the id if the weapon is unknown
+    pub fn name(&self, id: &str) -> String {
+        self.get(id)
+            .unwrap_or_else(|| CatalogWeapon::from_id(id))
+            .name
+    }
+
+    /// Returns the weapons grouped by category
+    pub fn by_category(&self) -> BTreeMap<WeaponCategory, Vec<CatalogWeapon>> {
+        let mut categories: BTreeMap<WeaponCategory, Vec<CatalogWeapon>> = BTreeMap::new();
+        for weapon in self.all() {
+            categories.entry(weapon.category).or_default().push(weapon);
+        }
+        categories
+    }
+
+    /// Adds new weapons and updates known ones
+    /// An update without a category keeps the category that was known
+    /// Returns the number of weapons that were added
+    pub fn update(&self, weapons: impl IntoIterator<Item = CatalogWeapon>) -> usize {
+        let mut known = self.weapons.write().unwrap();
+        let mut added = 0;
+        for mut weapon in weapons {
+            match known.iter_mut().find(|w| w.id == weapon.id) {
+                Some(existing) => {
+                    if weapon.category == WeaponCategory::Other {
+                        weapon.category = existing.category;
+                    }
+                    *existing = weapon;
+                }
+                None => {
+                    known.push(weapon);
+                    added += 1;
+                }
+            }
+        }
+        known.sort_by(|a, b| a.id.cmp(&b.id));
+        added
+    }
+
+    /// Learns the weapons listed in a tier list
+    /// Weapons that are only ranked, without any details, are added by their id
+    /// Returns the number of weapons that were added
+    pub fn update_from_tier_list(&self, tier_list: &TierListResponse) -> usize {
+        let mut weapons = tier_list
+            .weapons
+            .iter()
+            .filter(|w| !w.id.is_empty())
+            .map(|w| CatalogWeapon {
+                id: w.id.clone(),
+                name: match w.name.is_empty() {
+                    true => CatalogWeapon::from_id(&w.id).name,
+                    false => w.name.clone(),
+                },
+                category: w.category.parse().unwrap_or_default(),
+            })
+            .collect::<Vec<_>>();
+
+        for map in TierListMap::ALL {
+            for id in tier_list.wz_stats_tier_list.get_tiers(map).get_all() {
+                if self.get(id).is_none() && !weapons.iter().any(|w| &w.id == id) {
+                    weapons.push(CatalogWeapon::from_id(id));
+                }
+            }
+        }
+
+        let added = self.update(weapons);
+        if added > 0 {
+            info!("Learned {} new weapons from wzstats", added);
+        }
+        added
+    }
+}
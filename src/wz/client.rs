@@ -0,0 +1,345 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use futures::{stream, StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serenity::prelude::TypeMapKey;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::{config::env_var, storage};
+
+use super::{
+    catalog::WeaponCatalog,
+    types::{TierListMap, TierListResponse, Weapon, WzLoadouts},
+};
+
+const CACHE_FILE: &str = "wz_cache.json";
+const TIER_LIST_KEY: &str = "tier-list";
+/// The most builds fetched from wzstats at once
+const MAX_CONCURRENT_FETCHES: usize = 4;
+/// How often the cache is saved, if anything was fetched
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A weapon on the tier list and its ranked build
+pub struct TopBuild {
+    pub weapon_id: String,
+    /// The tier the weapon is in, e.g. `META`
+    pub tier: &'static str,
+    /// None if the build could not be fetched
+    pub build: Option<Weapon>,
+}
+
+/// A cached response and when it was fetched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry<T> {
+    value: Arc<T>,
+    fetched_at: SystemTime,
+}
+
+/// The cache as it is saved to the data directory
+#[derive(Default, Serialize, Deserialize)]
+struct PersistedCache {
+    builds: HashMap<String, CacheEntry<WzLoadouts>>,
+    tier_list: Option<CacheEntry<TierListResponse>>,
+}
+
+/// A client for wzstats.gg that caches the builds of every weapon and the tier list
+///
+/// Entries older than `ttl` are stale. A stale entry is still returned right away while it is
+/// refreshed in the background, so the bot keeps answering with the last known data when
+/// wzstats is slow or down. Only a missing entry makes a command wait on wzstats
+pub struct WzStatsClient {
+    http: reqwest::Client,
+    base_url: String,
+    ttl: Duration,
+    /// Save the cache to the data directory so it survives restarts
+    persist: bool,
+    /// Set when a response was fetched since the last save
+    dirty: AtomicBool,
+    builds: RwLock<HashMap<String, CacheEntry<WzLoadouts>>>,
+    tier_list: RwLock<Option<CacheEntry<TierListResponse>>>,
+    /// The keys being refreshed in the background, so each is only refreshed once at a time
+    refreshing: Mutex<HashSet<String>>,
+    /// The known weapons, updated with every tier list that is fetched
+    catalog: WeaponCatalog,
+}
+
+impl TypeMapKey for WzStatsClient {
+    type Value = Arc<WzStatsClient>;
+}
+
+impl Default for WzStatsClient {
+    fn default() -> Self {
+        Self {
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(15))
+                .build()
+                .expect("Error building the wzstats client"),
+            base_url: "https://app.wzstats.gg".to_string(),
+            ttl: Duration::from_secs(30 * 60),
+            persist: false,
+            dirty: AtomicBool::new(false),
+            builds: RwLock::new(HashMap::new()),
+            tier_list: RwLock::new(None),
+            refreshing: Mutex::new(HashSet::new()),
+            catalog: WeaponCatalog::default(),
+        }
+    }
+}
+
+impl WzStatsClient {
+    /// Reads the client configuration from the environment
+    /// * `WZ_STATS_BASE_URL` - the wzstats API, defaults to `https://app.wzstats.gg`
+    /// * `WZ_CACHE_TTL_MINS` - minutes before a cached response is refreshed, defaults to 30
+    /// * `WZ_CACHE_PERSIST` - `true` to save the cache to the data directory
+    /// * `WZ_WEAPONS_PATH` - see [`WeaponCatalog::from_env`]
+    pub fn from_env() -> Self {
+        let mut client = Self::default().with_catalog(WeaponCatalog::from_env());
+        if let Some(base_url) = env_var("WZ_STATS_BASE_URL") {
+            client.base_url = base_url;
+        }
+        if let Some(ttl) = env_var("WZ_CACHE_TTL_MINS").and_then(|v| v.parse::<u64>().ok()) {
+            client.ttl = Duration::from_secs(ttl * 60);
+        }
+        client.with_persistence(
+            env_var("WZ_CACHE_PERSIST")
+                .map(|v| v == "true")
+                .unwrap_or(false),
+        )
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_catalog(mut self, catalog: WeaponCatalog) -> Self {
+        self.catalog = catalog;
+        self
+    }
+
+    /// Enables or disables saving the cache, loading the saved cache when enabled
+    pub fn with_persistence(mut self, persist: bool) -> Self {
+        self.persist = persist;
+        if persist {
+            let cache: PersistedCache = storage::load_json(CACHE_FILE);
+            if let Some(entry) = &cache.tier_list {
+                self.catalog.update_from_tier_list(&entry.value);
+            }
+            self.builds = RwLock::new(cache.builds);
+            self.tier_list = RwLock::new(cache.tier_list);
+        }
+        self
+    }
+
+    /// Returns the weapons builds can be looked up for
+    pub fn catalog(&self) -> &WeaponCatalog {
+        &self.catalog
+    }
+
+    /// Returns the builds of a weapon
+    pub async fn builds(
+        self: &Arc<Self>,
+        weapon_id: &str,
+    ) -> Result<Arc<WzLoadouts>, anyhow::Error> {
+        let cached = self.builds.read().unwrap().get(weapon_id).cloned();
+        if let Some(entry) = cached {
+            if self.is_stale(&entry) {
+                let client = self.clone();
+                let weapon_id = weapon_id.to_string();
+                self.revalidate(weapon_id.clone(), async move {
+                    client.fetch_builds(&weapon_id).await.map(|_| ())
+                });
+            }
+            return Ok(entry.value);
+        }
+        self.fetch_builds(weapon_id).await
+    }
+
+    /// Returns the wzstats tier list
+    pub async fn tier_list(self: &Arc<Self>) -> Result<Arc<TierListResponse>, anyhow::Error> {
+        let cached = self.tier_list.read().unwrap().clone();
+        if let Some(entry) = cached {
+            if self.is_stale(&entry) {
+                let client = self.clone();
+                self.revalidate(TIER_LIST_KEY.to_string(), async move {
+                    client.fetch_tier_list().await.map(|_| ())
+                });
+            }
+            return Ok(entry.value);
+        }
+        self.fetch_tier_list().await
+    }
+
+    /// Returns the ranked builds of the best `n` weapons on the tier list of the map, best first
+    /// Builds are fetched a few at a time, and a build that can't be fetched doesn't fail the others
+    pub async fn top_builds(
+        self: &Arc<Self>,
+        map: TierListMap,
+        n: usize,
+    ) -> Result<Vec<TopBuild>, anyhow::Error> {
+        let tier_list = self.tier_list().await?;
+        let top = tier_list.wz_stats_tier_list.get_tiers(map).get_top(n);
+        let fetches = top
+            .into_iter()
+            .map(|(tier, weapon_id)| self.clone().top_build(tier, weapon_id))
+            .collect::<Vec<_>>();
+        let builds = stream::iter(fetches)
+            .buffered(MAX_CONCURRENT_FETCHES)
+            .collect()
+            .await;
+        Ok(builds)
+    }
+
+    async fn top_build(self: Arc<Self>, tier: &'static str, weapon_id: String) -> TopBuild {
+        let build = match self.builds(&weapon_id).await {
+            Ok(loadouts) => loadouts.get_ranked_build().cloned(),
+            Err(e) => {
+                error!("{:?}", e);
+                None
+            }
+        };
+        TopBuild {
+            weapon_id,
+            tier,
+            build,
+        }
+    }
+
+    /// Fetches the tier list and the builds of every cached weapon again
+    /// Returns the number of responses that were refreshed
+    pub async fn refresh(&self) -> Result<usize, anyhow::Error> {
+        self.fetch_tier_list().await?;
+        let mut refreshed = 1;
+        let weapon_ids = self
+            .builds
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        for weapon_id in weapon_ids {
+            match self.fetch_builds(&weapon_id).await {
+                Ok(_) => refreshed += 1,
+                Err(e) => error!("Error refreshing builds for {}: {:?}", weapon_id, e),
+            }
+        }
+        Ok(refreshed)
+    }
+
+    fn is_stale<T>(&self, entry: &CacheEntry<T>) -> bool {
+        entry
+            .fetched_at
+            .elapsed()
+            .map(|age| age >= self.ttl)
+            .unwrap_or(true)
+    }
+
+    /// Runs the refresh in the background unless the key is already being refreshed
+    fn revalidate<F>(self: &Arc<Self>, key: String, refresh: F)
+    where
+        F: std::future::Future<Output = Result<(), anyhow::Error>> + Send + 'static,
+    {
+        if !self.refreshing.lock().unwrap().insert(key.clone()) {
+            return;
+        }
+        let client = self.clone();
+        tokio::spawn(async move {
+            info!("Refreshing stale wzstats {}", key);
+            if let Err(e) = refresh.await {
+                error!(
+                    "Error refreshing wzstats {}, keeping the stale data: {:?}",
+                    key, e
+                );
+            }
+            client.refreshing.lock().unwrap().remove(&key);
+        });
+    }
+
+    /// Fetches the builds of a weapon from wzstats even if they are cached, updating the cache
+    pub async fn fetch_builds(&self, weapon_id: &str) -> Result<Arc<WzLoadouts>, anyhow::Error> {
+        let loadouts: WzLoadouts = self
+            .get(&format!(
+                "/wz2/weapons/builds/wzstats/with-attachments/weapon/{}/?game=wz2",
+                weapon_id
+            ))
+            .await
+            .context(format!("Failed to retrieve builds for {}", weapon_id))?;
+        let value = Arc::new(loadouts);
+        self.builds.write().unwrap().insert(
+            weapon_id.to_string(),
+            CacheEntry {
+                value: value.clone(),
+                fetched_at: SystemTime::now(),
+            },
+        );
+        self.dirty.store(true, Ordering::SeqCst);
+        Ok(value)
+    }
+
+    /// Fetches the tier list from wzstats even if it is cached, updating the cache
+    pub async fn fetch_tier_list(&self) -> Result<Arc<TierListResponse>, anyhow::Error> {
+        let tier_list: TierListResponse = self
+            .get("/wz2/weapons/meta/weapons-and-tier-lists/?streamerProfileId=wzstats")
+            .await
+            .context("Failed to retrieve the tier list")?;
+        self.catalog.update_from_tier_list(&tier_list);
+        let value = Arc::new(tier_list);
+        *self.tier_list.write().unwrap() = Some(CacheEntry {
+            value: value.clone(),
+            fetched_at: SystemTime::now(),
+        });
+        self.dirty.store(true, Ordering::SeqCst);
+        Ok(value)
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, anyhow::Error> {
+        let res = self
+            .http
+            .get(format!("{}{}", self.base_url, path))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(res.json::<T>().await?)
+    }
+
+    /// Starts a task that saves the cache every minute, if anything was fetched
+    /// Saving is batched so fetching never waits on the disk
+    pub fn spawn_saver(self: Arc<Self>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SAVE_INTERVAL);
+            loop {
+                interval.tick().await;
+                self.save().await;
+            }
+        })
+    }
+
+    /// Saves the cache if anything was fetched since the last save
+    pub async fn save(&self) {
+        if !self.persist || !self.dirty.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        let cache = PersistedCache {
+            builds: self.builds.read().unwrap().clone(),
+            tier_list: self.tier_list.read().unwrap().clone(),
+        };
+        if let Err(e) = storage::save_json_async(CACHE_FILE, cache).await {
+            error!("Error saving the wzstats cache: {:?}", e);
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+    }
+}
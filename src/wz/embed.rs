@@ -0,0 +1,181 @@
+use serenity::builder::CreateEmbed;
+
+use super::{
+    catalog::WeaponCatalog,
+    client::TopBuild,
+    types::{TierListMap, Tiers, Weapon},
+};
+
+/// Discord rejects fields with longer values
+const MAX_FIELD_LEN: usize = 1024;
+const MAX_DESCRIPTION_LEN: usize = 4096;
+/// The most weapons listed on one page of a tier list
+const WEAPONS_PER_PAGE: usize = 15;
+/// The colour of the embed for each tier, best first
+const TIER_COLOURS: [(&str, u32); 5] = [
+    ("META", 0xE67E22),
+    ("A", 0x2ECC71),
+    ("B", 0x3498DB),
+    ("C", 0x9B59B6),
+    ("D", 0x95A5A6),
+];
+
+/// Fills the embed with a build: its playstyle, tier and author, every attachment in slot
+/// order with its tuning, and the pros and cons
+/// * `name` - the display name of the weapon
+/// * `tier` - the tier the weapon is in on the tier list, if it is ranked
+pub fn build_embed<'a>(
+    embed: &'a mut CreateEmbed,
+    weapon: &Weapon,
+    name: &str,
+    tier: Option<&str>,
+) -> &'a mut CreateEmbed {
+    match weapon.title.is_empty() {
+        true => embed.title(name),
+        false => embed.title(format!("{} - {}", name, weapon.title)),
+    };
+    if !weapon.description.is_empty() {
+        embed.description(truncate(&weapon.description, MAX_DESCRIPTION_LEN));
+    }
+    if let Some(image) = weapon
+        .external_source_image
+        .as_ref()
+        .filter(|i| !i.is_empty())
+    {
+        embed.thumbnail(image);
+    }
+    if !weapon.author_display_name.is_empty() {
+        embed.author(|a| a.name(&weapon.author_display_name));
+    }
+    if let Some(colour) = tier.and_then(tier_colour) {
+        embed.colour(colour);
+    }
+
+    if !weapon.playstyle.is_empty() {
+        embed.field("Playstyle", weapon.get_playstyle_name(), true);
+    }
+    embed.field("Tier", tier.unwrap_or("Unranked"), true);
+    if weapon.is_warzone_ranked_build {
+        embed.field("Mode", "Ranked", true);
+    }
+
+    for (slot, attachment) in weapon.get_loadout_attachments() {
+        let mut value = attachment.name.clone();
+        if let Some((hz, vt)) = attachment
+            .get_tuning()
+            .filter(|&(hz, vt)| hz != 0.0 || vt != 0.0)
+        {
+            value.push_str(&format!(
+                "\n:left_right_arrow: {} :arrow_up_down: {}",
+                hz, vt
+            ));
+        }
+        embed.field(slot, truncate(&value, MAX_FIELD_LEN), true);
+    }
+
+    for (title, points) in [("Pros", &weapon.pros), ("Cons", &weapon.cons)] {
+        if points.is_empty() {
+            continue;
+        }
+        let value = points
+            .iter()
+            .map(|p| format!("• {}", p))
+            .collect::<Vec<_>>()
+            .join("\n");
+        embed.field(title, truncate(&value, MAX_FIELD_LEN), false);
+    }
+    embed
+}
+
+/// Returns a page for every tier of the tier list that has weapons, best first
+/// Tiers with more weapons than fit on a page are split over several pages
+pub fn tier_list_pages(
+    tiers: &Tiers,
+    map: TierListMap,
+    catalog: &WeaponCatalog,
+) -> Vec<CreateEmbed> {
+    let mut pages = vec![];
+    for (tier, weapons) in tiers.get_tiers() {
+        let chunks = weapons.chunks(WEAPONS_PER_PAGE).collect::<Vec<_>>();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut embed = CreateEmbed::default();
+            match chunks.len() {
+                1 => embed.title(format!("{} Tier List - {}", map, tier)),
+                _ => embed.title(format!(
+                    "{} Tier List - {} ({}/{})",
+                    map,
+                    tier,
+                    i + 1,
+                    chunks.len()
+                )),
+            };
+            let start = i * WEAPONS_PER_PAGE;
+            embed.description(
+                chunk
+                    .iter()
+                    .enumerate()
+                    .map(|(j, id)| format!("{}. {} (`{}`)", start + j + 1, catalog.name(id), id))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+            if let Some(colour) = tier_colour(tier) {
+                embed.colour(colour);
+            }
+            pages.push(embed);
+        }
+    }
+    pages
+}
+
+/// Returns the top builds as a compact ranked list, one line per weapon with its tier and attachments
+pub fn top_builds_text(map: TierListMap, builds: &[TopBuild], catalog: &WeaponCatalog) -> String {
+    if builds.is_empty() {
+        return format!("The {} tier list is empty", map);
+    }
+    let mut text = format!("**Top {} {} Builds**\n", builds.len(), map);
+    for (i, top) in builds.iter().enumerate() {
+        let name = catalog.name(&top.weapon_id);
+        let line = match &top.build {
+            Some(build) => format!(
+                "`#{}` **{}** {} - {}: {}",
+                i + 1,
+                top.tier,
+                name,
+                build.get_playstyle_name(),
+                build
+                    .get_loadout_attachments()
+                    .iter()
+                    .map(|(_, a)| a.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            None => format!(
+                "`#{}` **{}** {} - build unavailable right now",
+                i + 1,
+                top.tier,
+                name
+            ),
+        };
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text
+}
+
+/// Returns the colour used for a tier, e.g. `META`
+pub fn tier_colour(tier: &str) -> Option<u32> {
+    TIER_COLOURS
+        .iter()
+        .find(|(t, _)| *t == tier)
+        .map(|(_, colour)| *colour)
+}
+
+/// Cuts the text to at most `max` characters, ending it with `…` when it is cut
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut truncated = text.chars().take(max - 1).collect::<String>();
+    truncated.push('…');
+    truncated
+}
@@ -0,0 +1,325 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serenity::{http::Http, model::prelude::ChannelId, prelude::TypeMapKey};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::{bot, config::env_var, storage};
+
+use super::{
+    catalog::WeaponCatalog,
+    client::WzStatsClient,
+    types::{TierListMap, TierListResponse},
+};
+
+const STATE_FILE: &str = "wz_meta.json";
+
+/// The tier lists, and the ranked builds of the META weapons, at one point in time
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetaSnapshot {
+    /// The tier of every weapon on each tier list
+    pub tiers: BTreeMap<TierListMap, BTreeMap<String, String>>,
+    /// The id of the ranked build of every META weapon
+    pub ranked_builds: BTreeMap<String, String>,
+}
+
+impl MetaSnapshot {
+    /// Takes the tiers of every tier list, without any ranked builds
+    pub fn from_tier_list(tier_list: &TierListResponse) -> Self {
+        let tiers = TierListMap::ALL
+            .into_iter()
+            .map(|map| {
+                let weapons = tier_list
+                    .wz_stats_tier_list
+                    .get_tiers(map)
+                    .get_tiers()
+                    .into_iter()
+                    .flat_map(|(tier, weapons)| {
+                        weapons
+                            .iter()
+                            .map(move |w| (w.to_owned(), tier.to_string()))
+                    })
+                    .collect();
+                (map, weapons)
+            })
+            .collect();
+        Self {
+            tiers,
+            ranked_builds: BTreeMap::new(),
+        }
+    }
+
+    /// Returns every weapon that is META on any tier list
+    pub fn meta_weapons(&self) -> BTreeSet<String> {
+        self.tiers
+            .values()
+            .flat_map(|weapons| weapons.iter())
+            .filter(|(_, tier)| *tier == "META")
+            .map(|(weapon_id, _)| weapon_id.to_owned())
+            .collect()
+    }
+}
+
+/// A change in the meta between two snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetaChange {
+    /// A weapon moved into the META tier
+    NewMeta {
+        map: TierListMap,
+        weapon_id: String,
+        from: Option<String>,
+    },
+    /// A weapon moved between the other tiers, or on or off the tier list
+    Moved {
+        map: TierListMap,
+        weapon_id: String,
+        from: Option<String>,
+        to: Option<String>,
+    },
+    /// A META weapon has a different ranked build
+    NewRankedBuild { weapon_id: String },
+}
+
+/// Returns what changed from the old snapshot to the new one
+/// Tier lists that were empty in the old snapshot are skipped, so a new list doesn't report every weapon
+pub fn diff(old: &MetaSnapshot, new: &MetaSnapshot) -> Vec<MetaChange> {
+    let mut changes = vec![];
+    for map in TierListMap::ALL {
+        let (old_tiers, new_tiers) = match (old.tiers.get(&map), new.tiers.get(&map)) {
+            (Some(old_tiers), Some(new_tiers)) if !old_tiers.is_empty() => (old_tiers, new_tiers),
+            _ => continue,
+        };
+        let weapons = old_tiers
+            .keys()
+            .chain(new_tiers.keys())
+            .collect::<BTreeSet<_>>();
+        for weapon_id in weapons {
+            let from = old_tiers.get(weapon_id).cloned();
+            let to = new_tiers.get(weapon_id).cloned();
+            if from == to {
+                continue;
+            }
+            let weapon_id = weapon_id.to_owned();
+            changes.push(match to.as_deref() {
+                Some("META") => MetaChange::NewMeta {
+                    map,
+                    weapon_id,
+                    from,
+                },
+                _ => MetaChange::Moved {
+                    map,
+                    weapon_id,
+                    from,
+                    to,
+                },
+            });
+        }
+    }
+    for (weapon_id, build_id) in &new.ranked_builds {
+        if matches!(old.ranked_builds.get(weapon_id), Some(old_id) if old_id != build_id) {
+            changes.push(MetaChange::NewRankedBuild {
+                weapon_id: weapon_id.to_owned(),
+            });
+        }
+    }
+    changes
+}
+
+/// Writes the changes as a message, grouped into new META weapons, tier changes and new builds
+pub fn summary(changes: &[MetaChange], catalog: &WeaponCatalog) -> String {
+    let tier = |tier: &Option<String>| tier.clone().unwrap_or("unranked".to_string());
+    let (mut new_meta, mut moved, mut builds) = (vec![], vec![], vec![]);
+    for change in changes {
+        match change {
+            MetaChange::NewMeta {
+                map,
+                weapon_id,
+                from,
+            } => new_meta.push(format!(
+                "- {} ({}), was {}",
+                catalog.name(weapon_id),
+                map,
+                tier(from)
+            )),
+            MetaChange::Moved {
+                map,
+                weapon_id,
+                from,
+                to,
+            } => moved.push(format!(
+                "- {} ({}): {} -> {}",
+                catalog.name(weapon_id),
+                map,
+                tier(from),
+                tier(to)
+            )),
+            MetaChange::NewRankedBuild { weapon_id } => builds.push(format!(
+                "- {}, see `$wz ranked-build {}`",
+                catalog.name(weapon_id),
+                weapon_id
+            )),
+        }
+    }
+
+    let mut summary = "**The Warzone meta changed**".to_string();
+    for (title, lines) in [
+        ("New META", new_meta),
+        ("Tier changes", moved),
+        ("New ranked builds", builds),
+    ] {
+        if !lines.is_empty() {
+            summary.push_str(&format!("\n**{}**\n{}", title, lines.join("\n")));
+        }
+    }
+    summary
+}
+
+/// The channels subscribed to meta changes and the last snapshot, as saved to the data directory
+#[derive(Default, Serialize, Deserialize)]
+struct MetaWatchState {
+    subscribers: BTreeSet<ChannelId>,
+    snapshot: Option<MetaSnapshot>,
+}
+
+/// Checks the wzstats tier lists every `interval` and tells the subscribed channels what changed
+pub struct MetaWatch {
+    interval: Duration,
+    /// Save the subscribers and the last snapshot to the data directory
+    persist: bool,
+    state: Mutex<MetaWatchState>,
+}
+
+impl TypeMapKey for MetaWatch {
+    type Value = Arc<MetaWatch>;
+}
+
+impl Default for MetaWatch {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60 * 60),
+            persist: false,
+            state: Mutex::new(MetaWatchState::default()),
+        }
+    }
+}
+
+impl MetaWatch {
+    /// Loads the subscribers and the last snapshot, and reads the configuration from the environment
+    /// * `WZ_META_CHECK_MINS` - minutes between checks of the tier lists, defaults to 60
+    pub fn from_env() -> Self {
+        let mut watch = Self {
+            persist: true,
+            state: Mutex::new(storage::load_json(STATE_FILE)),
+            ..Default::default()
+        };
+        if let Some(mins) = env_var("WZ_META_CHECK_MINS").and_then(|v| v.parse::<u64>().ok()) {
+            watch.interval = Duration::from_secs(mins.max(1) * 60);
+        }
+        watch
+    }
+
+    pub fn is_subscribed(&self, channel_id: &ChannelId) -> bool {
+        self.state.lock().unwrap().subscribers.contains(channel_id)
+    }
+
+    /// Subscribes or unsubscribes a channel
+    /// Returns false if the channel already was or wasn't subscribed
+    pub fn set_subscribed(&self, channel_id: ChannelId, subscribed: bool) -> bool {
+        let changed = {
+            let mut state = self.state.lock().unwrap();
+            match subscribed {
+                true => state.subscribers.insert(channel_id),
+                false => state.subscribers.remove(&channel_id),
+            }
+        };
+        if changed {
+            self.save();
+        }
+        changed
+    }
+
+    /// Takes a new snapshot and returns what changed since the last one
+    /// The first snapshot has nothing to compare to, so it never has changes
+    pub async fn check(&self, client: &WzStatsClient) -> Result<Vec<MetaChange>, anyhow::Error> {
+        let tier_list = client.fetch_tier_list().await?;
+        let mut snapshot = MetaSnapshot::from_tier_list(&tier_list);
+        let previous = self.state.lock().unwrap().snapshot.clone();
+
+        for weapon_id in snapshot.meta_weapons() {
+            match client.fetch_builds(&weapon_id).await {
+                Ok(loadouts) => {
+                    if let Some(build) = loadouts.get_ranked_build() {
+                        snapshot.ranked_builds.insert(weapon_id, build.id.clone());
+                    }
+                }
+                Err(e) => {
+                    error!("{:?}", e);
+                    // Keep the last known build so a failed fetch isn't reported as a change
+                    let known = previous
+                        .as_ref()
+                        .and_then(|p| p.ranked_builds.get(&weapon_id).cloned());
+                    if let Some(build_id) = known {
+                        snapshot.ranked_builds.insert(weapon_id, build_id);
+                    }
+                }
+            }
+        }
+
+        let changes = match &previous {
+            Some(previous) => diff(previous, &snapshot),
+            None => vec![],
+        };
+        self.state.lock().unwrap().snapshot = Some(snapshot);
+        self.save();
+        Ok(changes)
+    }
+
+    /// Starts a task that checks for meta changes every `interval` while any channel is subscribed
+    pub fn spawn_watcher(
+        self: Arc<Self>,
+        client: Arc<WzStatsClient>,
+        http: Arc<Http>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(self.interval).await;
+                let subscribers = self.state.lock().unwrap().subscribers.clone();
+                if subscribers.is_empty() {
+                    continue;
+                }
+                let changes = match self.check(&client).await {
+                    Ok(changes) => changes,
+                    Err(e) => {
+                        error!("Error checking for meta changes: {:?}", e);
+                        continue;
+                    }
+                };
+                if changes.is_empty() {
+                    continue;
+                }
+                info!("The Warzone meta changed: {} changes", changes.len());
+                let summary = summary(&changes, client.catalog());
+                for channel_id in subscribers {
+                    if let Err(e) = bot::send_text_in_chunks(&http, channel_id, &summary).await {
+                        error!("Error posting meta changes to {}: {:?}", channel_id, e);
+                    }
+                }
+            }
+        })
+    }
+
+    fn save(&self) {
+        if !self.persist {
+            return;
+        }
+        let state = self.state.lock().unwrap();
+        if let Err(e) = storage::save_json(STATE_FILE, &*state) {
+            error!("Error saving the meta watch: {:?}", e);
+        }
+    }
+}
@@ -1,3 +1,9 @@
+pub mod card;
+pub mod catalog;
+pub mod client;
+pub mod embed;
+pub mod meta_watch;
+pub mod resolver;
 pub mod types;
 mod wz_commands;
 
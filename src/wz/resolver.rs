@@ -0,0 +1,150 @@
+use std::fmt::{Display, Formatter};
+
+use super::catalog::{CatalogWeapon, WeaponCatalog};
+
+/// Names the community uses for weapons, mapped to their wzstats ids
+const NICKNAMES: [(&str, &str); 20] = [
+    ("ak", "kastov-762"),
+    ("ak47", "kastov-762"),
+    ("akm", "kastov-762"),
+    ("74u", "kastov-74u"),
+    ("aks74u", "kastov-74u"),
+    ("ak545", "kastov-545"),
+    ("mp5", "lachmann-sub"),
+    ("ump", "iso-45"),
+    ("ump45", "iso-45"),
+    ("hemlock", "iso-hemlock"),
+    ("hdr", "fjx-imperium"),
+    ("fjx", "fjx-imperium"),
+    ("mk2", "lockwood-mk2"),
+    ("raal", "raal-mg"),
+    ("m4a1", "m4"),
+    ("m13", "m13b"),
+    ("hcr", "hcr-56"),
+    ("ebr", "ebr-14"),
+    ("kv", "kv-broadside"),
+    ("icarus", "556-icarus"),
+];
+/// Matches scoring at least this are used without asking
+const MATCH_SCORE: f64 = 0.8;
+/// The best match also has to be this far ahead of the next one to be used without asking
+const MATCH_MARGIN: f64 = 0.1;
+/// Weapons scoring at least this are suggested
+const SUGGEST_SCORE: f64 = 0.5;
+const MAX_SUGGESTIONS: usize = 5;
+
+/// The weapon a search resolved to
+#[derive(Debug, Clone, PartialEq)]
+pub enum WeaponMatch {
+    /// The wzstats id of the weapon
+    Found(String),
+    /// The weapons that were close, best first
+    DidYouMean(Vec<CatalogWeapon>),
+    NotFound,
+}
+
+impl Display for WeaponMatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WeaponMatch::Found(id) => write!(f, "{}", id),
+            WeaponMatch::DidYouMean(weapons) => write!(
+                f,
+                "No weapon matches that exactly. Did you mean {}?",
+                weapons
+                    .iter()
+                    .map(|weapon| format!("`{}` ({})", weapon.id, weapon.name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            WeaponMatch::NotFound => write!(
+                f,
+                "No weapon found. Use `$wz weapon-ids` for a full list of weapons"
+            ),
+        }
+    }
+}
+
+/// Lowercases and strips everything but letters and digits, so `Kastov 762` matches `kastov-762`
+fn normalize(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Scores how well the query matches a name, from 0 to 1
+fn score(query: &str, name: &str) -> f64 {
+    let similarity = strsim::normalized_damerau_levenshtein(query, name);
+    // `lachmann` should find every Lachmann even though the names are much longer
+    if query.len() >= 3 && name.starts_with(query) {
+        return similarity.max(MATCH_SCORE + 0.05);
+    }
+    similarity
+}
+
+/// Finds the weapon in the catalog matching the query, by its id, its name or a nickname
+/// Exact matches are found right away, otherwise the closest weapons are found with fuzzy matching
+pub fn resolve_weapon(catalog: &WeaponCatalog, query: &str) -> WeaponMatch {
+    let query = normalize(query);
+    if query.is_empty() {
+        return WeaponMatch::NotFound;
+    }
+
+    // Every name a weapon goes by, with its id
+    let weapons = catalog.all();
+    let mut names = weapons
+        .iter()
+        .flat_map(|w| {
+            [
+                (w.id.as_str(), normalize(&w.id)),
+                (w.id.as_str(), normalize(&w.name)),
+            ]
+        })
+        .collect::<Vec<_>>();
+    names.extend(
+        NICKNAMES
+            .iter()
+            .filter(|(_, id)| weapons.iter().any(|w| w.id == *id))
+            .map(|(nickname, id)| (*id, normalize(nickname))),
+    );
+
+    if let Some((id, _)) = names.iter().find(|(_, name)| *name == query) {
+        return WeaponMatch::Found(id.to_string());
+    }
+
+    // The best score of every weapon, best first
+    let mut scores: Vec<(&str, f64)> = vec![];
+    for (id, name) in &names {
+        let score = score(&query, name);
+        match scores.iter_mut().find(|(scored, _)| scored == id) {
+            Some((_, best)) => *best = best.max(score),
+            None => scores.push((id, score)),
+        }
+    }
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(b.0)));
+
+    match scores.as_slice() {
+        [(id, best), rest @ ..]
+            if *best >= MATCH_SCORE
+                && rest
+                    .first()
+                    .map(|(_, next)| best - next >= MATCH_MARGIN)
+                    .unwrap_or(true) =>
+        {
+            WeaponMatch::Found(id.to_string())
+        }
+        _ => {
+            let suggestions = scores
+                .iter()
+                .filter(|(_, score)| *score >= SUGGEST_SCORE)
+                .take(MAX_SUGGESTIONS)
+                .filter_map(|(id, _)| weapons.iter().find(|w| w.id == *id).cloned())
+                .collect::<Vec<_>>();
+            if suggestions.is_empty() {
+                WeaponMatch::NotFound
+            } else {
+                WeaponMatch::DidYouMean(suggestions)
+            }
+        }
+    }
+}
@@ -1,60 +1,11 @@
 use std::{
-    collections::HashMap,
+    collections::BTreeMap,
     fmt::{Display, Formatter},
+    str::FromStr,
 };
 
-use phf::phf_map;
-use serde::{Deserialize, Serialize};
-
-/// Current as of Warzone Season 5 (9/24/2023)
-pub static WZ_WEAPONS: phf::Map<&str, WzWeaponName> = phf_map! {
-    "50gs" => WzWeaponName::FiftyGS,
-    "556-icarus" => WzWeaponName::FiveFiveSixIcarus,
-    "9mm-daemon" => WzWeaponName::NineMilliMeterDaemon,
-    "bas-p" => WzWeaponName::BasP,
-    "basilisk" => WzWeaponName::Basilisk,
-    "bryson-800" => WzWeaponName::Bryson800,
-    "bryson-890" => WzWeaponName::Bryson890,
-    "carrack-300" => WzWeaponName::Carrack300,
-    "chimera" => WzWeaponName::Chimera,
-    "cronen-squall" => WzWeaponName::CronenSquall,
-    "ebr-14" => WzWeaponName::Ebr14,
-    "expedite-12" => WzWeaponName::Expedite12,
-    "fennec-45" => WzWeaponName::Fennec45,
-    "fjx-imperium" => WzWeaponName::FjxImperium,
-    "fr-avancer" => WzWeaponName::FrAvancer,
-    "fss-hurricane" => WzWeaponName::FssHurricane,
-    "ftac-recon" => WzWeaponName::FtacRecon,
-    "ftac-siege" => WzWeaponName::FtacSiege,
-    "gs-magma" => WzWeaponName::GsMagma,
-    "hcr-56" => WzWeaponName::Hcr56,
-    "iso-45" => WzWeaponName::Iso45,
-    "iso-9mm" => WzWeaponName::Iso9mm,
-    "iso-hemlock" => WzWeaponName::IsoHemlock,
-    "kastov-545" => WzWeaponName::Kastov545,
-    "kastov-74u" => WzWeaponName::Kastov74u,
-    "kastov-762" => WzWeaponName::Kastov762,
-    "kv-broadside" => WzWeaponName::KvBroadside,
-    "la-b-330" => WzWeaponName::LaB330,
-    "lachmann-556" => WzWeaponName::Lachmann556,
-    "lachmann-762" => WzWeaponName::Lachmann762,
-    "lachmann-shroud" => WzWeaponName::LachmannShroud,
-    "lachmann-sub" => WzWeaponName::LachmannSub,
-    "lm-s" => WzWeaponName::LmS,
-    "lockwood-300" => WzWeaponName::Lockwood300,
-    "lockwood-mk2" => WzWeaponName::LockwoodMk2,
-    "m13b" => WzWeaponName::M13b,
-    "m13c" => WzWeaponName::M13c,
-    "m16" => WzWeaponName::M16,
-    "m4" => WzWeaponName::M4,
-    "mcpr-300" => WzWeaponName::Mcpr300,
-    "minibak" => WzWeaponName::Minibak,
-    "mx-guardian" => WzWeaponName::MxGuardian,
-    "mx9" => WzWeaponName::Mx9,
-    "p890" => WzWeaponName::P890,
-    "pdsw-528" => WzWeaponName::Pdsw528,
-    "raal-mg" => WzWeaponName::RaalMg
-};
+use serde::{Deserialize, Deserializer, Serialize};
+use tracing::warn;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct VerticalTuning {
@@ -66,48 +17,190 @@ pub struct HorizontalTuning {
     pub value: f64,
 }
 
+/// The slots a weapon can have attachments in, in the order they are shown
+/// Slots are named as wzstats names them, e.g. `rearGrip`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum AttachmentSlot {
+    Muzzle,
+    Barrel,
+    Guard,
+    Laser,
+    Optic,
+    Rail,
+    Stock,
+    Comb,
+    Underbarrel,
+    Ammunition,
+    Bolt,
+    TriggerAction,
+    RearGrip,
+    Magazine,
+    /// A slot wzstats added that the bot does not know yet, shown after the known ones
+    Other(String),
+}
+
+impl AttachmentSlot {
+    /// Every known slot, in the order they are shown
+    pub const ALL: [AttachmentSlot; 14] = [
+        AttachmentSlot::Muzzle,
+        AttachmentSlot::Barrel,
+        AttachmentSlot::Guard,
+        AttachmentSlot::Laser,
+        AttachmentSlot::Optic,
+        AttachmentSlot::Rail,
+        AttachmentSlot::Stock,
+        AttachmentSlot::Comb,
+        AttachmentSlot::Underbarrel,
+        AttachmentSlot::Ammunition,
+        AttachmentSlot::Bolt,
+        AttachmentSlot::TriggerAction,
+        AttachmentSlot::RearGrip,
+        AttachmentSlot::Magazine,
+    ];
+
+    /// Returns the name wzstats uses for the slot
+    pub fn key(&self) -> &str {
+        match self {
+            AttachmentSlot::Muzzle => "muzzle",
+            AttachmentSlot::Barrel => "barrel",
+            AttachmentSlot::Guard => "guard",
+            AttachmentSlot::Laser => "laser",
+            AttachmentSlot::Optic => "optic",
+            AttachmentSlot::Rail => "rail",
+            AttachmentSlot::Stock => "stock",
+            AttachmentSlot::Comb => "comb",
+            AttachmentSlot::Underbarrel => "underbarrel",
+            AttachmentSlot::Ammunition => "ammunition",
+            AttachmentSlot::Bolt => "bolt",
+            AttachmentSlot::TriggerAction => "triggerAction",
+            AttachmentSlot::RearGrip => "rearGrip",
+            AttachmentSlot::Magazine => "magazine",
+            AttachmentSlot::Other(key) => key,
+        }
+    }
+}
+
+impl From<String> for AttachmentSlot {
+    fn from(key: String) -> Self {
+        AttachmentSlot::ALL
+            .into_iter()
+            .find(|slot| slot.key() == key)
+            .unwrap_or(AttachmentSlot::Other(key))
+    }
+}
+
+impl From<AttachmentSlot> for String {
+    fn from(slot: AttachmentSlot) -> Self {
+        slot.key().to_string()
+    }
+}
+
+impl Display for AttachmentSlot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AttachmentSlot::Muzzle => "Muzzle",
+            AttachmentSlot::Barrel => "Barrel",
+            AttachmentSlot::Guard => "Guard",
+            AttachmentSlot::Laser => "Laser",
+            AttachmentSlot::Optic => "Optic",
+            AttachmentSlot::Rail => "Rail",
+            AttachmentSlot::Stock => "Stock",
+            AttachmentSlot::Comb => "Comb",
+            AttachmentSlot::Underbarrel => "Underbarrel",
+            AttachmentSlot::Ammunition => "Ammunition",
+            AttachmentSlot::Bolt => "Bolt",
+            AttachmentSlot::TriggerAction => "Trigger Action",
+            AttachmentSlot::RearGrip => "Rear Grip",
+            AttachmentSlot::Magazine => "Magazine",
+            // Split camelCase names into words, e.g. `underGrip` is shown as `Under Grip`
+            AttachmentSlot::Other(key) => {
+                let mut name = String::new();
+                for (i, c) in key.chars().enumerate() {
+                    if i == 0 {
+                        name.extend(c.to_uppercase());
+                    } else if c.is_uppercase() {
+                        name.push(' ');
+                        name.push(c);
+                    } else {
+                        name.push(c);
+                    }
+                }
+                return write!(f, "{}", name);
+            }
+        };
+        write!(f, "{}", name)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Attachment {
     pub attachment_id: String,
     pub vertical_tuning: serde_json::Value,
     pub horizontal_tuning: serde_json::Value,
-    pub slot: String,
+    pub slot: AttachmentSlot,
     pub name: String,
 }
 impl Display for Attachment {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let mut tunings = String::new();
-        let (hz, vt) = self.get_tuning();
-        tunings.push_str(&format!(":left_right_arrow: {} ", hz));
-        tunings.push_str(&format!(":arrow_up_down: {}", vt));
-        write!(f, "{}: {}", self.name, tunings)
+        match self.get_tuning() {
+            Some((hz, vt)) => write!(
+                f,
+                "{}: :left_right_arrow: {} :arrow_up_down: {}",
+                self.name, hz, vt
+            ),
+            None => write!(f, "{}", self.name),
+        }
     }
 }
 impl Attachment {
-    pub fn get_tuning(&self) -> (f64, f64) {
+    /// Returns the horizontal and vertical tuning of the attachment
+    /// Missing tunings are 0, and None is returned if wzstats sent a tuning the bot can't read
+    pub fn get_tuning(&self) -> Option<(f64, f64)> {
         let mut hz = HorizontalTuning { value: 0.0 };
         let mut vt = VerticalTuning { value: 0.0 };
 
-        if self.horizontal_tuning.is_f64() {
-            hz.value = self.horizontal_tuning.as_f64().unwrap();
+        if let Some(value) = self.horizontal_tuning.as_f64() {
+            hz.value = value;
         } else if self.horizontal_tuning.is_object() {
-            hz = serde_json::from_value(self.horizontal_tuning.clone())
-                .expect("Error parsing horizontal tuning");
+            hz = serde_json::from_value(self.horizontal_tuning.clone()).ok()?;
         }
 
-        if self.vertical_tuning.is_f64() {
-            vt.value = self.vertical_tuning.as_f64().unwrap();
+        if let Some(value) = self.vertical_tuning.as_f64() {
+            vt.value = value;
         } else if self.vertical_tuning.is_object() {
-            vt = serde_json::from_value(self.vertical_tuning.clone())
-                .expect("Error parsing vertical tuning");
+            vt = serde_json::from_value(self.vertical_tuning.clone()).ok()?;
         }
 
-        (hz.value, vt.value)
+        Some((hz.value, vt.value))
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// Reads the attachments of a build from the fields wzstats names after their slot
+/// Fields that are not attachments are ignored, and attachments that can't be read are skipped
+fn deserialize_attachments<'de, D>(
+    deserializer: D,
+) -> Result<BTreeMap<AttachmentSlot, Attachment>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let fields = BTreeMap::<String, serde_json::Value>::deserialize(deserializer)?;
+    let attachments = fields
+        .into_iter()
+        .filter(|(_, value)| value.get("slot").is_some())
+        .filter_map(|(key, value)| match serde_json::from_value(value) {
+            Ok(attachment) => Some((AttachmentSlot::from(key), attachment)),
+            Err(e) => {
+                warn!("Error parsing the {} attachment: {:?}", key, e);
+                None
+            }
+        })
+        .collect();
+    Ok(attachments)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 #[serde(default)]
 pub struct Weapon {
@@ -145,21 +238,9 @@ pub struct Weapon {
     pub vertical_recoil_reduction: Option<String>,
     pub horizontal_recoil_reduction: Option<String>,
 
-    // Attachments
-    pub stock: Option<Attachment>,
-    pub optic: Option<Attachment>,
-    pub ammunition: Option<Attachment>,
-    pub muzzle: Option<Attachment>,
-    pub laser: Option<Attachment>,
-    pub barrel: Option<Attachment>,
-    pub magazine: Option<Attachment>,
-    pub rear_grip: Option<Attachment>,
-    pub underbarrel: Option<Attachment>,
-    pub guard: Option<Attachment>,
-    pub comb: Option<Attachment>,
-    pub rail: Option<Attachment>,
-    pub bolt: Option<Attachment>,
-    pub trigger_action: Option<Attachment>,
+    // Attachments, wzstats sends each one as a field named after its slot
+    #[serde(flatten, deserialize_with = "deserialize_attachments")]
+    pub attachments: BTreeMap<AttachmentSlot, Attachment>,
 }
 
 impl Weapon {
@@ -171,268 +252,85 @@ impl Weapon {
             .join(" ")
     }
 
-    pub fn get_loadout_attachments(&self) -> HashMap<String, Attachment> {
-        // Go through attachements and get the name of each attachment
-        let mut map = HashMap::new();
-        // Muzzle
-        if let Some(muzzle) = &self.muzzle {
-            map.insert("Muzzle".to_string(), muzzle.to_owned());
-        }
-        // Barrel
-        if let Some(barrel) = &self.barrel {
-            map.insert("Barrel".to_string(), barrel.to_owned());
-        }
-        // Laser
-        if let Some(laser) = &self.laser {
-            map.insert("Laser".to_string(), laser.to_owned());
-        }
-        // Optic
-        if let Some(optic) = &self.optic {
-            map.insert("Optic".to_string(), optic.to_owned());
-        }
-        // Stock
-        if let Some(stock) = &self.stock {
-            map.insert("Stock".to_string(), stock.to_owned());
-        }
-        // Underbarrel
-        if let Some(underbarrel) = &self.underbarrel {
-            map.insert("Underbarrel".to_string(), underbarrel.to_owned());
-        }
-        // Ammunition
-        if let Some(ammunition) = &self.ammunition {
-            map.insert("Ammunition".to_string(), ammunition.to_owned());
-        }
-        // Rear Grip
-        if let Some(rear_grip) = &self.rear_grip {
-            map.insert("Rear Grip".to_string(), rear_grip.to_owned());
-        }
-        // Magazine
-        if let Some(mag) = &self.magazine {
-            map.insert("Magazine".to_string(), mag.to_owned());
-        }
+    /// Returns the playstyle of the build, if it is one the bot knows
+    pub fn get_playstyle(&self) -> Option<Playstyles> {
+        self.playstyle.parse().ok()
+    }
 
-        map
+    /// Returns the name of the playstyle, as wzstats names it when the bot doesn't know it
+    pub fn get_playstyle_name(&self) -> String {
+        self.get_playstyle()
+            .map(|p| p.to_string())
+            .unwrap_or(self.playstyle.clone())
     }
 
-    pub fn to_string(&self) -> String {
-        let mut loadout_string = String::new();
-        loadout_string.push_str(&format!("Weapon: {}\n", self.get_weapon_name()));
-        loadout_string.push_str(&format!("Playstyle: {}\n", self.playstyle));
-        loadout_string.push_str(&format!("Description: {}\n", self.description));
-        loadout_string.push_str(&format!("Attachments:\n"));
-        for (key, value) in self.get_loadout_attachments() {
-            loadout_string.push_str(&format!("    {} - {}\n", key, value));
+    pub fn is_for_mode(&self, mode: BuildMode) -> bool {
+        match mode {
+            BuildMode::Resurgence => self.is_ashika_build,
+            BuildMode::Ranked => self.is_warzone_ranked_build,
         }
-        loadout_string
+    }
+
+    /// Returns the attachment in the slot
+    pub fn attachment(&self, slot: AttachmentSlot) -> Option<&Attachment> {
+        self.attachments.get(&slot)
+    }
+
+    /// Returns the attachments of the build in slot order
+    pub fn get_loadout_attachments(&self) -> Vec<(AttachmentSlot, Attachment)> {
+        self.attachments
+            .iter()
+            .map(|(slot, attachment)| (slot.clone(), attachment.clone()))
+            .collect()
     }
 }
 
-impl Default for Weapon {
-    fn default() -> Self {
-        Self {
-            id: Default::default(),
-            cons: Default::default(),
-            pros: Default::default(),
-            r#type: Default::default(),
-            title: Default::default(),
-            muzzle: Default::default(),
-            added_at: Default::default(),
-            trigger_action: Default::default(),
-            optic: Default::default(),
-            ammunition: Default::default(),
-            ads_time: Default::default(),
-            author_id: Default::default(),
-            guard: Default::default(),
-            comb: Default::default(),
-            stock: Default::default(),
-            magazine: Default::default(),
-            laser: Default::default(),
-            stats_analysis: Default::default(),
-            barrel: Default::default(),
-            is_ashika_build: Default::default(),
-            position: Default::default(),
-            rail: Default::default(),
-            bolt: Default::default(),
-            rear_grip: Default::default(),
-            weapon_id: Default::default(),
-            created_at: Default::default(),
-            playstyle: Default::default(),
-            tier_score: Default::default(),
-            updated_at: Default::default(),
-            pros_cons: Default::default(),
-            description: Default::default(),
-            is_published: Default::default(),
-            underbarrel: Default::default(),
-            display_order: Default::default(),
-            movement_speed: Default::default(),
-            bullet_velocity: Default::default(),
-            ads_movement_speed: Default::default(),
-            interaction_count: Default::default(),
-            author_display_name: Default::default(),
-            vertical_recoil_reduction: Default::default(),
-            horizontal_recoil_reduction: Default::default(),
-            external_source_title: Default::default(),
-            is_warzone_ranked_build: Default::default(),
-            external_source_image: Default::default(),
+impl Display for Weapon {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Weapon: {}", self.get_weapon_name())?;
+        writeln!(f, "Playstyle: {}", self.playstyle)?;
+        writeln!(f, "Description: {}", self.description)?;
+        writeln!(f, "Attachments:")?;
+        for (key, value) in self.get_loadout_attachments() {
+            writeln!(f, "    {} - {}", key, value)?;
         }
+        Ok(())
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct WzLoadouts {
     pub builds: Vec<Weapon>,
 }
 
-impl Default for WzLoadouts {
-    fn default() -> Self {
-        Self { builds: vec![] }
+impl WzLoadouts {
+    /// Returns the ranked build, or the best build if the weapon doesn't have one
+    pub fn get_ranked_build(&self) -> Option<&Weapon> {
+        self.builds
+            .iter()
+            .find(|w| w.is_warzone_ranked_build)
+            .or_else(|| self.builds.iter().min_by_key(|w| w.position))
     }
-}
 
-#[derive(Debug, PartialEq, Deserialize, Serialize)]
-pub enum WzWeaponName {
-    FiftyGS,
-    FiveFiveSixIcarus,
-    NineMilliMeterDaemon,
-    BasP,
-    Basilisk,
-    Bryson800,
-    Bryson890,
-    Carrack300,
-    Chimera,
-    CronenSquall,
-    Ebr14,
-    Expedite12,
-    Fennec45,
-    FjxImperium,
-    FrAvancer,
-    FssHurricane,
-    FtacRecon,
-    FtacSiege,
-    GsMagma,
-    Hcr56,
-    Iso45,
-    Iso9mm,
-    IsoHemlock,
-    Kastov545,
-    Kastov74u,
-    Kastov762,
-    KvBroadside,
-    LaB330,
-    Lachmann556,
-    Lachmann762,
-    LachmannShroud,
-    LachmannSub,
-    LmS,
-    Lockwood300,
-    LockwoodMk2,
-    M13b,
-    M13c,
-    M16,
-    M4,
-    Mcpr300,
-    Minibak,
-    MxGuardian,
-    Mx9,
-    P890,
-    Pdsw528,
-    RaalMg,
-    RappH,
-    Rpk,
-    SaB50,
-    SakinMg38,
-    Signal50,
-    So14,
-    SpR208,
-    SpX80,
-    Stb556,
-    Taq56,
-    TaqM,
-    TaqV,
-    TempusRazorback,
-    TempusTorrent,
-    Tr76Geist,
-    Vaznev9k,
-    Vel46,
-    VictusXmr,
-    X12,
-    X13Auto,
-}
-
-impl Display for WzWeaponName {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let name = match self {
-            WzWeaponName::FiftyGS => "50 Gs",
-            WzWeaponName::FiveFiveSixIcarus => "556 Icarus",
-            WzWeaponName::NineMilliMeterDaemon => "9mm Daemon",
-            WzWeaponName::BasP => "Bas P",
-            WzWeaponName::Basilisk => "Basilisk",
-            WzWeaponName::Bryson800 => "Bryson 800",
-            WzWeaponName::Bryson890 => "Bryson 890",
-            WzWeaponName::Carrack300 => "Carrack 300",
-            WzWeaponName::Chimera => "Chimera",
-            WzWeaponName::CronenSquall => "Cronen Squall",
-            WzWeaponName::Ebr14 => "Ebr 14",
-            WzWeaponName::Expedite12 => "Expedite 12",
-            WzWeaponName::Fennec45 => "Fennec 45",
-            WzWeaponName::FjxImperium => "Fjx Imperium",
-            WzWeaponName::FrAvancer => "Fr Avancer",
-            WzWeaponName::FssHurricane => "Fss Hurricane",
-            WzWeaponName::FtacRecon => "Ftac Recon",
-            WzWeaponName::FtacSiege => "Ftac Siege",
-            WzWeaponName::GsMagma => "Gs Magma",
-            WzWeaponName::Hcr56 => "Hcr 56",
-            WzWeaponName::Iso45 => "Iso 45",
-            WzWeaponName::Iso9mm => "Iso 9mm",
-            WzWeaponName::IsoHemlock => "Iso Hemlock",
-            WzWeaponName::Kastov545 => "Kastov 545",
-            WzWeaponName::Kastov74u => "Kastov 74u",
-            WzWeaponName::Kastov762 => "Kastov 762",
-            WzWeaponName::KvBroadside => "Kv Broadside",
-            WzWeaponName::LaB330 => "La B 330",
-            WzWeaponName::Lachmann556 => "Lachmann 556",
-            WzWeaponName::Lachmann762 => "Lachmann 762",
-            WzWeaponName::LachmannShroud => "Lachmann Shroud",
-            WzWeaponName::LachmannSub => "Lachmann Sub",
-            WzWeaponName::LmS => "Lm S",
-            WzWeaponName::Lockwood300 => "Lockwood 300",
-            WzWeaponName::LockwoodMk2 => "Lockwood Mk2",
-            WzWeaponName::M13b => "M13b",
-            WzWeaponName::M13c => "M13c",
-            WzWeaponName::M16 => "M16",
-            WzWeaponName::M4 => "M4",
-            WzWeaponName::Mcpr300 => "Mcpr 300",
-            WzWeaponName::Minibak => "Minibak",
-            WzWeaponName::MxGuardian => "Mx Guardian",
-            WzWeaponName::Mx9 => "Mx9",
-            WzWeaponName::P890 => "P890",
-            WzWeaponName::Pdsw528 => "Pdsw 528",
-            WzWeaponName::RaalMg => "Raal Mg",
-            WzWeaponName::RappH => "Rapp H",
-            WzWeaponName::Rpk => "Rpk",
-            WzWeaponName::SaB50 => "Sa B 50",
-            WzWeaponName::SakinMg38 => "Sakin Mg38",
-            WzWeaponName::Signal50 => "Signal 50",
-            WzWeaponName::So14 => "So 14",
-            WzWeaponName::SpR208 => "Sp R 208",
-            WzWeaponName::SpX80 => "Sp X 80",
-            WzWeaponName::Stb556 => "Stb 556",
-            WzWeaponName::Taq56 => "Taq 56",
-            WzWeaponName::TaqM => "Taq M",
-            WzWeaponName::TaqV => "Taq V",
-            WzWeaponName::TempusRazorback => "Tempus Razorback",
-            WzWeaponName::TempusTorrent => "Tempus Torrent",
-            WzWeaponName::Tr76Geist => "Tr 76 Geist",
-            WzWeaponName::Vaznev9k => "Vaznev 9k",
-            WzWeaponName::Vel46 => "Vel 46",
-            WzWeaponName::VictusXmr => "Victus Xmr",
-            WzWeaponName::X12 => "X12",
-            WzWeaponName::X13Auto => "X13 Auto",
-        };
-        write!(f, "{}", name)
+    /// Returns the published builds with the playstyle and for the mode, in the order wzstats ranks them
+    pub fn get_published_builds(
+        &self,
+        playstyle: Option<Playstyles>,
+        mode: Option<BuildMode>,
+    ) -> Vec<&Weapon> {
+        let mut builds = self
+            .builds
+            .iter()
+            .filter(|b| b.is_published)
+            .filter(|b| playstyle.is_none_or(|p| b.get_playstyle() == Some(p)))
+            .filter(|b| mode.is_none_or(|m| b.is_for_mode(m)))
+            .collect::<Vec<_>>();
+        builds.sort_by_key(|b| b.position);
+        builds
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Playstyles {
     AdsSpeed,
     BulletVelocity,
@@ -461,11 +359,80 @@ impl Display for Playstyles {
     }
 }
 
+impl Playstyles {
+    pub const ALL: [Playstyles; 9] = [
+        Playstyles::AdsSpeed,
+        Playstyles::BulletVelocity,
+        Playstyles::FireRate,
+        Playstyles::HipFire,
+        Playstyles::LongRange,
+        Playstyles::LowRecoil,
+        Playstyles::Mobility,
+        Playstyles::ShortRange,
+        Playstyles::SniperSupport,
+    ];
+}
+
+impl FromStr for Playstyles {
+    type Err = String;
+
+    /// Parses a playstyle written like wzstats does, e.g. `LONG_RANGE`, or like users do, e.g. `long-range`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect::<String>();
+        Playstyles::ALL
+            .into_iter()
+            .find(|p| {
+                p.to_string()
+                    .chars()
+                    .filter(|c| c.is_alphanumeric())
+                    .flat_map(char::to_lowercase)
+                    .eq(normalized.chars())
+            })
+            .ok_or(format!("Unknown playstyle `{}`", s))
+    }
+}
+
+/// The modes builds are made for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildMode {
+    /// Builds for the resurgence maps, like Ashika Island
+    Resurgence,
+    Ranked,
+}
+
+impl FromStr for BuildMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "resurgence" | "ashika" => Ok(BuildMode::Resurgence),
+            "ranked" => Ok(BuildMode::Ranked),
+            _ => Err(format!("Unknown mode `{}`", s)),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 #[serde(default)]
 pub struct TierListResponse {
     pub wz_stats_tier_list: WzStatsTierList,
+    /// The details of the weapons in the tier list
+    pub weapons: Vec<TierListWeapon>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct TierListWeapon {
+    pub id: String,
+    pub name: String,
+    #[serde(alias = "type")]
+    pub category: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -479,6 +446,71 @@ pub struct WzStatsTierList {
     pub wz2_ranked: Tiers,
 }
 
+impl WzStatsTierList {
+    pub fn get_tiers(&self, map: TierListMap) -> &Tiers {
+        match map {
+            TierListMap::Wz2Ranked => &self.wz2_ranked,
+            TierListMap::AlMazrah => &self.al_mazrah,
+            TierListMap::AshikaIsland => &self.ashika_island,
+            TierListMap::Mw2Ranked => &self.mw2_ranked,
+        }
+    }
+}
+
+/// The maps and modes wzstats has a tier list for
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, Default,
+)]
+pub enum TierListMap {
+    #[default]
+    Wz2Ranked,
+    AlMazrah,
+    AshikaIsland,
+    Mw2Ranked,
+}
+
+impl TierListMap {
+    pub const ALL: [TierListMap; 4] = [
+        TierListMap::Wz2Ranked,
+        TierListMap::AlMazrah,
+        TierListMap::AshikaIsland,
+        TierListMap::Mw2Ranked,
+    ];
+}
+
+impl Display for TierListMap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TierListMap::Wz2Ranked => write!(f, "Warzone Ranked"),
+            TierListMap::AlMazrah => write!(f, "Al Mazrah"),
+            TierListMap::AshikaIsland => write!(f, "Ashika Island"),
+            TierListMap::Mw2Ranked => write!(f, "MW2 Ranked"),
+        }
+    }
+}
+
+impl FromStr for TierListMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect::<String>();
+        match normalized.as_str() {
+            "ranked" | "wz2ranked" | "wzranked" | "warzoneranked" => Ok(TierListMap::Wz2Ranked),
+            "mazrah" | "almazrah" | "br" | "battleroyale" => Ok(TierListMap::AlMazrah),
+            "ashika" | "ashikaisland" | "resurgence" => Ok(TierListMap::AshikaIsland),
+            "mw2" | "mw2ranked" | "multiplayer" => Ok(TierListMap::Mw2Ranked),
+            _ => Err(format!(
+                "Unknown map `{}`. Use `ranked`, `al-mazrah`, `ashika` or `mw2`",
+                s
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 #[serde(rename_all = "UPPERCASE")]
 #[serde(default)]
@@ -491,22 +523,38 @@ pub struct Tiers {
 }
 
 impl Tiers {
-    pub fn get_top_10(&self) -> Vec<String> {
-        let mut top_ten_weapons: Vec<String> = vec![];
-        let mut get_weapons = |weapons: &Vec<String>| {
-            for weapon in weapons {
-                if top_ten_weapons.len() >= 10 {
-                    break;
-                }
-                top_ten_weapons.push(weapon.to_owned())
-            }
-        };
-        get_weapons(&self.meta);
-        get_weapons(&self.a);
-        get_weapons(&self.b);
-        get_weapons(&self.c);
-        get_weapons(&self.d);
+    /// Returns the best `n` weapons with their tier, best first
+    pub fn get_top(&self, n: usize) -> Vec<(&'static str, String)> {
+        self.get_tiers()
+            .into_iter()
+            .flat_map(|(tier, weapons)| weapons.iter().map(move |w| (tier, w.to_owned())))
+            .take(n)
+            .collect()
+    }
+
+    /// Returns the name of every tier with its weapons, best first
+    pub fn get_tiers(&self) -> [(&'static str, &Vec<String>); 5] {
+        [
+            ("META", &self.meta),
+            ("A", &self.a),
+            ("B", &self.b),
+            ("C", &self.c),
+            ("D", &self.d),
+        ]
+    }
+
+    /// Returns the tier the weapon is in, e.g. `META` or `A`
+    pub fn tier_of(&self, weapon_id: &str) -> Option<&'static str> {
+        self.get_tiers()
+            .into_iter()
+            .find(|(_, weapons)| weapons.iter().any(|w| w == weapon_id))
+            .map(|(tier, _)| tier)
+    }
 
-        top_ten_weapons
+    /// Returns every weapon in the tiers, best first
+    pub fn get_all(&self) -> impl Iterator<Item = &String> {
+        self.get_tiers()
+            .into_iter()
+            .flat_map(|(_, weapons)| weapons.iter())
     }
 }
@@ -1,21 +1,45 @@
-use anyhow::Context;
+use std::sync::Arc;
+
 use serenity::{
+    builder::CreateEmbed,
     framework::standard::{
         macros::{command, group},
         Args, CommandResult,
     },
-    model::prelude::Message,
+    model::prelude::{AttachmentType, Message},
     prelude::Context as Ctx,
 };
 
-use crate::wz::types::WzLoadouts;
+use tracing::error;
+
+use crate::bot::{self, paginator};
+
+use super::card;
+use super::client::WzStatsClient;
+use super::embed;
+use super::meta_watch::MetaWatch;
+use super::resolver::{resolve_weapon, WeaponMatch};
+use super::types::{BuildMode, Playstyles, TierListMap, Weapon};
+/// The number of builds `$wz top` shows when no number is given
+const DEFAULT_TOP_BUILDS: usize = 3;
+const MAX_TOP_BUILDS: usize = 25;
 
-use super::types::{TierListResponse, WZ_WEAPONS};
 #[group("Warzone Commands")]
 #[prefixes("wz")]
 #[description("Commands for Warzone")]
 #[summary("Commands for Warzone")]
-#[commands(weapon_ids, ranked_build, top_3, future_features, help)]
+#[commands(
+    weapon_ids,
+    ranked_build,
+    builds,
+    tiers,
+    top,
+    future_features,
+    refresh,
+    subscribe,
+    unsubscribe,
+    help
+)]
 struct WzCommands;
 
 #[command]
@@ -27,9 +51,14 @@ async fn help(ctx: &Ctx, msg: &Message) -> CommandResult {
     let help = "
     **Warzone Commands**
     `$wz weapon-ids` - Displays all weapon ids
-    `$wz ranked-build <weapon_id>` - Displays the ranked build for the weapon
-    `$wz top-3` - Displays the top 3 builds
+    `$wz ranked-build <weapon>` - Displays the ranked build for the weapon, by id, name or nickname
+    `$wz builds <weapon> [--playstyle <playstyle>] [--mode resurgence|ranked]` - Lists every build for the weapon
+    `$wz tiers [map]` - Displays the tier list for `ranked`, `al-mazrah`, `ashika` or `mw2`
+    `$wz top [n] [map]` - Displays the ranked builds of the best `n` weapons on a tier list, 3 by default
     `$wz future-features` - Displays future features
+    `$wz refresh` - Fetches the latest builds and tier list from wzstats (admin only)
+    `$wz subscribe` - Posts in this channel when the meta changes (admin only)
+    `$wz unsubscribe` - Stops posting meta changes in this channel (admin only)
     ";
     msg.channel_id.say(&ctx.http, help).await?;
     Ok(())
@@ -41,47 +70,265 @@ async fn help(ctx: &Ctx, msg: &Message) -> CommandResult {
 #[min_args(0)]
 #[max_args(0)]
 async fn weapon_ids(ctx: &Ctx, msg: &Message) -> CommandResult {
+    let client = wz_client(ctx).await;
     let mut weapons = String::new();
-    for (key, value) in WZ_WEAPONS.entries() {
-        weapons.push_str(&format!("{}: {}\n", key, value));
+    for (category, category_weapons) in client.catalog().by_category() {
+        weapons.push_str(&format!("**{}**\n", category));
+        for weapon in category_weapons {
+            weapons.push_str(&format!("{}: {}\n", weapon.id, weapon.name));
+        }
     }
-    msg.channel_id.say(&ctx.http, weapons).await?;
+    bot::send_text_in_chunks(&ctx.http, msg.channel_id, &weapons).await?;
     Ok(())
 }
 
 #[command("ranked-build")]
 #[description("Displays the ranked build for the weapon")]
-#[usage("<weapon_id>")]
-#[example("kastov-762")]
+#[usage("<weapon>")]
+#[example("kastov 762")]
 #[aliases("rb")]
 #[min_args(1)]
-#[max_args(1)]
 async fn ranked_build(ctx: &Ctx, msg: &Message, args: Args) -> CommandResult {
-    let weapon_id = args.rest();
+    let client = wz_client(ctx).await;
+    let weapon_id = match resolve_or_reply(ctx, msg, &client, args.rest()).await? {
+        Some(weapon_id) => weapon_id,
+        None => return Ok(()),
+    };
     let typing = msg.channel_id.start_typing(&ctx.http)?;
-    let res = get_wz_ranked_build(weapon_id).await;
-    msg.channel_id.say(&ctx.http, res?).await?;
+    match get_wz_ranked_build(&client, &weapon_id).await {
+        Ok(build) => {
+            // The tier is only extra information, so the build is still shown without it
+            let tier = client.tier_list().await.ok().and_then(|res| {
+                res.wz_stats_tier_list
+                    .wz2_ranked
+                    .tier_of(&weapon_id)
+                    .map(str::to_string)
+            });
+            let name = client.catalog().name(&weapon_id);
+            let card = {
+                let (build, name, tier) = (build.clone(), name.clone(), tier.clone());
+                tokio::task::spawn_blocking(move || {
+                    card::render_build_card(&build, &name, tier.as_deref())
+                })
+                .await?
+            };
+            let card = match card {
+                Ok(card) => Some(card),
+                Err(e) => {
+                    error!("Error rendering the build card for {}: {:?}", weapon_id, e);
+                    None
+                }
+            };
+            msg.channel_id
+                .send_message(&ctx.http, |m| {
+                    m.embed(|e| {
+                        embed::build_embed(e, &build, &name, tier.as_deref());
+                        if card.is_some() {
+                            e.image(format!("attachment://{}.png", weapon_id));
+                        }
+                        e
+                    });
+                    if let Some(card) = card {
+                        m.add_file(AttachmentType::Bytes {
+                            data: card.into(),
+                            filename: format!("{}.png", weapon_id),
+                        });
+                    }
+                    m
+                })
+                .await?;
+        }
+        Err(res) => {
+            msg.channel_id.say(&ctx.http, res).await?;
+        }
+    }
     typing.stop().ok_or("Error stopping typing")?;
     Ok(())
 }
 
-#[command("top-3")]
-#[description("Displays the top 3 builds")]
-#[aliases("t3")]
-#[min_args(0)]
-#[max_args(0)]
-async fn top_3(ctx: &Ctx, msg: &Message) -> CommandResult {
-    let top_three_builds = get_top_three_builds().await;
-    let mut top_three_builds = top_three_builds?
+#[command]
+#[description("Lists every published build for the weapon, optionally only the builds with a playstyle or for a mode")]
+#[usage("<weapon> [--playstyle <playstyle>] [--mode resurgence|ranked]")]
+#[example("kastov 762 --playstyle long-range --mode ranked")]
+#[min_args(1)]
+async fn builds(ctx: &Ctx, msg: &Message, args: Args) -> CommandResult {
+    let (query, playstyle, mode) = match parse_build_filters(args.rest()) {
+        Ok(filters) => filters,
+        Err(e) => {
+            msg.channel_id.say(&ctx.http, e).await?;
+            return Ok(());
+        }
+    };
+    let client = wz_client(ctx).await;
+    let weapon_id = match resolve_or_reply(ctx, msg, &client, &query).await? {
+        Some(weapon_id) => weapon_id,
+        None => return Ok(()),
+    };
+
+    let typing = msg.channel_id.start_typing(&ctx.http)?;
+    let loadouts = match client.builds(&weapon_id).await {
+        Ok(loadouts) => loadouts,
+        Err(e) => {
+            error!("{:?}", e);
+            msg.channel_id
+                .say(
+                    &ctx.http,
+                    format!("Could not get the builds for {}", weapon_id),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+    let builds = loadouts.get_published_builds(playstyle, mode);
+    if builds.is_empty() {
+        msg.channel_id
+            .say(
+                &ctx.http,
+                format!("No builds found for {} with those filters", weapon_id),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let tier = client.tier_list().await.ok().and_then(|res| {
+        res.wz_stats_tier_list
+            .wz2_ranked
+            .tier_of(&weapon_id)
+            .map(str::to_string)
+    });
+    let name = client.catalog().name(&weapon_id);
+    let pages = builds
         .iter()
-        .enumerate()
-        .map(|(i, build)| format!("{}: {}", i + 1, build))
-        .collect::<Vec<String>>()
-        .join("\n");
-    top_three_builds.insert_str(0, "Top 10 Builds:\n");
-    msg.channel_id
-        .send_message(&ctx.http, |m| m.content(top_three_builds))
-        .await?;
+        .map(|build| {
+            let mut embed = CreateEmbed::default();
+            embed::build_embed(&mut embed, build, &name, tier.as_deref());
+            embed
+        })
+        .collect::<Vec<_>>();
+    typing.stop().ok_or("Error stopping typing")?;
+    paginator::send_pages(ctx, msg.channel_id, msg.author.id, pages).await
+}
+
+#[command]
+#[description(
+    "Displays the tier list of a map or mode: ranked (default), al-mazrah, ashika or mw2"
+)]
+#[usage("[map]")]
+#[example("ashika")]
+#[aliases("tier-list")]
+async fn tiers(ctx: &Ctx, msg: &Message, args: Args) -> CommandResult {
+    let map = match args.rest().trim() {
+        "" => TierListMap::default(),
+        map => match map.parse::<TierListMap>() {
+            Ok(map) => map,
+            Err(e) => {
+                msg.channel_id.say(&ctx.http, e).await?;
+                return Ok(());
+            }
+        },
+    };
+    let client = wz_client(ctx).await;
+    let tier_list = match client.tier_list().await {
+        Ok(tier_list) => tier_list,
+        Err(e) => {
+            error!("{:?}", e);
+            msg.channel_id
+                .say(&ctx.http, "Could not get the tier list from wzstats")
+                .await?;
+            return Ok(());
+        }
+    };
+    let pages = embed::tier_list_pages(
+        tier_list.wz_stats_tier_list.get_tiers(map),
+        map,
+        client.catalog(),
+    );
+    if pages.is_empty() {
+        msg.channel_id
+            .say(&ctx.http, format!("The {} tier list is empty", map))
+            .await?;
+        return Ok(());
+    }
+    paginator::send_pages(ctx, msg.channel_id, msg.author.id, pages).await
+}
+
+/// Splits the `--playstyle` and `--mode` flags from the weapon, which can be given in any order
+fn parse_build_filters(
+    args: &str,
+) -> Result<(String, Option<Playstyles>, Option<BuildMode>), String> {
+    let mut words = args.split_whitespace();
+    let mut query = vec![];
+    let (mut playstyle, mut mode) = (None, None);
+    while let Some(word) = words.next() {
+        match word {
+            "--playstyle" | "-p" => {
+                let value = words.next().ok_or("`--playstyle` needs a playstyle")?;
+                playstyle = Some(value.parse::<Playstyles>().map_err(|e| {
+                    format!(
+                        "{}. Use one of {}",
+                        e,
+                        Playstyles::ALL
+                            .iter()
+                            .map(|p| format!(
+                                "`{}`",
+                                p.to_string().to_lowercase().replace(' ', "-")
+                            ))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })?);
+            }
+            "--mode" | "-m" => {
+                let value = words.next().ok_or("`--mode` needs a mode")?;
+                mode = Some(
+                    value
+                        .parse::<BuildMode>()
+                        .map_err(|e| format!("{}. Use `resurgence` or `ranked`", e))?,
+                );
+            }
+            _ => query.push(word),
+        }
+    }
+    Ok((query.join(" "), playstyle, mode))
+}
+
+#[command]
+#[description("Displays the ranked builds of the best weapons on the tier list of a map: ranked (default), al-mazrah, ashika or mw2")]
+#[usage("[n] [map]")]
+#[example("5 ashika")]
+#[aliases("top-3", "t3")]
+async fn top(ctx: &Ctx, msg: &Message, args: Args) -> CommandResult {
+    let mut n = DEFAULT_TOP_BUILDS;
+    let mut map = TierListMap::default();
+    for arg in args.raw() {
+        if let Ok(count) = arg.parse::<usize>() {
+            n = count.clamp(1, MAX_TOP_BUILDS);
+            continue;
+        }
+        match arg.parse::<TierListMap>() {
+            Ok(parsed) => map = parsed,
+            Err(e) => {
+                msg.channel_id.say(&ctx.http, e).await?;
+                return Ok(());
+            }
+        }
+    }
+
+    let client = wz_client(ctx).await;
+    let typing = msg.channel_id.start_typing(&ctx.http)?;
+    let builds = match client.top_builds(map, n).await {
+        Ok(builds) => builds,
+        Err(e) => {
+            error!("{:?}", e);
+            msg.channel_id
+                .say(&ctx.http, "Could not get the tier list from wzstats")
+                .await?;
+            return Ok(());
+        }
+    };
+    let text = embed::top_builds_text(map, &builds, client.catalog());
+    typing.stop().ok_or("Error stopping typing")?;
+    bot::send_text_in_chunks(&ctx.http, msg.channel_id, &text).await?;
     Ok(())
 }
 
@@ -95,66 +342,122 @@ async fn future_features(ctx: &Ctx, msg: &Message) -> CommandResult {
     **NOTE**: This Feature is Currently Under Testing. Expect issues.
             Future Features:
                 - `$wz loadout <weapon_id>` - Displays the ranked loadout for the weapon
-                - Better Querying for weapons: `$wz weapon <weapon_name>` or `$wz weapon <weapon_id>`
-                - Displaying images of builds instead of text
     ";
     msg.channel_id.say(&ctx.http, features).await?;
     Ok(())
 }
 
-async fn get_wz_ranked_build(weapon_id: &str) -> Result<String, anyhow::Error> {
-    let res = reqwest::Client::new()
-        .get(format!(
-            "https:///app.wzstats.gg/wz2/weapons/builds/wzstats/with-attachments/weapon/{}/?game=wz2",
-            weapon_id
-        ))
-        .send()
-        .await
-        .context(format!("Failed to retrieve ranked build for {}", weapon_id))?;
-
-    let loadouts = res.json::<WzLoadouts>().await.ok();
-    if loadouts.is_none() {
-        return Ok(format!(
-            "No ranked build found for {}. Use `$wz weapon-ids` for a full list of weapon ids",
-            weapon_id
-        ));
-    }
+#[command]
+#[description("Fetches the latest builds and tier list from wzstats")]
+#[min_args(0)]
+#[max_args(0)]
+#[only_in(guilds)]
+#[required_permissions("ADMINISTRATOR")]
+async fn refresh(ctx: &Ctx, msg: &Message) -> CommandResult {
+    let client = wz_client(ctx).await;
+    let typing = msg.channel_id.start_typing(&ctx.http)?;
+    let res = match client.refresh().await {
+        Ok(count) => format!("Refreshed {} responses from wzstats", count),
+        Err(e) => format!(
+            "Could not refresh from wzstats, keeping the cached data: {}",
+            e
+        ),
+    };
+    msg.channel_id.say(&ctx.http, res).await?;
+    typing.stop().ok_or("Error stopping typing")?;
+    Ok(())
+}
 
-    let mut loadouts = loadouts.unwrap();
-    // Find the ranked build
-    let ranked_build = loadouts.builds.iter().find(|w| w.is_warzone_ranked_build);
-    // If this weapon doesn't have a ranked build, return the first position build
-    let ranked_build = match ranked_build {
-        Some(ranked_build) => ranked_build,
-        None => {
-            loadouts.builds.sort_by(|a, b| a.position.cmp(&b.position));
-            &loadouts.builds[0]
+#[command]
+#[description("Posts in this channel when weapons move tiers or get new ranked builds")]
+#[min_args(0)]
+#[max_args(0)]
+#[only_in(guilds)]
+#[required_permissions("ADMINISTRATOR")]
+async fn subscribe(ctx: &Ctx, msg: &Message) -> CommandResult {
+    let (client, watch) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<WzStatsClient>().unwrap().clone(),
+            data.get::<MetaWatch>().unwrap().clone(),
+        )
+    };
+    let reply = match watch.set_subscribed(msg.channel_id, true) {
+        true => {
+            // Take the first snapshot now, so the next check already has something to compare to
+            tokio::spawn(async move {
+                if let Err(e) = watch.check(&client).await {
+                    error!("Error taking a meta snapshot: {:?}", e);
+                }
+            });
+            "This channel will get a message when the Warzone meta changes"
         }
+        false => "This channel is already subscribed to meta changes",
     };
+    msg.channel_id.say(&ctx.http, reply).await?;
+    Ok(())
+}
 
-    // Return String of ranked build
-    Ok(ranked_build.to_string())
+#[command]
+#[description("Stops posting meta changes in this channel")]
+#[min_args(0)]
+#[max_args(0)]
+#[only_in(guilds)]
+#[required_permissions("ADMINISTRATOR")]
+async fn unsubscribe(ctx: &Ctx, msg: &Message) -> CommandResult {
+    let watch = {
+        let data = ctx.data.read().await;
+        data.get::<MetaWatch>().unwrap().clone()
+    };
+    let reply = match watch.set_subscribed(msg.channel_id, false) {
+        true => "This channel will no longer get meta changes",
+        false => "This channel is not subscribed to meta changes",
+    };
+    msg.channel_id.say(&ctx.http, reply).await?;
+    Ok(())
 }
 
-pub async fn get_top_three_builds() -> Result<Vec<String>, anyhow::Error> {
-    let res = reqwest::Client::new().get(
-        "https://app.wzstats.gg/wz2/weapons/meta/weapons-and-tier-lists/?streamerProfileId=wzstats",
-    ).send().await.context("Failed to retrieve top 3 builds")?;
-    let res = res
-        .json::<TierListResponse>()
-        .await
-        .context("Failed to parse top 3 builds")?;
-
-    let tiers = res.wz_stats_tier_list.wz2_ranked;
-    let top_10 = tiers.get_top_10();
-    let mut top_3_builds = Vec::new();
-
-    for weapon in top_10 {
-        if top_3_builds.len() == 3 {
-            break;
+/// Resolves the weapon a user asked for
+/// If there is no clear match, the user is told what was found and None is returned
+async fn resolve_or_reply(
+    ctx: &Ctx,
+    msg: &Message,
+    client: &WzStatsClient,
+    query: &str,
+) -> Result<Option<String>, serenity::Error> {
+    match resolve_weapon(client.catalog(), query) {
+        WeaponMatch::Found(weapon_id) => Ok(Some(weapon_id)),
+        not_found => {
+            msg.channel_id.say(&ctx.http, not_found.to_string()).await?;
+            Ok(None)
         }
-        top_3_builds.push(get_wz_ranked_build(&weapon).await?);
     }
+}
+
+/// Returns the shared wzstats client
+async fn wz_client(ctx: &Ctx) -> Arc<WzStatsClient> {
+    let data = ctx.data.read().await;
+    data.get::<WzStatsClient>().unwrap().clone()
+}
 
-    Ok(top_3_builds)
+/// Returns the ranked build of the weapon, or the message to show when there is none
+async fn get_wz_ranked_build(
+    client: &Arc<WzStatsClient>,
+    weapon_id: &str,
+) -> Result<Weapon, String> {
+    let loadouts = match client.builds(weapon_id).await {
+        Ok(loadouts) => loadouts,
+        Err(e) => {
+            error!("{:?}", e);
+            return Err(format!(
+                "No ranked build found for {}. Use `$wz weapon-ids` for a full list of weapon ids",
+                weapon_id
+            ));
+        }
+    };
+
+    match loadouts.get_ranked_build() {
+        Some(build) => Ok(build.clone()),
+        None => Err(format!("No builds found for {}", weapon_id)),
+    }
 }
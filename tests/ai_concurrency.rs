@@ -0,0 +1,119 @@
+mod common;
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use animeboys_bot::chatgpt::{animeboys_ai::AnimeboysAI, backend::MockBackend};
+use common::ai::{setup_with, turn};
+use serenity::{
+    futures::future::join_all,
+    model::prelude::ChannelId,
+    prelude::{RwLock, TypeMap, TypeMapKey},
+};
+
+const DELAY: Duration = Duration::from_millis(300);
+const CONVERSATIONS: u64 = 20;
+
+fn setup() -> Arc<AnimeboysAI> {
+    Arc::new(setup_with(MockBackend::new().with_delay(DELAY)).1)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn independent_conversations_run_in_parallel() {
+    let ai = setup();
+
+    let start = Instant::now();
+    let replies = join_all((0..CONVERSATIONS).map(|i| {
+        let ai = ai.clone();
+        tokio::spawn(async move {
+            ai.send_message(&turn("josue", &format!("message {}", i)), &ChannelId(i))
+                .await
+        })
+    }))
+    .await;
+    let elapsed = start.elapsed();
+
+    for (i, reply) in replies.into_iter().enumerate() {
+        assert_eq!(
+            reply.unwrap().content,
+            format!("mock reply to: [josue]: message {}", i)
+        );
+    }
+    // Serialized, this would take CONVERSATIONS * DELAY
+    assert!(
+        elapsed < DELAY * 3,
+        "{} conversations took {:?}",
+        CONVERSATIONS,
+        elapsed
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn messages_in_the_same_conversation_are_answered_in_order() {
+    let ai = setup();
+    let channel = ChannelId(1);
+
+    let start = Instant::now();
+    let handles = (0..3)
+        .map(|i| {
+            let ai = ai.clone();
+            tokio::spawn(async move {
+                ai.send_message(&turn("gio", &format!("message {}", i)), &channel)
+                    .await
+            })
+        })
+        .collect::<Vec<_>>();
+    for handle in join_all(handles).await {
+        handle.unwrap();
+    }
+    assert!(start.elapsed() >= DELAY * 3);
+
+    // Every question is directly followed by its answer
+    let conversation = ai.get_conversation(&channel).unwrap();
+    let history = &conversation.lock().await.history;
+    assert_eq!(history.len(), 7);
+    for pair in history[1..].chunks(2) {
+        assert_eq!(
+            pair[1].content,
+            format!("mock reply to: {}", pair[0].content)
+        );
+    }
+}
+
+struct Counter;
+
+impl TypeMapKey for Counter {
+    type Value = u64;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn other_commands_are_not_blocked_by_a_slow_reply() {
+    // Mirrors how the bot shares state through the client's TypeMap
+    let mut map = TypeMap::new();
+    map.insert::<AnimeboysAI>(setup());
+    map.insert::<Counter>(0);
+    let data = Arc::new(RwLock::new(map));
+
+    let chat = {
+        let data = data.clone();
+        tokio::spawn(async move {
+            let ai = {
+                let data = data.read().await;
+                data.get::<AnimeboysAI>().unwrap().clone()
+            };
+            ai.send_message(&turn("chris", "hello"), &ChannelId(1))
+                .await
+        })
+    };
+    tokio::time::sleep(DELAY / 10).await;
+
+    // A command that needs the write lock gets it while the AI is still responding
+    let start = Instant::now();
+    *data.write().await.get_mut::<Counter>().unwrap() += 1;
+    assert!(start.elapsed() < DELAY / 2);
+    assert!(!chat.is_finished());
+
+    chat.await.unwrap();
+}
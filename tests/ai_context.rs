@@ -0,0 +1,110 @@
+mod common;
+
+use animeboys_bot::chatgpt::context::{read_attachment, TurnAttachment, UserTurn};
+use common::ai::turn;
+use serenity::model::prelude::Attachment;
+
+fn turn_with(content: &str, attachments: Vec<TurnAttachment>) -> UserTurn {
+    UserTurn {
+        attachments,
+        ..turn("josue", content)
+    }
+}
+
+fn attachment(
+    filename: &str,
+    content: Option<&str>,
+    note: Option<&str>,
+    image: bool,
+) -> TurnAttachment {
+    TurnAttachment {
+        filename: filename.to_string(),
+        content: content.map(String::from),
+        note: note.map(String::from),
+        image_url: image.then(|| format!("https://cdn.discordapp.com/{}", filename)),
+        size: 0,
+    }
+}
+
+/// An attachment as Discord sends it, without downloading anything
+fn discord_attachment(filename: &str, content_type: Option<&str>, size: u64) -> Attachment {
+    serde_json::from_value(serde_json::json!({
+        "id": "1",
+        "filename": filename,
+        "content_type": content_type,
+        "size": size,
+        "url": format!("https://cdn.discordapp.com/{}", filename),
+        "proxy_url": format!("https://media.discordapp.net/{}", filename),
+    }))
+    .unwrap()
+}
+
+#[test]
+fn prompts_name_the_author_and_quote_the_reply() {
+    let mut turn = turn("josue", "what about this?");
+    assert_eq!(turn.to_prompt(), "[josue]: what about this?");
+
+    // Long replies are cut short
+    turn.reply_to = Some(("gio".to_string(), "x".repeat(600)));
+    assert_eq!(
+        turn.to_prompt(),
+        format!(
+            "[josue]: (replying to gio: \"{}...\")\nwhat about this?",
+            "x".repeat(500)
+        )
+    );
+}
+
+#[test]
+fn attachments_are_rendered_by_what_could_be_read() {
+    let turn = turn_with(
+        "help",
+        vec![
+            attachment("main.rs", Some("fn main() {}"), None, false),
+            attachment("big.log", None, Some("too large"), false),
+            attachment("error.png", Some("panicked at main.rs"), None, true),
+            attachment("cat.png", None, None, true),
+            attachment("song.mp3", None, None, false),
+        ],
+    );
+    let prompt = turn.to_prompt();
+    assert!(prompt.contains("Attached file `main.rs`:\n```\nfn main() {}\n```"));
+    assert!(prompt.contains("Attached file `big.log` (too large)"));
+    assert!(prompt.contains(
+        "Attached image `error.png`, the text read from it:\n```\npanicked at main.rs\n```"
+    ));
+    assert!(prompt.contains("Attached image `cat.png`"));
+    assert!(!prompt.contains("song.mp3"));
+
+    // Only images that haven't been read are sent to the AI
+    assert_eq!(turn.images(), vec!["https://cdn.discordapp.com/cat.png"]);
+    assert_eq!(turn.with_content("new").content, "new");
+}
+
+#[tokio::test]
+async fn attachments_are_only_downloaded_when_they_are_small_text_files() {
+    let image = read_attachment(&discord_attachment("cat.PNG", None, 10)).await;
+    assert_eq!(
+        image.image_url.as_deref(),
+        Some("https://cdn.discordapp.com/cat.PNG")
+    );
+    assert!(image.content.is_none() && image.note.is_none());
+
+    let binary = read_attachment(&discord_attachment(
+        "game.exe",
+        Some("application/octet-stream"),
+        10,
+    ))
+    .await;
+    assert_eq!(
+        binary.note.as_deref(),
+        Some("not a text file, content not included")
+    );
+
+    let large = read_attachment(&discord_attachment("main.rs", None, 64 * 1024)).await;
+    assert!(large.content.is_none());
+    assert!(large
+        .note
+        .unwrap()
+        .contains("larger than the 32768 byte limit"));
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use std::{
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use animeboys_bot::chatgpt::{
+    backend::Role,
+    digest::{delay_until_hour, fit_transcript, DailyDigest},
+    knowledge::{chunk_markdown, KnowledgeBase},
+    moderation::Moderator,
+};
+use common::ai::setup;
+use serenity::{http::Http, model::prelude::ChannelId};
+
+#[test]
+fn digests_are_posted_at_the_next_configured_hour() {
+    // 1970-01-02 06:30 UTC
+    let now = UNIX_EPOCH + Duration::from_secs(86400 + 6 * 3600 + 1800);
+    assert_eq!(delay_until_hour(now, 8), Duration::from_secs(5400));
+    assert_eq!(delay_until_hour(now, 6), Duration::from_secs(86400 - 1800));
+
+    let on_the_hour = UNIX_EPOCH + Duration::from_secs(8 * 3600);
+    assert_eq!(delay_until_hour(on_the_hour, 8), Duration::from_secs(86400));
+}
+
+#[test]
+fn long_days_keep_the_latest_messages() {
+    let messages = (0..200)
+        .map(|i| (format!("user{}", i), "x".repeat(1000)))
+        .collect::<Vec<_>>();
+    let transcript = fit_transcript(messages);
+
+    // Every message is cut short, and the oldest ones are dropped to fit
+    assert!(transcript.len() < 200);
+    assert_eq!(transcript.last().unwrap().0, "user199");
+    assert_eq!(transcript[0].1.chars().count(), 303);
+}
+
+#[tokio::test]
+async fn digest_asks_for_topics_decisions_and_links() {
+    let (backend, ai) = setup();
+    let channel = ChannelId(1);
+
+    backend.push_reply("**Topics**\n- minecraft");
+    let messages = vec![
+        ("josue".to_string(), "minecraft tonight?".to_string()),
+        (
+            "kevin".to_string(),
+            "https://example.com/modpack".to_string(),
+        ),
+    ];
+    let digest = ai.digest(&messages, &channel).await.content;
+    assert_eq!(digest, "**Topics**\n- minecraft");
+    assert!(!ai.does_conversation_exist(&channel));
+
+    let request = backend.requests().pop().unwrap();
+    let prompt = &request.messages.last().unwrap().content;
+    assert!(prompt.contains("decisions"));
+    assert!(prompt.contains("links"));
+    assert!(prompt.ends_with("[kevin]: https://example.com/modpack"));
+}
+
+#[tokio::test]
+async fn digests_have_their_own_prompt_without_knowledge() {
+    let faq = "# Minecraft\n\nThe minecraft server IP is only shared in #minecraft.";
+    let knowledge = Arc::new(KnowledgeBase::from_chunks(chunk_markdown("faq.md", faq)));
+    let (backend, ai) = setup();
+    let ai = ai.with_knowledge(knowledge);
+
+    let messages = vec![("josue".to_string(), "minecraft server tonight?".to_string())];
+    ai.digest(&messages, &ChannelId(1)).await;
+
+    let request = backend.requests().pop().unwrap();
+    assert_eq!(request.messages.len(), 2);
+    assert_eq!(request.messages[0].role, Role::System);
+    assert!(request.messages[0].content.contains("daily digest"));
+    assert_eq!(request.messages[1].role, Role::User);
+}
+
+#[tokio::test]
+async fn digests_are_only_posted_in_configured_channels() {
+    let (backend, ai) = setup();
+    let digest = DailyDigest::default();
+    let channel = ChannelId(1);
+
+    assert!(!digest.is_subscribed(&channel));
+    let posted = digest
+        .post(&ai, &Moderator::default(), &Http::new(""), channel)
+        .await;
+    assert!(!posted);
+    assert!(backend.requests().is_empty());
+}
@@ -0,0 +1,504 @@
+mod common;
+
+use std::sync::Arc;
+
+use animeboys_bot::chatgpt::{
+    animeboys_ai::AnimeboysAI,
+    backend::{
+        ChatChunk, ChatMessage, ChatRequest, FunctionCall, LlmBackend, LlmError, MockBackend,
+        OpenAiBackend, Role,
+    },
+    context::UserTurn,
+    export::{self, ExportFormat},
+    persona::{Persona, PersonaRegistry, DEFAULT_CHAT_PERSONA},
+};
+use common::ai::{setup, turn};
+use serenity::model::prelude::{ChannelId, MessageId, UserId};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    task::JoinHandle,
+};
+
+#[tokio::test]
+async fn chat_flow_uses_default_persona_and_keeps_history() {
+    let (backend, ai) = setup();
+    let channel = ChannelId(10);
+
+    let intro = ai
+        .create_conversation("josue", UserId(1), &channel, None)
+        .await
+        .content;
+    assert!(intro.starts_with("mock reply to: Hello bot! I am josue!"));
+    assert_eq!(
+        ai.conversation_persona(&channel).await.as_deref(),
+        Some("default")
+    );
+
+    backend.push_reply("It is 42");
+    let reply = ai
+        .send_message(&turn("josue", "What is the answer?"), &channel)
+        .await
+        .content;
+    assert_eq!(reply, "It is 42");
+
+    let requests = backend.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].model, "gpt-3.5-turbo");
+    let last = requests[1].messages.last().unwrap();
+    assert_eq!(last.role, Role::User);
+    assert_eq!(last.content, "[josue]: What is the answer?");
+
+    // System prompt, intro, intro reply, question, answer
+    let conversation = ai.get_conversation(&channel).unwrap();
+    let history = &conversation.lock().await.history;
+    assert_eq!(history.len(), 5);
+    assert_eq!(history[0].role, Role::System);
+    assert_eq!(history[4], ChatMessage::new(Role::Assistant, "It is 42"));
+}
+
+#[tokio::test]
+async fn debug_flow_uses_debug_persona() {
+    let (backend, ai) = setup();
+    let channel = ChannelId(11);
+
+    ai.debug(&turn("gio", "```py\nprint(1/0)\n```"), &channel, None)
+        .await;
+
+    assert_eq!(
+        ai.conversation_persona(&channel).await.as_deref(),
+        Some("debug")
+    );
+    let request = &backend.requests()[0];
+    assert_eq!(request.model, "gpt-4");
+    assert!(request.messages[0]
+        .content
+        .contains("help members of the Animeboys Discord server debug their code"));
+}
+
+#[tokio::test]
+async fn switching_persona_replaces_system_prompt() {
+    let (backend, ai) = setup();
+    let channel = ChannelId(12);
+
+    ai.create_conversation("chris", UserId(1), &channel, None)
+        .await;
+    assert!(ai.set_persona(&channel, "debug").await);
+    assert!(!ai.set_persona(&channel, "does-not-exist").await);
+    ai.send_message(&turn("chris", "help"), &channel).await;
+
+    let request = backend.requests().pop().unwrap();
+    assert_eq!(request.model, "gpt-4");
+    assert_eq!(
+        request
+            .messages
+            .iter()
+            .filter(|m| m.role == Role::System)
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn export_labels_participants_and_keeps_code_blocks() {
+    let (backend, ai) = setup();
+    let channel = ChannelId(14);
+
+    ai.debug(&turn("gio", "```py\nprint(1/0)\n```"), &channel, None)
+        .await;
+    backend.push_reply("Try this:\n```py\nprint(1)\n```");
+    ai.send_message(&turn("josue", "any ideas?"), &channel)
+        .await;
+    let history = ai
+        .get_conversation(&channel)
+        .unwrap()
+        .lock()
+        .await
+        .history
+        .clone();
+
+    let entries = export::transcript(&history);
+    assert_eq!(entries.len(), 4);
+    assert_eq!(entries[0].author, "gio");
+    assert_eq!(entries[0].content, "```py\nprint(1/0)\n```");
+    assert_eq!(entries[1].author, "Animeboys Bot");
+    assert_eq!(entries[2].author, "josue");
+
+    let markdown = export::render("Debug thread", &history, ExportFormat::Markdown);
+    assert!(markdown.starts_with("# Debug thread\n"));
+    assert!(markdown.contains("### josue\n\nany ideas?"));
+    assert!(markdown.contains("```py\nprint(1)\n```"));
+    assert!(!markdown.contains("help members of the Animeboys Discord server"));
+
+    let json: serde_json::Value = serde_json::from_str(&export::render(
+        "Debug thread",
+        &history,
+        ExportFormat::Json,
+    ))
+    .unwrap();
+    assert_eq!(json["messages"][3]["author"], "Animeboys Bot");
+    assert_eq!(json["messages"][3]["role"], "assistant");
+}
+
+#[tokio::test]
+async fn export_leaves_out_the_bots_own_prompts() {
+    let (backend, ai) = setup();
+    let channel = ChannelId(19);
+
+    backend.push_reply("Hi josue!");
+    ai.create_conversation("josue", UserId(1), &channel, None)
+        .await;
+    backend.push_reply("It is");
+    ai.send_message(&turn("josue", "What is 6 * 7?"), &channel)
+        .await;
+    backend.push_reply(" 42");
+    ai.continue_reply(&channel, UserId(1)).await;
+    let history = ai
+        .get_conversation(&channel)
+        .unwrap()
+        .lock()
+        .await
+        .history
+        .clone();
+
+    let entries = export::transcript(&history);
+    let authors = entries
+        .iter()
+        .map(|entry| entry.author.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        authors,
+        ["Animeboys Bot", "josue", "Animeboys Bot", "Animeboys Bot"]
+    );
+    assert!(entries
+        .iter()
+        .all(|entry| entry.role != Role::User || entry.content == "What is 6 * 7?"));
+}
+
+#[tokio::test]
+async fn regenerate_continue_and_edit_rewrite_the_history() {
+    let (backend, ai) = setup();
+    let channel = ChannelId(15);
+
+    let mut question = turn("josue", "What is 6 * 7?");
+    question.message_id = Some(MessageId(100));
+    backend.push_reply("It is 41");
+    ai.send_message(&question, &channel).await;
+
+    // Regenerating replaces the last answer
+    backend.push_reply("It is 42");
+    assert_eq!(
+        ai.regenerate(&channel, UserId(2))
+            .await
+            .map(|reply| reply.content)
+            .as_deref(),
+        Some("It is 42")
+    );
+    let history = |ai: &AnimeboysAI| {
+        let conversation = ai.get_conversation(&channel).unwrap();
+        async move {
+            conversation
+                .lock()
+                .await
+                .history
+                .iter()
+                .map(|m| m.content.clone())
+                .collect::<Vec<_>>()
+        }
+    };
+    assert_eq!(
+        history(&ai).await[1..],
+        ["[josue]: What is 6 * 7?", "It is 42"]
+    );
+
+    // Continuing asks for more after the last answer
+    backend.push_reply(", the answer to everything");
+    assert!(ai.continue_reply(&channel, UserId(2)).await.is_some());
+    assert_eq!(history(&ai).await.len(), 5);
+
+    // Both are counted for the user who reacted, not only in the totals
+    let users = ai.usage().report(1).users;
+    let reacted = users.iter().find(|(user, _)| *user == UserId(2)).unwrap();
+    assert_eq!(reacted.1.requests, 2);
+
+    // Only the last turn can be edited
+    let mut other = turn("josue", "What is 6 * 9?");
+    other.message_id = Some(MessageId(99));
+    assert!(ai.edit_last_turn(&other, &channel).await.is_none());
+    assert!(ai.is_last_turn(&channel, MessageId(100)).await);
+
+    other.message_id = Some(MessageId(100));
+    backend.push_reply("It is 54");
+    assert_eq!(
+        ai.edit_last_turn(&other, &channel)
+            .await
+            .map(|reply| reply.content)
+            .as_deref(),
+        Some("It is 54")
+    );
+    assert_eq!(
+        history(&ai).await[1..],
+        ["[josue]: What is 6 * 9?", "It is 54"]
+    );
+}
+
+fn screenshot_turn(author: &str) -> UserTurn {
+    common::ai::screenshot_turn(author, "https://cdn.example.com/error.png")
+}
+
+#[tokio::test]
+async fn images_are_sent_to_personas_that_can_see_them() {
+    let backend = Arc::new(MockBackend::new());
+    let defaults = PersonaRegistry::default();
+    let mut personas = defaults.all().cloned().collect::<Vec<_>>();
+    personas.push(Persona {
+        name: "vision".to_string(),
+        vision: true,
+        ..defaults.get("default").unwrap().clone()
+    });
+    let personas = PersonaRegistry::from_personas(personas);
+    let ai = AnimeboysAI::new(backend.clone(), personas);
+    let channel = ChannelId(16);
+    ai.create_conversation("josue", UserId(1), &channel, Some("vision"))
+        .await;
+
+    let mut screenshot = screenshot_turn("josue");
+    let vision = ai
+        .conversation_vision(&channel, None, DEFAULT_CHAT_PERSONA)
+        .await;
+    ai.read_images(&mut screenshot, vision).await;
+    ai.send_message(&screenshot, &channel).await;
+    let last = backend.requests().pop().unwrap().messages.pop().unwrap();
+    assert_eq!(last.images, ["https://cdn.example.com/error.png"]);
+    assert!(last.content.ends_with("Attached image `error.png`"));
+
+    // Only the latest turn carries its images
+    ai.send_message(&turn("josue", "thanks"), &channel).await;
+    let request = backend.requests().pop().unwrap();
+    assert!(request.messages.iter().all(|m| m.images.is_empty()));
+}
+
+#[tokio::test]
+async fn images_are_described_to_personas_that_cannot_see_them() {
+    let (backend, ai) = setup();
+    let channel = ChannelId(17);
+
+    let mut screenshot = screenshot_turn("gio");
+    let vision = ai
+        .conversation_vision(&channel, None, DEFAULT_CHAT_PERSONA)
+        .await;
+    ai.read_images(&mut screenshot, vision).await;
+    ai.send_message(&screenshot, &channel).await;
+
+    let last = backend.requests().pop().unwrap().messages.pop().unwrap();
+    assert!(last.images.is_empty());
+    assert!(last
+        .content
+        .ends_with("Attached image `error.png` (the AI cannot see images)"));
+}
+
+#[tokio::test]
+async fn ask_and_tldr_do_not_start_conversations() {
+    let (backend, ai) = setup();
+    let channel = ChannelId(14);
+
+    backend.push_reply("42");
+    let reply = ai
+        .ask(&turn("josue", "what is the answer?"), &channel, None)
+        .await
+        .content;
+    assert_eq!(reply, "42");
+    assert!(!ai.does_conversation_exist(&channel));
+    // One-shot replies are not told about threads or `$ai stop`
+    let request = backend.requests().pop().unwrap();
+    assert_eq!(request.messages[0].role, Role::System);
+    assert!(request.messages[0].content.contains("single request"));
+    assert!(!request.messages[0].content.contains("$ai stop"));
+
+    backend.push_reply("- josue asked about the server");
+    let messages = vec![
+        ("josue".to_string(), "is the server up?".to_string()),
+        ("kevin".to_string(), "yes".to_string()),
+    ];
+    let summary = ai.summarize(&messages, &channel, UserId(1)).await.content;
+    assert_eq!(summary, "- josue asked about the server");
+    assert!(!ai.does_conversation_exist(&channel));
+
+    let request = backend.requests().pop().unwrap();
+    let prompt = &request.messages.last().unwrap().content;
+    assert!(prompt.ends_with("\n[josue]: is the server up?\n[kevin]: yes"));
+}
+
+#[tokio::test]
+async fn backend_errors_are_reported_to_the_user() {
+    let (backend, ai) = setup();
+    let channel = ChannelId(13);
+
+    // A rejected request is not retried
+    backend.push_error(LlmError::Api {
+        status: 400,
+        message: "boom".to_string(),
+        code: None,
+    });
+    let reply = ai
+        .send_message(&turn("josue", "hi"), &channel)
+        .await
+        .content;
+    assert_eq!(
+        reply,
+        "There was an error processing your request. Please try again later!"
+    );
+}
+
+#[tokio::test]
+async fn mock_backend_streams_function_calls() {
+    let backend = MockBackend::new();
+    backend.push_function_call("get_weather", "{\"city\":\"Austin\"}");
+
+    let response = backend
+        .chat(ChatRequest {
+            model: "gpt-4".to_string(),
+            messages: vec![ChatMessage::new(Role::User, "weather?")],
+            temperature: 0.0,
+            max_tokens: None,
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response.function_call,
+        Some(FunctionCall {
+            name: "get_weather".to_string(),
+            arguments: "{\"city\":\"Austin\"}".to_string(),
+        })
+    );
+    assert_eq!(response.finish_reason.as_deref(), Some("function_call"));
+}
+
+/// Serves a single canned streaming response, like a local llama.cpp server would
+/// The task returns the body of the request it got
+async fn serve_once(body: &'static str) -> (String, JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = vec![];
+        let mut buf = vec![0; 16 * 1024];
+        let body_start = loop {
+            let read = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..read]);
+            if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end + 4;
+            }
+        };
+        let headers = String::from_utf8_lossy(&request[..body_start]).to_lowercase();
+        let length = headers
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length: "))
+            .and_then(|length| length.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        while request.len() < body_start + length {
+            let read = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..read]);
+        }
+
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        socket.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8_lossy(&request[body_start..]).to_string()
+    });
+    (format!("http://{}/v1", addr), server)
+}
+
+#[tokio::test]
+async fn compatible_backend_parses_streamed_chunks() {
+    let (base_url, server) = serve_once(concat!(
+        "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Hello\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{\"content\":\" there\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+        "data: [DONE]\n\n",
+    ))
+    .await;
+    let backend = OpenAiBackend::compatible(&base_url, None);
+
+    let mut stream = backend
+        .stream_chat(ChatRequest {
+            model: "llama".to_string(),
+            messages: vec![ChatMessage::new(Role::User, "hi")],
+            temperature: 0.5,
+            max_tokens: None,
+        })
+        .await
+        .unwrap();
+
+    let mut chunks = vec![];
+    while let Some(chunk) = serenity::futures::StreamExt::next(&mut stream).await {
+        chunks.push(chunk.unwrap());
+    }
+    assert_eq!(
+        chunks,
+        vec![
+            ChatChunk::Content("Hello".to_string()),
+            ChatChunk::Content(" there".to_string()),
+            ChatChunk::Finish("stop".to_string()),
+        ]
+    );
+
+    // Compatible servers may reject `stream_options`, so it is only sent when enabled
+    let request: serde_json::Value = serde_json::from_str(&server.await.unwrap()).unwrap();
+    assert_eq!(request["model"], "llama");
+    assert!(request.get("stream_options").is_none());
+}
+
+#[tokio::test]
+async fn usage_reporting_can_be_enabled_for_compatible_backends() {
+    let (base_url, server) = serve_once(concat!(
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"},\"finish_reason\":\"stop\"}]}\n\n",
+        "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":3,\"completion_tokens\":1}}\n\n",
+        "data: [DONE]\n\n",
+    ))
+    .await;
+    let backend = OpenAiBackend::compatible(&base_url, None).with_usage_reporting(true);
+
+    let response = backend
+        .chat(ChatRequest {
+            model: "llama".to_string(),
+            messages: vec![ChatMessage::new(Role::User, "hi")],
+            temperature: 0.5,
+            max_tokens: None,
+        })
+        .await
+        .unwrap();
+    assert_eq!(response.content, "Hi");
+    assert_eq!(response.usage.map(|u| u.prompt_tokens), Some(3));
+
+    let request: serde_json::Value = serde_json::from_str(&server.await.unwrap()).unwrap();
+    assert_eq!(request["stream_options"]["include_usage"], true);
+}
+
+#[tokio::test]
+async fn images_are_sent_when_the_default_persona_can_see_them() {
+    let backend = Arc::new(MockBackend::new());
+    let personas = PersonaRegistry::default()
+        .all()
+        .map(|persona| Persona {
+            vision: true,
+            ..persona.clone()
+        })
+        .collect();
+    let ai = AnimeboysAI::new(backend.clone(), PersonaRegistry::from_personas(personas));
+    let channel = ChannelId(18);
+
+    // `$ai ask` has no conversation, the default persona decides
+    let mut screenshot = screenshot_turn("josue");
+    let vision = ai.resolve_persona(None, DEFAULT_CHAT_PERSONA).vision;
+    ai.read_images(&mut screenshot, vision).await;
+    ai.ask(&screenshot, &channel, None).await;
+    let last = backend.requests().pop().unwrap().messages.pop().unwrap();
+    assert_eq!(last.images, ["https://cdn.example.com/error.png"]);
+    assert!(screenshot.attachments[0].note.is_none());
+}
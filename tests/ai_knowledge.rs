@@ -0,0 +1,92 @@
+mod common;
+
+use std::sync::Arc;
+
+use animeboys_bot::chatgpt::{
+    backend::Role,
+    knowledge::{chunk_markdown, KnowledgeBase},
+};
+use common::ai::{setup, turn};
+use serenity::model::prelude::ChannelId;
+
+const FAQ: &str = "
+# Minecraft
+
+The server IP is only shared in #minecraft. Do not post it anywhere else.
+
+Start the server with `$mc start` and stop it when you are done.
+
+# Rules
+
+Be nice to each other.
+
+```py
+# this is a comment, not a heading
+print('hi')
+```
+";
+
+#[test]
+fn markdown_is_chunked_by_section() {
+    let chunks = chunk_markdown("faq.md", FAQ);
+    let sources = chunks.iter().map(|c| c.source.as_str()).collect::<Vec<_>>();
+    assert_eq!(sources, ["faq.md > Minecraft", "faq.md > Rules"]);
+    assert!(chunks[1]
+        .text
+        .contains("# this is a comment, not a heading"));
+}
+
+#[test]
+fn search_ranks_the_matching_section_first() {
+    let knowledge = KnowledgeBase::from_chunks(chunk_markdown("faq.md", FAQ));
+    let results = knowledge.search("What is the minecraft server IP?");
+    assert_eq!(results[0].source, "faq.md > Minecraft");
+
+    assert!(knowledge.search("the and of").is_empty());
+}
+
+#[test]
+fn markers_in_code_are_not_citations() {
+    let chunks = chunk_markdown("faq.md", FAQ);
+    let code = "Use the first one:\n```py\nprint(servers[1])\n```\nor `ips[2]`";
+    assert_eq!(KnowledgeBase::citations(&chunks, code), None);
+
+    let cited = format!("{}\nThe IP is in #minecraft [1]", code);
+    assert_eq!(
+        KnowledgeBase::citations(&chunks, &cited).as_deref(),
+        Some("Sources: [1] faq.md > Minecraft")
+    );
+}
+
+#[tokio::test]
+async fn matching_knowledge_is_given_to_the_ai_and_cited() {
+    let knowledge = Arc::new(KnowledgeBase::from_chunks(chunk_markdown("faq.md", FAQ)));
+    let (backend, ai) = setup();
+    let ai = ai.with_knowledge(knowledge);
+    let channel = ChannelId(1);
+
+    backend.push_reply("Ask in #minecraft for the IP [1]");
+    let reply = ai
+        .send_message(&turn("josue", "what's the minecraft server ip?"), &channel)
+        .await
+        .content;
+    assert_eq!(
+        reply,
+        "Ask in #minecraft for the IP [1]\n\nSources: [1] faq.md > Minecraft"
+    );
+
+    // The knowledge sits right before the message it was retrieved for
+    let request = backend.requests().pop().unwrap();
+    let context = &request.messages[request.messages.len() - 2];
+    assert_eq!(context.role, Role::System);
+    assert!(context.content.contains("[1] (faq.md > Minecraft)"));
+
+    // But it is not kept in the history
+    let conversation = ai.get_conversation(&channel).unwrap();
+    let history = &conversation.lock().await.history;
+    assert_eq!(history.iter().filter(|m| m.role == Role::System).count(), 1);
+    assert_eq!(
+        history.last().unwrap().content,
+        "Ask in #minecraft for the IP [1]"
+    );
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use animeboys_bot::chatgpt::{backend::MockBackend, lifecycle::ConversationLifecycle};
+use common::ai::{setup, setup_with, turn};
+use serenity::model::prelude::{ChannelId, UserId};
+
+#[tokio::test]
+async fn idle_conversations_are_expired() {
+    let (_, ai) = setup();
+    let lifecycle = ConversationLifecycle::default()
+        .with_idle_ttl(Duration::from_millis(100), Duration::from_millis(10));
+
+    ai.create_conversation("josue", UserId(1), &ChannelId(1), None)
+        .await;
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    ai.create_conversation("gio", UserId(1), &ChannelId(2), None)
+        .await;
+
+    assert_eq!(lifecycle.expire_idle(&ai).await, 1);
+    assert!(!ai.does_conversation_exist(&ChannelId(1)));
+    assert!(ai.does_conversation_exist(&ChannelId(2)));
+
+    // Without persistence an expired conversation cannot be resumed,
+    // the next message is told so once
+    assert!(!lifecycle.resume(&ai, &ChannelId(1)).await);
+    assert!(lifecycle.take_expired(&ChannelId(1)));
+    assert!(!lifecycle.take_expired(&ChannelId(1)));
+    assert!(!lifecycle.take_expired(&ChannelId(2)));
+}
+
+#[tokio::test]
+async fn suspended_conversations_resume_with_their_history() {
+    let dir = std::env::temp_dir().join(format!("animeboys-lifecycle-{}", std::process::id()));
+    std::env::set_var("DATA_DIR", &dir);
+
+    let (_, ai) = setup();
+    let lifecycle = ConversationLifecycle::default().with_persistence(true);
+    let channel = ChannelId(3);
+
+    ai.create_conversation("chris", UserId(1), &channel, Some("debug"))
+        .await;
+    ai.send_message(&turn("chris", "still there?"), &channel)
+        .await;
+    let history = ai
+        .get_conversation(&channel)
+        .unwrap()
+        .lock()
+        .await
+        .history
+        .clone();
+
+    lifecycle.suspend(&ai, &channel).await;
+    assert!(!ai.does_conversation_exist(&channel));
+
+    assert!(lifecycle.resume(&ai, &channel).await);
+    assert_eq!(
+        ai.conversation_persona(&channel).await.as_deref(),
+        Some("debug")
+    );
+    let conversation = ai.get_conversation(&channel).unwrap();
+    assert_eq!(conversation.lock().await.history, history);
+
+    // Ending the conversation deletes the saved history
+    lifecycle.end(&ai, &channel).await;
+    assert!(!lifecycle.resume(&ai, &channel).await);
+
+    // Only the conversations saved by this lifecycle, or found when it was created, are looked for
+    let other = ConversationLifecycle::default().with_persistence(true);
+    ai.create_conversation("gio", UserId(1), &channel, None)
+        .await;
+    other.suspend(&ai, &channel).await;
+    assert!(!lifecycle.resume(&ai, &channel).await);
+
+    // A restarted bot finds the saved conversations, and deletes them once they are stale
+    let restarted = ConversationLifecycle::default().with_persistence(true);
+    assert!(restarted.resume(&ai, &channel).await);
+    restarted.suspend(&ai, &channel).await;
+    let restarted = restarted.with_retention(Duration::from_millis(10));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(restarted.prune_saved().await, 1);
+    assert!(!dir.join("conversations").join("3.json").exists());
+    assert!(!restarted.resume(&ai, &channel).await);
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn turns_waiting_during_a_suspend_keep_the_conversation() {
+    // The same data directory as the other tests, the environment is shared by the whole process
+    let dir = std::env::temp_dir().join(format!("animeboys-lifecycle-{}", std::process::id()));
+    std::env::set_var("DATA_DIR", &dir);
+
+    let ai = Arc::new(setup_with(MockBackend::new().with_delay(Duration::from_millis(200))).1);
+    let lifecycle = ConversationLifecycle::default().with_persistence(true);
+    let channel = ChannelId(4);
+    ai.create_conversation("josue", UserId(1), &channel, None)
+        .await;
+
+    // The first turn holds the conversation while the suspend and the second turn wait for it
+    let first = tokio::spawn({
+        let ai = ai.clone();
+        async move { ai.send_message(&turn("josue", "first"), &channel).await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let suspend = tokio::spawn({
+        let (ai, lifecycle) = (ai.clone(), lifecycle.clone());
+        async move { lifecycle.suspend(&ai, &channel).await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let second = tokio::spawn({
+        let ai = ai.clone();
+        async move { ai.send_message(&turn("josue", "second"), &channel).await }
+    });
+    first.await.unwrap();
+    suspend.await.unwrap();
+    second.await.unwrap();
+
+    // The second turn is in the conversation the channel has, not in the one that was dropped
+    assert!(lifecycle.resume(&ai, &channel).await);
+    let conversation = ai.get_conversation(&channel).unwrap();
+    let history = conversation.lock().await.history.clone();
+    assert!(history.iter().any(|m| m.content.ends_with("first")));
+    assert!(history.iter().any(|m| m.content.ends_with("second")));
+    assert!(!conversation.lock().await.suspended);
+
+    lifecycle.end(&ai, &channel).await;
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use std::sync::Arc;
+
+use animeboys_bot::chatgpt::{
+    backend::Role,
+    context::{TurnAttachment, UserTurn},
+    moderation::{parse_word_list, ModerationAction, Moderator},
+};
+use common::ai::{setup, turn};
+use serenity::{
+    http::Http,
+    model::prelude::{ChannelId, UserId},
+};
+
+const WORD_LIST: &str = "
+# Comments and empty lines are skipped
+
+darn
+re: fr[i1]ck
+re: [unclosed
+";
+
+#[test]
+fn word_lists_skip_comments_and_invalid_regexes() {
+    let patterns = parse_word_list(WORD_LIST);
+    assert_eq!(patterns.len(), 2);
+
+    // Words only match whole words, in any case
+    assert!(patterns[0].is_match("well DARN it"));
+    assert!(!patterns[0].is_match("darned"));
+    assert!(patterns[1].is_match("what the fr1ck"));
+}
+
+#[tokio::test]
+async fn local_matches_are_redacted_or_blocked() {
+    let moderator = Moderator::default().with_word_list(WORD_LIST);
+    let result = moderator.check("darn, that is fine").await;
+    assert!(result.flagged);
+    assert!(!result.must_block);
+    assert_eq!(result.redacted, "[redacted], that is fine");
+    assert_eq!(result.reasons, vec![r"matched `\bdarn\b`".to_string()]);
+
+    let clean = moderator.check("all good").await;
+    assert!(!clean.flagged);
+    assert_eq!(clean.redacted, "all good");
+
+    let blocking = Moderator::default()
+        .with_word_list(WORD_LIST)
+        .with_action(ModerationAction::Block);
+    assert!(blocking.check("frick").await.must_block);
+}
+
+fn turn_with_attachment(content: &str, attachment: &str) -> UserTurn {
+    UserTurn {
+        attachments: vec![TurnAttachment {
+            filename: "log.txt".to_string(),
+            content: Some(attachment.to_string()),
+            note: None,
+            image_url: None,
+            size: attachment.len() as u64,
+        }],
+        ..turn("josue", content)
+    }
+}
+
+#[tokio::test]
+async fn turns_are_redacted_in_place_including_attachments() {
+    let moderator = Moderator::default().with_word_list(WORD_LIST);
+    let http = Http::new("");
+    let mut turn = turn_with_attachment("darn bug", "frick this log");
+    assert!(
+        moderator
+            .moderate_turn(&http, ChannelId(1), &mut turn)
+            .await
+    );
+    assert_eq!(turn.content, "[redacted] bug");
+    assert_eq!(
+        turn.attachments[0].content.as_deref(),
+        Some("[redacted] this log")
+    );
+
+    // The quoted message is sent to the AI as well
+    let mut reply = UserTurn {
+        reply_to: Some(("gio".to_string(), "darn it".to_string())),
+        ..common::ai::turn("josue", "what did they mean?")
+    };
+    assert!(
+        moderator
+            .moderate_turn(&http, ChannelId(1), &mut reply)
+            .await
+    );
+    assert_eq!(
+        reply.reply_to,
+        Some(("gio".to_string(), "[redacted] it".to_string()))
+    );
+
+    // A flagged attachment blocks the whole turn
+    let moderator = moderator.with_action(ModerationAction::Block);
+    let mut turn = turn_with_attachment("fine", "frick this log");
+    assert!(
+        !moderator
+            .moderate_turn(&http, ChannelId(1), &mut turn)
+            .await
+    );
+}
+
+#[tokio::test]
+async fn bans_are_saved_and_loaded() {
+    let dir = std::env::temp_dir().join(format!("ai-moderation-{}", std::process::id()));
+    std::env::set_var("DATA_DIR", &dir);
+
+    let moderator = Moderator::from_env();
+    assert!(moderator.ban(UserId(7)).await);
+    assert!(!moderator.ban(UserId(7)).await);
+    assert!(Moderator::from_env().is_banned(&UserId(7)));
+
+    assert!(moderator.unban(&UserId(7)).await);
+    assert!(!moderator.unban(&UserId(7)).await);
+    assert!(!Moderator::from_env().is_banned(&UserId(7)));
+
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn flagged_responses_are_saved_moderated() {
+    let (backend, ai) = setup();
+    let moderator = Moderator::default().with_word_list(WORD_LIST);
+    let ai = ai.with_moderator(Arc::new(moderator.with_action(ModerationAction::Block)));
+    let channel = ChannelId(30);
+
+    backend.push_reply("frick, that is broken");
+    let reply = ai.send_message(&turn("josue", "why?"), &channel).await;
+    // The reply is moderated once, the sender gets the text to post and the incident to log
+    assert_eq!(
+        reply.content,
+        "The AI's response was blocked by moderation."
+    );
+    let incident = reply.incident.unwrap();
+    assert!(incident.must_block);
+    assert_eq!(incident.reasons, vec![r"matched `fr[i1]ck`".to_string()]);
+
+    let conversation = ai.get_conversation(&channel).unwrap();
+    let last = conversation.lock().await.history.last().cloned().unwrap();
+    assert_eq!(last.role, Role::Assistant);
+    assert_eq!(last.content, reply.content);
+
+    backend.push_reply("all good");
+    let reply = ai.send_message(&turn("josue", "and now?"), &channel).await;
+    assert_eq!(reply.content, "all good");
+    assert!(reply.incident.is_none());
+}
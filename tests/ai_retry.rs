@@ -0,0 +1,169 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use animeboys_bot::chatgpt::{
+    animeboys_ai::AnimeboysAI,
+    backend::{ChatMessage, LlmError, LlmErrorKind, MockBackend, Role},
+    retry::RetryPolicy,
+};
+use common::ai::turn;
+use serenity::model::prelude::ChannelId;
+
+fn api_error(status: u16, code: Option<&str>) -> LlmError {
+    LlmError::Api {
+        status,
+        message: "boom".to_string(),
+        code: code.map(String::from),
+    }
+}
+
+fn setup(retry: RetryPolicy) -> (Arc<MockBackend>, AnimeboysAI) {
+    let (backend, ai) = common::ai::setup();
+    let ai = ai.with_retry(retry.with_retries(2, Duration::from_millis(1)));
+    (backend, ai)
+}
+
+#[test]
+fn errors_are_classified() {
+    assert_eq!(api_error(429, None).kind(), LlmErrorKind::RateLimit);
+    assert_eq!(
+        api_error(429, Some("insufficient_quota")).kind(),
+        LlmErrorKind::Auth
+    );
+    assert_eq!(
+        api_error(400, Some("context_length_exceeded")).kind(),
+        LlmErrorKind::ContextLength
+    );
+    assert_eq!(api_error(401, None).kind(), LlmErrorKind::Auth);
+    assert_eq!(api_error(503, None).kind(), LlmErrorKind::Server);
+    assert_eq!(LlmError::Timeout.kind(), LlmErrorKind::Timeout);
+    assert!(!LlmErrorKind::Auth.is_transient());
+    assert!(LlmErrorKind::RateLimit.is_transient());
+}
+
+#[tokio::test]
+async fn transient_errors_are_retried() {
+    let (backend, ai) = setup(RetryPolicy::default());
+    let channel = ChannelId(1);
+
+    backend.push_error(api_error(429, None));
+    backend.push_error(api_error(503, None));
+    backend.push_reply("finally");
+    let reply = ai
+        .send_message(&turn("josue", "hi"), &channel)
+        .await
+        .content;
+    assert_eq!(reply, "finally");
+    assert_eq!(backend.requests().len(), 3);
+}
+
+#[tokio::test]
+async fn the_user_is_told_what_went_wrong_and_keeps_their_message() {
+    let (backend, ai) = setup(RetryPolicy::default());
+    let channel = ChannelId(2);
+
+    for _ in 0..3 {
+        backend.push_error(api_error(429, None));
+    }
+    let reply = ai
+        .send_message(&turn("josue", "hi"), &channel)
+        .await
+        .content;
+    assert_eq!(reply, LlmErrorKind::RateLimit.user_message());
+
+    let conversation = ai.get_conversation(&channel).unwrap();
+    let conversation = conversation.lock().await;
+    let last = conversation.history.last().unwrap();
+    assert_eq!(last.role, Role::User);
+    assert_eq!(last.content, "[josue]: hi");
+}
+
+#[tokio::test]
+async fn failing_models_fall_back_to_the_secondary_model() {
+    let (backend, ai) = setup(RetryPolicy::default().with_fallback_model("backup-model"));
+    let channel = ChannelId(3);
+
+    for _ in 0..3 {
+        backend.push_error(api_error(500, None));
+    }
+    backend.push_reply("from the backup");
+    let reply = ai
+        .send_message(&turn("josue", "hi"), &channel)
+        .await
+        .content;
+    assert_eq!(reply, "from the backup");
+
+    let requests = backend.requests();
+    assert_eq!(requests.len(), 4);
+    assert_ne!(requests[0].model, "backup-model");
+    assert_eq!(requests[3].model, "backup-model");
+
+    // A bad API key is not fixed by another model
+    backend.push_error(api_error(401, None));
+    let reply = ai
+        .send_message(&turn("josue", "hi again"), &channel)
+        .await
+        .content;
+    assert_eq!(reply, LlmErrorKind::Auth.user_message());
+    assert_eq!(backend.requests().len(), 5);
+}
+
+#[tokio::test]
+async fn the_fallback_model_gets_a_single_attempt() {
+    let (backend, ai) = setup(RetryPolicy::default().with_fallback_model("backup-model"));
+    let channel = ChannelId(5);
+
+    for _ in 0..5 {
+        backend.push_error(api_error(503, None));
+    }
+    let reply = ai
+        .send_message(&turn("josue", "hi"), &channel)
+        .await
+        .content;
+    assert_eq!(reply, LlmErrorKind::Server.user_message());
+
+    // Three attempts with the persona's model, then one with the fallback
+    let models = backend
+        .requests()
+        .into_iter()
+        .map(|request| request.model)
+        .collect::<Vec<_>>();
+    assert_eq!(models.len(), 4);
+    assert_eq!(
+        models
+            .iter()
+            .filter(|model| *model == "backup-model")
+            .count(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn long_conversations_are_trimmed() {
+    let (backend, ai) = setup(RetryPolicy::default());
+    let channel = ChannelId(4);
+
+    ai.send_message(&turn("josue", "one"), &channel).await;
+    ai.send_message(&turn("josue", "two"), &channel).await;
+    ai.send_message(&turn("josue", "three"), &channel).await;
+
+    backend.push_error(api_error(400, Some("context_length_exceeded")));
+    backend.push_reply("short again");
+    let reply = ai
+        .send_message(&turn("josue", "four"), &channel)
+        .await
+        .content;
+    assert_eq!(reply, "short again");
+
+    // The system prompt and the latest turn survive, the oldest turns do not
+    let request = backend.requests().pop().unwrap();
+    assert_eq!(request.messages[0].role, Role::System);
+    assert_eq!(request.messages[1].role, Role::User);
+    assert_eq!(
+        request.messages.last().unwrap(),
+        &ChatMessage::new(Role::User, "[josue]: four")
+    );
+    assert!(!request.messages.iter().any(|m| m.content == "[josue]: one"));
+    assert!(request.messages.len() < 8);
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use animeboys_bot::chatgpt::sandbox::{CodeBlock, Sandbox, SandboxError};
+
+/// Runs the code directly, `env` stands in for a real isolating wrapper
+fn sandbox() -> Sandbox {
+    Sandbox::default()
+        .with_wrapper(&["env"])
+        .with_timeout(Duration::from_secs(2))
+}
+
+fn bash(code: &str) -> CodeBlock {
+    CodeBlock {
+        language: Some("bash".to_string()),
+        code: code.to_string(),
+    }
+}
+
+#[test]
+fn code_blocks_are_extracted_with_their_language() {
+    let block = CodeBlock::extract("fix this ```py\nprint('hi')\n``` please").unwrap();
+    assert_eq!(block.language.as_deref(), Some("py"));
+    assert_eq!(block.code, "print('hi')\n");
+
+    let block = CodeBlock::extract("```\nlet x = 1;\n```").unwrap();
+    assert_eq!(block.language, None);
+    assert_eq!(block.code, "let x = 1;\n");
+
+    // A single line block has no language
+    let block = CodeBlock::extract("```echo hi```").unwrap();
+    assert_eq!(block.language, None);
+    assert_eq!(block.code, "echo hi");
+
+    assert!(CodeBlock::extract("no code here").is_none());
+    assert!(CodeBlock::extract("```unterminated").is_none());
+}
+
+#[tokio::test]
+async fn execution_needs_an_isolating_wrapper() {
+    let sandbox = Sandbox::default();
+    assert!(!sandbox.is_enabled());
+    assert!(matches!(
+        sandbox.run(&bash("echo hi")).await,
+        Err(SandboxError::Disabled)
+    ));
+
+    std::env::set_var("AI_SANDBOX_ENABLED", "true");
+    assert!(!Sandbox::from_env().is_enabled());
+    std::env::set_var("AI_SANDBOX_WRAPPER", "firejail --net=none --quiet");
+    assert!(Sandbox::from_env().is_enabled());
+    std::env::remove_var("AI_SANDBOX_ENABLED");
+    std::env::remove_var("AI_SANDBOX_WRAPPER");
+}
+
+#[tokio::test]
+async fn output_is_captured_and_truncated() {
+    let result = sandbox()
+        .run(&bash("echo hello; echo oops >&2; exit 3"))
+        .await
+        .unwrap();
+    assert_eq!(result.exit_code, Some(3));
+    assert_eq!(result.stdout, "hello\n");
+    assert_eq!(result.stderr, "oops\n");
+
+    let result = sandbox()
+        .with_max_output(10)
+        .run(&bash("printf 'é%.0s' $(seq 100)"))
+        .await
+        .unwrap();
+    assert!(result.stdout.starts_with("ééééé\n"));
+    assert!(result.stdout.ends_with("(output truncated)"));
+}
+
+#[tokio::test]
+async fn programs_that_run_too_long_are_killed() {
+    let result = sandbox().run(&bash("sleep 30")).await.unwrap();
+    assert!(result.timed_out);
+    assert_eq!(result.exit_code, None);
+}
+
+#[tokio::test]
+async fn background_processes_are_killed_with_the_program() {
+    let marker = std::env::temp_dir().join(format!("sandbox-marker-{}", std::process::id()));
+    let code = format!(
+        "(sleep 1; touch {}) >/dev/null 2>&1 &\necho started",
+        marker.display()
+    );
+    let result = sandbox().run(&bash(&code)).await.unwrap();
+    assert_eq!(result.stdout, "started\n");
+
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    assert!(!marker.exists(), "a background process outlived the run");
+}
+
+#[tokio::test]
+async fn programs_that_flood_the_output_are_killed() {
+    let started = std::time::Instant::now();
+    let result = sandbox()
+        .with_timeout(Duration::from_secs(30))
+        .with_max_output(100)
+        .run(&bash("yes"))
+        .await
+        .unwrap();
+    assert!(!result.timed_out);
+    assert_eq!(result.exit_code, None);
+    assert!(result.stdout.ends_with("(output truncated)"));
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
+
+#[tokio::test]
+async fn hidden_paths_are_not_readable() {
+    // Mounting needs a mount namespace, skip where unprivileged namespaces aren't available
+    let wrapper = ["unshare", "--user", "--map-root-user", "--mount"];
+    let available = std::process::Command::new(wrapper[0])
+        .args(&wrapper[1..])
+        .arg("true")
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    if !available {
+        return;
+    }
+
+    let dir = std::env::temp_dir().join(format!("sandbox-hidden-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("ai_bans.json"), "secret").unwrap();
+
+    let sandbox = Sandbox::default()
+        .with_wrapper(&wrapper)
+        .with_hidden_path(&dir);
+    let code = format!("cat {}/ai_bans.json", dir.display());
+    let result = sandbox.run(&bash(&code)).await.unwrap();
+    assert_ne!(result.exit_code, Some(0));
+    assert!(!result.stdout.contains("secret"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use animeboys_bot::chatgpt::{
+    backend::{LlmError, Usage},
+    usage::{Outcome, UsageRecord, UsageTracker},
+};
+use common::ai::{setup, turn_from};
+use serenity::model::prelude::{ChannelId, UserId};
+
+#[test]
+fn cost_uses_the_longest_matching_model_price() {
+    let usage = UsageTracker::default();
+    let tokens = Usage {
+        prompt_tokens: 1000,
+        completion_tokens: 2000,
+    };
+    assert!((usage.cost("gpt-4-0613", &tokens) - 0.15).abs() < 1e-9);
+    assert!((usage.cost("gpt-4o-mini", &tokens) - 0.00135).abs() < 1e-9);
+    assert_eq!(usage.cost("llama3", &tokens), 0.0);
+}
+
+#[tokio::test]
+async fn every_request_is_recorded_per_user_and_channel() {
+    let usage = Arc::new(UsageTracker::default());
+    let (backend, ai) = setup();
+    let ai = ai.with_usage(usage.clone());
+
+    backend.push_reply("one two three");
+    ai.send_message(&turn_from(1, "hello"), &ChannelId(10))
+        .await;
+    backend.push_reply("four five");
+    ai.send_message(&turn_from(2, "hi"), &ChannelId(20)).await;
+    backend.push_error(LlmError::Api {
+        status: 401,
+        message: "invalid api key".to_string(),
+        code: None,
+    });
+    ai.send_message(&turn_from(2, "again"), &ChannelId(20))
+        .await;
+
+    let report = usage.report(1);
+    assert_eq!(report.total.requests, 3);
+    assert_eq!(report.total.errors, 1);
+    // The mock counts words as tokens
+    assert_eq!(report.total.completion_tokens, 5);
+
+    let user = |id| {
+        report
+            .users
+            .iter()
+            .find(|(user, _)| *user == UserId(id))
+            .map(|(_, totals)| *totals)
+            .unwrap()
+    };
+    assert_eq!(user(1).requests, 1);
+    assert_eq!(user(1).completion_tokens, 3);
+    assert_eq!(user(2).requests, 2);
+    assert_eq!(user(2).errors, 1);
+    assert_eq!(report.channels.len(), 2);
+    assert_eq!(report.models.len(), 1);
+    assert!(report.total.cost_usd > 0.0);
+
+    let metrics = usage.to_prometheus();
+    let model = &report.models[0].0;
+    assert!(metrics.contains(&format!(
+        "animeboys_ai_requests_total{{model=\"{}\",outcome=\"success\"}} 2",
+        model
+    )));
+    assert!(metrics.contains(&format!(
+        "animeboys_ai_requests_total{{model=\"{}\",outcome=\"error\"}} 1",
+        model
+    )));
+    assert!(metrics.contains(&format!(
+        "animeboys_ai_completion_tokens_total{{model=\"{}\"}} 5",
+        model
+    )));
+}
+
+#[test]
+fn latency_is_a_summary() {
+    let usage = UsageTracker::default();
+    usage.record(UsageRecord {
+        model: "gpt-4o".to_string(),
+        user: None,
+        channel: ChannelId(1),
+        usage: Usage::default(),
+        latency: Duration::from_millis(1500),
+        outcome: Outcome::Success,
+    });
+    let metrics = usage.to_prometheus();
+    assert!(metrics.contains("# TYPE animeboys_ai_latency_seconds summary"));
+    assert!(metrics.contains("animeboys_ai_latency_seconds_sum{model=\"gpt-4o\"} 1.500"));
+    assert!(metrics.contains("animeboys_ai_latency_seconds_count{model=\"gpt-4o\"} 1"));
+    assert!(!metrics.contains("gauge"));
+}
+
+#[test]
+fn label_values_are_escaped() {
+    let usage = UsageTracker::default();
+    // Compatible servers can be given any model name
+    usage.record(UsageRecord {
+        model: "C:\\models\\\"llama\"\n7b".to_string(),
+        user: None,
+        channel: ChannelId(1),
+        usage: Usage::default(),
+        latency: Duration::from_millis(10),
+        outcome: Outcome::Success,
+    });
+    let metrics = usage.to_prometheus();
+    assert!(metrics.contains(
+        "animeboys_ai_requests_total{model=\"C:\\\\models\\\\\\\"llama\\\"\\n7b\",outcome=\"success\"} 1"
+    ));
+    // Every sample stays on its own line
+    assert!(metrics
+        .lines()
+        .all(|line| line.starts_with('#') || line.starts_with("animeboys_ai_")));
+}
+
+#[tokio::test]
+async fn counters_survive_a_restart_and_old_days_are_dropped() {
+    let dir = std::env::temp_dir().join(format!("ai-usage-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::env::set_var("DATA_DIR", &dir);
+    std::fs::write(
+        dir.join("ai_usage.json"),
+        r#"{ "days": { "2000-01-01": { "total": { "requests": 7, "errors": 0, "prompt_tokens": 0,
+            "completion_tokens": 0, "latency_ms": 0, "cost_usd": 0.0 },
+            "models": {}, "users": {}, "channels": {} } }, "models": {}, "outcomes": {} }"#,
+    )
+    .unwrap();
+
+    let usage = UsageTracker::from_env();
+    usage.record(UsageRecord {
+        model: "gpt-4o".to_string(),
+        user: Some(UserId(1)),
+        channel: ChannelId(1),
+        usage: Usage {
+            prompt_tokens: 10,
+            completion_tokens: 20,
+        },
+        latency: Duration::from_millis(100),
+        outcome: Outcome::Success,
+    });
+    usage.save().await;
+    let before = usage.to_prometheus();
+
+    let saved = std::fs::read_to_string(dir.join("ai_usage.json")).unwrap();
+    assert!(!saved.contains("2000-01-01"));
+
+    let restarted = UsageTracker::from_env();
+    assert_eq!(restarted.to_prometheus(), before);
+    assert!(before.contains("animeboys_ai_requests_total{model=\"gpt-4o\",outcome=\"success\"} 1"));
+    assert!(before.contains("animeboys_ai_prompt_tokens_total{model=\"gpt-4o\"} 10"));
+    assert_eq!(restarted.report(1).total.requests, 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use std::sync::Arc;
+
+use animeboys_bot::chatgpt::{
+    animeboys_ai::AnimeboysAI,
+    backend::MockBackend,
+    persona::{Persona, PersonaRegistry, DEFAULT_CHAT_PERSONA},
+    vision::{Ocr, OcrError},
+};
+use common::ai::{screenshot_turn, serve_image, setup};
+use serenity::model::prelude::{ChannelId, UserId};
+
+/// An OCR "command" that prints the words before the image path, so no OCR engine is needed
+const OCR_COMMAND: &str = "echo TypeError: undefined";
+
+#[tokio::test]
+async fn large_images_are_not_downloaded() {
+    let ocr = Ocr::default().with_command("tesseract");
+    // Nothing listens on this port, so a download would fail with an HTTP error instead
+    let result = ocr
+        .read("http://127.0.0.1:9/huge.png", 64 * 1024 * 1024)
+        .await;
+    assert!(matches!(result, Err(OcrError::TooLarge(size)) if size == 64 * 1024 * 1024));
+
+    let result = ocr.read("http://127.0.0.1:9/small.png", 1024).await;
+    assert!(matches!(result, Err(OcrError::Http(_))));
+}
+
+#[tokio::test]
+async fn text_read_from_images_is_added_to_the_turn() {
+    let (backend, ai) = setup();
+    let ai = ai.with_ocr(Ocr::default().with_command(OCR_COMMAND));
+    let url = format!("{}/error.png", serve_image().await);
+    let channel = ChannelId(30);
+
+    let mut screenshot = screenshot_turn("josue", &url);
+    ai.read_images(&mut screenshot, false).await;
+    let attachment = &screenshot.attachments[0];
+    assert!(attachment.note.is_none());
+    assert!(attachment
+        .content
+        .as_deref()
+        .unwrap()
+        .starts_with("TypeError: undefined"));
+
+    ai.ask(&screenshot, &channel, None).await;
+    let last = backend.requests().pop().unwrap().messages.pop().unwrap();
+    assert!(last.images.is_empty());
+    assert!(last.content.contains("TypeError: undefined"));
+}
+
+#[tokio::test]
+async fn images_are_noted_when_ocr_is_disabled() {
+    let (backend, ai) = setup();
+    let channel = ChannelId(31);
+
+    let mut screenshot = screenshot_turn("gio", "https://cdn.example.com/error.png");
+    ai.read_images(&mut screenshot, false).await;
+    let attachment = &screenshot.attachments[0];
+    assert!(attachment.content.is_none());
+    assert_eq!(attachment.note.as_deref(), Some("the AI cannot see images"));
+
+    ai.ask(&screenshot, &channel, None).await;
+    let last = backend.requests().pop().unwrap().messages.pop().unwrap();
+    assert!(last.images.is_empty());
+    assert!(last
+        .content
+        .ends_with("Attached image `error.png` (the AI cannot see images)"));
+}
+
+#[tokio::test]
+async fn one_shot_answers_ignore_the_persona_of_the_conversation() {
+    let backend = Arc::new(MockBackend::new());
+    let defaults = PersonaRegistry::default();
+    let mut personas = defaults.all().cloned().collect::<Vec<_>>();
+    personas.push(Persona {
+        name: "vision".to_string(),
+        vision: true,
+        ..defaults.get("default").unwrap().clone()
+    });
+    let ai = AnimeboysAI::new(backend.clone(), PersonaRegistry::from_personas(personas))
+        .with_ocr(Ocr::default().with_command(OCR_COMMAND));
+    let url = format!("{}/error.png", serve_image().await);
+    let channel = ChannelId(32);
+    ai.create_conversation("josue", UserId(1), &channel, Some("vision"))
+        .await;
+
+    // The conversation could see the image, but `$ai ask` answers with the default persona
+    assert!(
+        ai.conversation_vision(&channel, None, DEFAULT_CHAT_PERSONA)
+            .await
+    );
+    let vision = ai.resolve_persona(None, DEFAULT_CHAT_PERSONA).vision;
+    assert!(!vision);
+
+    let mut screenshot = screenshot_turn("josue", &url);
+    ai.read_images(&mut screenshot, vision).await;
+    ai.ask(&screenshot, &channel, None).await;
+    let last = backend.requests().pop().unwrap().messages.pop().unwrap();
+    assert!(last.images.is_empty());
+    assert!(last.content.contains("TypeError: undefined"));
+}
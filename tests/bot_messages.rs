@@ -0,0 +1,14 @@
+use animeboys_bot::bot::message_chunks;
+
+#[test]
+fn long_messages_are_split_between_characters() {
+    // Each of these is 3 bytes, so a byte split at 2000 would land inside one
+    let text = "あ".repeat(2500);
+    let chunks = message_chunks(&text);
+    assert_eq!(chunks.len(), 2);
+    assert_eq!(chunks[0].chars().count(), 2000);
+    assert_eq!(chunks[1].chars().count(), 500);
+    assert_eq!(chunks.concat(), text);
+
+    assert_eq!(message_chunks("hello"), vec!["hello".to_string()]);
+}
@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use animeboys_bot::chatgpt::{
+    animeboys_ai::AnimeboysAI,
+    backend::MockBackend,
+    context::{TurnAttachment, UserTurn},
+    persona::PersonaRegistry,
+};
+use serenity::model::prelude::UserId;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+/// A turn from user 1 without a reply or attachments
+pub fn turn(author: &str, content: &str) -> UserTurn {
+    UserTurn {
+        message_id: None,
+        author_id: UserId(1),
+        author: author.to_string(),
+        content: content.to_string(),
+        reply_to: None,
+        attachments: vec![],
+    }
+}
+
+/// A turn from another user, named after their id
+pub fn turn_from(author_id: u64, content: &str) -> UserTurn {
+    UserTurn {
+        author_id: UserId(author_id),
+        ..turn(&format!("user{}", author_id), content)
+    }
+}
+
+/// An AI with the default personas answered by a mock backend
+pub fn setup() -> (Arc<MockBackend>, AnimeboysAI) {
+    setup_with(MockBackend::new())
+}
+
+pub fn setup_with(backend: MockBackend) -> (Arc<MockBackend>, AnimeboysAI) {
+    let backend = Arc::new(backend);
+    let ai = AnimeboysAI::new(backend.clone(), PersonaRegistry::default());
+    (backend, ai)
+}
+
+/// A turn with a screenshot whose image is at `image_url`
+pub fn screenshot_turn(author: &str, image_url: &str) -> UserTurn {
+    let mut turn = turn(author, "why does this fail?");
+    turn.attachments.push(TurnAttachment {
+        filename: "error.png".to_string(),
+        content: None,
+        note: None,
+        image_url: Some(image_url.to_string()),
+        size: 1024,
+    });
+    turn
+}
+
+/// Serves the same image bytes on every path and returns the server's base URL
+pub async fn serve_image() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = vec![0; 16 * 1024];
+                let _ = socket.read(&mut buf).await;
+                let body = b"not really a png";
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: image/png\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                    body.len()
+                );
+                let _ = socket.write_all(head.as_bytes()).await;
+                let _ = socket.write_all(body).await;
+            });
+        }
+    });
+    format!("http://{}", addr)
+}
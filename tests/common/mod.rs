@@ -0,0 +1,111 @@
+//! A fake wzstats.gg for the Warzone tests, and the fixtures shared by the AI tests in [`ai`]
+#![allow(dead_code)]
+
+pub mod ai;
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+pub const TIER_LIST: &str = r#"{
+    "wzStatsTierList": {
+        "streamerProfileId": "wzstats",
+        "wz2Ranked": { "META": ["kastov-762", "m4"], "A": ["mcpr-300", "static-hv"], "B": ["bp50"], "C": [], "D": [] }
+    },
+    "weapons": [{ "id": "static-hv", "name": "Static-HV", "type": "SMG" }]
+}"#;
+
+/// Returns the builds of a weapon, with a ranked build using the weapon id in its description
+pub fn builds(weapon_id: &str) -> String {
+    format!(
+        r#"{{ "builds": [
+            {{ "weaponId": "{id}", "position": 2, "playstyle": "Long Range", "description": "not ranked",
+               "isWarzoneRankedBuild": false }},
+            {{ "weaponId": "{id}", "position": 1, "playstyle": "Long Range", "description": "ranked {id}",
+               "isWarzoneRankedBuild": true,
+               "muzzle": {{ "attachmentId": "a", "verticalTuning": 0.5, "horizontalTuning": {{ "value": -1.0 }},
+                           "slot": "muzzle", "name": "Harbinger D20" }} }}
+        ] }}"#,
+        id = weapon_id
+    )
+}
+
+/// The weapon whose builds always fail to load
+pub const BROKEN_WEAPON: &str = "bp50";
+
+/// A fake wzstats that counts the requests it gets and can be taken down
+#[derive(Clone, Default)]
+pub struct FakeWzStats {
+    pub requests: Arc<AtomicUsize>,
+    pub down: Arc<AtomicBool>,
+    /// The builds requests being answered, and the most there were at once
+    pub in_flight: Arc<AtomicUsize>,
+    pub max_in_flight: Arc<AtomicUsize>,
+}
+
+impl FakeWzStats {
+    /// Starts the server and returns its base URL
+    pub async fn start(&self) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let server = server.clone();
+                tokio::spawn(async move {
+                    let mut buf = vec![0; 16 * 1024];
+                    let n = socket.read(&mut buf).await.unwrap();
+                    let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                    let path = request.split_whitespace().nth(1).unwrap_or_default();
+                    server.requests.fetch_add(1, Ordering::SeqCst);
+
+                    let (status, body) = if server.down.load(Ordering::SeqCst) {
+                        ("503 Service Unavailable", String::new())
+                    } else if path.contains("tier-lists") {
+                        ("200 OK", TIER_LIST.to_string())
+                    } else if let Some(rest) = path.split("/weapon/").nth(1) {
+                        let weapon_id = rest.split('/').next().unwrap();
+                        let in_flight = server.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        server.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                        server.in_flight.fetch_sub(1, Ordering::SeqCst);
+                        if weapon_id == BROKEN_WEAPON {
+                            ("500 Internal Server Error", String::new())
+                        } else {
+                            ("200 OK", builds(weapon_id))
+                        }
+                    } else {
+                        ("404 Not Found", String::new())
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    let _ = socket.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn set_down(&self, down: bool) {
+        self.down.store(down, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,85 @@
+use animeboys_bot::{
+    bot::paginator::{PageTurn, Paginator},
+    wz::types::{BuildMode, Playstyles, WzLoadouts},
+};
+use serenity::{
+    builder::CreateEmbed,
+    model::prelude::{MessageId, UserId},
+};
+
+const BUILDS: &str = include_str!("fixtures/builds.json");
+
+#[test]
+fn builds_are_filtered_by_playstyle_and_mode() {
+    let mut loadouts: WzLoadouts = serde_json::from_str(BUILDS).unwrap();
+    assert_eq!("long-range".parse(), Ok(Playstyles::LongRange));
+    assert_eq!("LOW_RECOIL".parse(), Ok(Playstyles::LowRecoil));
+    assert_eq!("Sniper Support".parse(), Ok(Playstyles::SniperSupport));
+    assert!("fast".parse::<Playstyles>().is_err());
+    assert_eq!("Resurgence".parse(), Ok(BuildMode::Resurgence));
+
+    let ids = |builds: Vec<&animeboys_bot::wz::types::Weapon>| {
+        builds
+            .iter()
+            .map(|b| b.weapon_id.clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(loadouts.get_published_builds(None, None).len(), 6);
+    assert_eq!(
+        ids(loadouts.get_published_builds(Some(Playstyles::LongRange), None)),
+        ["la-b-330", "sp-x-80", "lockwood-300"]
+    );
+    assert_eq!(
+        ids(loadouts.get_published_builds(Some(Playstyles::LongRange), Some(BuildMode::Ranked))),
+        ["sp-x-80"]
+    );
+    assert!(loadouts
+        .get_published_builds(None, Some(BuildMode::Resurgence))
+        .is_empty());
+
+    // Unpublished builds are never listed
+    loadouts.builds[0].is_published = false;
+    assert_eq!(loadouts.get_published_builds(None, None).len(), 5);
+}
+
+#[test]
+fn only_the_author_can_turn_pages() {
+    let paginator = Paginator::default();
+    let pages = ["one", "two", "three"]
+        .iter()
+        .map(|title| {
+            let mut embed = CreateEmbed::default();
+            embed.title(title);
+            embed
+        })
+        .collect::<Vec<_>>();
+    let (message, author) = (MessageId(1), UserId(2));
+    paginator.register(message, author, pages);
+
+    let title = |page: Option<CreateEmbed>| {
+        let page = page.unwrap();
+        (
+            page.0["title"].as_str().unwrap().to_string(),
+            page.0["footer"]["text"].as_str().unwrap().to_string(),
+        )
+    };
+    assert_eq!(
+        title(paginator.turn(message, author, PageTurn::Next)),
+        ("two".to_string(), "Page 2/3".to_string())
+    );
+    // Pages wrap around at either end
+    paginator.turn(message, author, PageTurn::Next);
+    assert_eq!(
+        title(paginator.turn(message, author, PageTurn::Next)).0,
+        "one"
+    );
+    assert_eq!(
+        title(paginator.turn(message, author, PageTurn::Previous)).0,
+        "three"
+    );
+
+    assert!(paginator.turn(message, UserId(3), PageTurn::Next).is_none());
+    assert!(paginator
+        .turn(MessageId(4), author, PageTurn::Next)
+        .is_none());
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use animeboys_bot::wz::client::WzStatsClient;
+use common::FakeWzStats;
+
+#[tokio::test]
+async fn responses_are_cached_until_they_expire() {
+    let wzstats = FakeWzStats::default();
+    let client = Arc::new(WzStatsClient::default().with_base_url(wzstats.start().await));
+
+    let builds = client.builds("kastov-762").await.unwrap();
+    assert_eq!(builds.builds.len(), 2);
+    client.builds("kastov-762").await.unwrap();
+    client.tier_list().await.unwrap();
+    client.tier_list().await.unwrap();
+    assert_eq!(wzstats.requests(), 2);
+
+    // Refreshing fetches everything that is cached again
+    assert_eq!(client.refresh().await.unwrap(), 2);
+    assert_eq!(wzstats.requests(), 4);
+}
+
+#[tokio::test]
+async fn stale_data_is_served_while_wzstats_is_down() {
+    let wzstats = FakeWzStats::default();
+    let client = Arc::new(
+        WzStatsClient::default()
+            .with_base_url(wzstats.start().await)
+            .with_ttl(Duration::ZERO),
+    );
+
+    let tier_list = client.tier_list().await.unwrap();
+    wzstats.set_down(true);
+
+    // The stale tier list is returned right away and refreshed in the background
+    let stale = client.tier_list().await.unwrap();
+    assert_eq!(
+        stale.wz_stats_tier_list.wz2_ranked.meta,
+        tier_list.wz_stats_tier_list.wz2_ranked.meta
+    );
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(wzstats.requests(), 2);
+    assert!(client.tier_list().await.is_ok());
+
+    // Nothing is cached for this weapon, so the error comes through
+    assert!(client.builds("m4").await.is_err());
+    assert!(client.refresh().await.is_err());
+}
+
+#[tokio::test]
+async fn the_cache_is_saved_in_batches_and_loaded_on_restart() {
+    let dir = std::env::temp_dir().join(format!("wz-cache-{}", std::process::id()));
+    std::env::set_var("DATA_DIR", &dir);
+    let wzstats = FakeWzStats::default();
+    let base_url = wzstats.start().await;
+    let client = Arc::new(
+        WzStatsClient::default()
+            .with_base_url(base_url.clone())
+            .with_persistence(true),
+    );
+
+    client.builds("kastov-762").await.unwrap();
+    client.tier_list().await.unwrap();
+    // Fetching only marks the cache as changed, it is written by the saver
+    assert!(!dir.join("wz_cache.json").exists());
+    client.save().await;
+    assert!(dir.join("wz_cache.json").exists());
+
+    let restarted = Arc::new(
+        WzStatsClient::default()
+            .with_base_url(base_url)
+            .with_persistence(true),
+    );
+    restarted.builds("kastov-762").await.unwrap();
+    restarted.tier_list().await.unwrap();
+    assert_eq!(wzstats.requests(), 2);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,35 @@
+use animeboys_bot::wz::{card::render_build_card, types::Weapon};
+use tiny_skia::Pixmap;
+
+const BUILD: &str = r#"{
+    "weaponId": "kastov-762", "playstyle": "Long Range", "authorDisplayName": "WZStats",
+    "muzzle": { "attachmentId": "a", "verticalTuning": { "value": 0.5 }, "horizontalTuning": -1.0,
+                "slot": "muzzle", "name": "Harbinger D20" },
+    "optic": { "attachmentId": "o", "verticalTuning": 1.5, "horizontalTuning": { "value": 3.25 },
+               "slot": "optic", "name": "Cronen Mini Pro" }
+}"#;
+
+#[test]
+fn cards_have_a_row_per_attachment() {
+    let weapon: Weapon = serde_json::from_str(BUILD).unwrap();
+    let png = render_build_card(&weapon, "Kastov 762", Some("META")).unwrap();
+    let card = Pixmap::decode_png(&png).unwrap();
+    assert_eq!(card.width(), 800);
+
+    let empty = render_build_card(&Weapon::default(), "Kastov 762", None).unwrap();
+    let empty = Pixmap::decode_png(&empty).unwrap();
+    // One row for each attachment, and a single row saying there are none
+    assert_eq!(card.height() - empty.height(), 56);
+
+    // The META badge is drawn in its tier colour
+    let meta = card
+        .pixels()
+        .iter()
+        .filter(|p| (p.red(), p.green(), p.blue()) == (0xE6, 0x7E, 0x22))
+        .count();
+    assert!(meta > 500);
+    assert!(!empty
+        .pixels()
+        .iter()
+        .any(|p| (p.red(), p.green(), p.blue()) == (0xE6, 0x7E, 0x22)));
+}
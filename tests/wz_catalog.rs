@@ -0,0 +1,46 @@
+mod common;
+
+use std::sync::Arc;
+
+use animeboys_bot::wz::{
+    catalog::{WeaponCatalog, WeaponCategory},
+    client::WzStatsClient,
+    resolver::{resolve_weapon, WeaponMatch},
+};
+use common::FakeWzStats;
+
+#[test]
+fn bundled_weapons_have_names_and_categories() {
+    let catalog = WeaponCatalog::default();
+    assert_eq!(catalog.name("kastov-762"), "Kastov 762");
+    assert_eq!(
+        catalog.get("mcpr-300").unwrap().category,
+        WeaponCategory::SniperRifle
+    );
+    assert_eq!(catalog.name("new-gun-9"), "New Gun 9");
+    assert!(catalog.by_category()[&WeaponCategory::Smg]
+        .iter()
+        .any(|w| w.id == "lachmann-sub"));
+    assert_eq!("Assault Rifles".parse(), Ok(WeaponCategory::AssaultRifle));
+}
+
+#[tokio::test]
+async fn new_weapons_are_learned_from_the_tier_list() {
+    let wzstats = FakeWzStats::default();
+    let client = Arc::new(WzStatsClient::default().with_base_url(wzstats.start().await));
+    let known = client.catalog().len();
+    assert!(client.catalog().get("static-hv").is_none());
+
+    client.tier_list().await.unwrap();
+    assert_eq!(client.catalog().len(), known + 2);
+
+    let static_hv = client.catalog().get("static-hv").unwrap();
+    assert_eq!(static_hv.name, "Static-HV");
+    assert_eq!(static_hv.category, WeaponCategory::Smg);
+    // Weapons that are only ranked are named after their id
+    assert_eq!(client.catalog().name("bp50"), "Bp50");
+    assert_eq!(
+        resolve_weapon(client.catalog(), "static hv"),
+        WeaponMatch::Found("static-hv".to_string())
+    );
+}
@@ -0,0 +1,31 @@
+use animeboys_bot::wz::WZCOMMANDS_GROUP;
+
+/// Returns the names of every command in the Warzone group
+fn command_names() -> Vec<&'static str> {
+    WZCOMMANDS_GROUP
+        .options
+        .commands
+        .iter()
+        .flat_map(|c| c.options.names.iter().copied())
+        .collect()
+}
+
+#[test]
+fn every_warzone_command_is_registered() {
+    let names = command_names();
+    for name in [
+        "weapon-ids",
+        "ranked-build",
+        "builds",
+        "tiers",
+        "tier-list",
+        "top",
+        "future-features",
+        "refresh",
+        "subscribe",
+        "unsubscribe",
+        "help",
+    ] {
+        assert!(names.contains(&name), "`$wz {}` is not registered", name);
+    }
+}
@@ -0,0 +1,60 @@
+use animeboys_bot::wz::{embed::build_embed, types::Weapon};
+use serenity::builder::CreateEmbed;
+
+const BUILD: &str = r#"{
+    "weaponId": "kastov-762", "playstyle": "Long Range", "description": "Beams at range",
+    "authorDisplayName": "WZStats", "externalSourceImage": "https://example.com/kastov.png",
+    "isWarzoneRankedBuild": true, "pros": ["Low recoil"], "cons": [],
+    "magazine": { "attachmentId": "m", "verticalTuning": 0, "horizontalTuning": 0,
+                  "slot": "magazine", "name": "40 Round Mag" },
+    "optic": { "attachmentId": "o", "verticalTuning": 1.5, "horizontalTuning": { "value": 3.25 },
+               "slot": "optic", "name": "Cronen Mini Pro" },
+    "muzzle": { "attachmentId": "a", "verticalTuning": { "value": 0.5 }, "horizontalTuning": -1.0,
+                "slot": "muzzle", "name": "Harbinger D20" }
+}"#;
+
+#[test]
+fn builds_render_every_slot_in_order() {
+    let weapon: Weapon = serde_json::from_str(BUILD).unwrap();
+    let mut embed = CreateEmbed::default();
+    build_embed(&mut embed, &weapon, "Kastov 762", Some("META"));
+
+    let fields = embed.0["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| {
+            (
+                f["name"].as_str().unwrap().to_string(),
+                f["value"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let names = fields.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        [
+            "Playstyle",
+            "Tier",
+            "Mode",
+            "Muzzle",
+            "Optic",
+            "Magazine",
+            "Pros"
+        ]
+    );
+    assert_eq!(fields[1].1, "META");
+    assert_eq!(
+        fields[3].1,
+        "Harbinger D20\n:left_right_arrow: -1 :arrow_up_down: 0.5"
+    );
+    assert_eq!(fields[5].1, "40 Round Mag");
+    assert_eq!(fields[6].1, "• Low recoil");
+
+    assert_eq!(embed.0["title"], "Kastov 762");
+    assert_eq!(embed.0["author"]["name"], "WZStats");
+    assert_eq!(
+        embed.0["thumbnail"]["url"],
+        "https://example.com/kastov.png"
+    );
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use std::collections::BTreeMap;
+
+use animeboys_bot::wz::{
+    catalog::WeaponCatalog,
+    client::WzStatsClient,
+    meta_watch::{diff, summary, MetaChange, MetaSnapshot, MetaWatch},
+    types::TierListMap,
+};
+use common::FakeWzStats;
+use serenity::model::prelude::ChannelId;
+
+fn snapshot(ranked: &[(&str, &str)], builds: &[(&str, &str)]) -> MetaSnapshot {
+    let to_map = |pairs: &[(&str, &str)]| {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<BTreeMap<_, _>>()
+    };
+    MetaSnapshot {
+        tiers: BTreeMap::from([(TierListMap::Wz2Ranked, to_map(ranked))]),
+        ranked_builds: to_map(builds),
+    }
+}
+
+#[test]
+fn changes_between_snapshots_are_summarized() {
+    let old = snapshot(
+        &[("kastov-762", "META"), ("m4", "A"), ("mcpr-300", "B")],
+        &[("kastov-762", "build-1")],
+    );
+    let new = snapshot(
+        &[("kastov-762", "META"), ("m4", "META"), ("iso-45", "C")],
+        &[("kastov-762", "build-2"), ("m4", "build-3")],
+    );
+    let changes = diff(&old, &new);
+    assert_eq!(
+        changes,
+        [
+            MetaChange::Moved {
+                map: TierListMap::Wz2Ranked,
+                weapon_id: "iso-45".to_string(),
+                from: None,
+                to: Some("C".to_string()),
+            },
+            MetaChange::NewMeta {
+                map: TierListMap::Wz2Ranked,
+                weapon_id: "m4".to_string(),
+                from: Some("A".to_string()),
+            },
+            MetaChange::Moved {
+                map: TierListMap::Wz2Ranked,
+                weapon_id: "mcpr-300".to_string(),
+                from: Some("B".to_string()),
+                to: None,
+            },
+            MetaChange::NewRankedBuild {
+                weapon_id: "kastov-762".to_string(),
+            },
+        ]
+    );
+    assert!(diff(&new, &new).is_empty());
+    // Snapshots are saved between restarts
+    let saved = serde_json::to_string(&new).unwrap();
+    assert_eq!(serde_json::from_str::<MetaSnapshot>(&saved).unwrap(), new);
+    // A tier list that was empty before doesn't report every weapon as new
+    assert!(diff(&MetaSnapshot::default(), &new).is_empty());
+
+    let summary = summary(&changes, &WeaponCatalog::default());
+    assert_eq!(
+        summary,
+        "**The Warzone meta changed**\n\
+         **New META**\n- M4 (Warzone Ranked), was A\n\
+         **Tier changes**\n- Iso 45 (Warzone Ranked): unranked -> C\n- Mcpr 300 (Warzone Ranked): B -> unranked\n\
+         **New ranked builds**\n- Kastov 762, see `$wz ranked-build kastov-762`"
+    );
+}
+
+#[tokio::test]
+async fn the_first_snapshot_has_no_changes() {
+    let wzstats = FakeWzStats::default();
+    let client = WzStatsClient::default().with_base_url(wzstats.start().await);
+    let watch = MetaWatch::default();
+
+    assert!(watch.set_subscribed(ChannelId(1), true));
+    assert!(!watch.set_subscribed(ChannelId(1), true));
+    assert!(watch.is_subscribed(&ChannelId(1)));
+
+    assert!(watch.check(&client).await.unwrap().is_empty());
+    // The tier list and the builds of both META weapons
+    assert_eq!(wzstats.requests(), 3);
+    assert!(watch.check(&client).await.unwrap().is_empty());
+
+    assert!(watch.set_subscribed(ChannelId(1), false));
+    assert!(!watch.is_subscribed(&ChannelId(1)));
+}
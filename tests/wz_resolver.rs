@@ -0,0 +1,45 @@
+use animeboys_bot::wz::{
+    catalog::WeaponCatalog,
+    resolver::{self, WeaponMatch},
+};
+
+fn resolve_weapon(query: &str) -> WeaponMatch {
+    resolver::resolve_weapon(&WeaponCatalog::default(), query)
+}
+
+fn found(id: &str) -> WeaponMatch {
+    WeaponMatch::Found(id.to_string())
+}
+
+#[test]
+fn ids_names_and_nicknames_resolve_exactly() {
+    assert_eq!(resolve_weapon("kastov-762"), found("kastov-762"));
+    assert_eq!(resolve_weapon("Kastov 762"), found("kastov-762"));
+    assert_eq!(resolve_weapon("AK-47"), found("kastov-762"));
+    assert_eq!(resolve_weapon("hdr"), found("fjx-imperium"));
+}
+
+#[test]
+fn typos_resolve_to_the_closest_weapon() {
+    assert_eq!(resolve_weapon("kastv 762"), found("kastov-762"));
+    assert_eq!(resolve_weapon("lachman shroud"), found("lachmann-shroud"));
+    assert_eq!(resolve_weapon("mcpr"), found("mcpr-300"));
+}
+
+#[test]
+fn unclear_searches_suggest_weapons() {
+    match resolve_weapon("kastov") {
+        WeaponMatch::DidYouMean(weapons) => {
+            let ids = weapons.iter().map(|w| w.id.as_str()).collect::<Vec<_>>();
+            assert!(ids.contains(&"kastov-545"));
+            assert!(ids.contains(&"kastov-74u"));
+            assert!(ids.contains(&"kastov-762"));
+            assert!(resolve_weapon("kastov")
+                .to_string()
+                .contains("`kastov-762` (Kastov 762)"));
+        }
+        other => panic!("expected suggestions, got {:?}", other),
+    }
+    assert_eq!(resolve_weapon("zzzzzzzzzz"), WeaponMatch::NotFound);
+    assert_eq!(resolve_weapon("!!"), WeaponMatch::NotFound);
+}
@@ -0,0 +1,104 @@
+use animeboys_bot::wz::{
+    card::render_build_card,
+    types::{AttachmentSlot, Weapon, WzLoadouts},
+};
+
+/// Real wzstats builds, including ones using the guard, comb, rail, bolt and trigger action slots
+const BUILDS: &str = include_str!("fixtures/builds.json");
+
+#[test]
+fn every_attachment_in_a_build_is_shown() {
+    let raw: serde_json::Value = serde_json::from_str(BUILDS).unwrap();
+    let loadouts: WzLoadouts = serde_json::from_str(BUILDS).unwrap();
+
+    for (raw, build) in raw["builds"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .zip(&loadouts.builds)
+    {
+        let attachments = build.get_loadout_attachments();
+        let expected = raw
+            .as_object()
+            .unwrap()
+            .values()
+            .filter(|v| v.get("slot").is_some())
+            .count();
+        assert_eq!(attachments.len(), expected, "{}", build.weapon_id);
+
+        for (slot, attachment) in &attachments {
+            assert_eq!(attachment.slot, *slot);
+            assert!(!matches!(slot, AttachmentSlot::Other(_)));
+        }
+        // Attachments are always shown in slot order
+        assert!(attachments.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+}
+
+#[test]
+fn rare_slots_have_display_names() {
+    let loadouts: WzLoadouts = serde_json::from_str(BUILDS).unwrap();
+    let slots = loadouts
+        .builds
+        .iter()
+        .flat_map(|b| b.get_loadout_attachments())
+        .map(|(slot, _)| slot.to_string())
+        .collect::<Vec<_>>();
+    for slot in [
+        "Guard",
+        "Comb",
+        "Rail",
+        "Bolt",
+        "Trigger Action",
+        "Rear Grip",
+    ] {
+        assert!(slots.iter().any(|s| s == slot), "missing {}", slot);
+    }
+
+    let lockwood = loadouts
+        .builds
+        .iter()
+        .find(|b| b.weapon_id == "lockwood-300")
+        .unwrap();
+    let trigger = lockwood.attachment(AttachmentSlot::TriggerAction).unwrap();
+    assert_eq!(trigger.slot, AttachmentSlot::TriggerAction);
+    assert_eq!(
+        lockwood.get_loadout_attachments().last().unwrap().0,
+        AttachmentSlot::TriggerAction
+    );
+}
+
+#[test]
+fn unknown_slots_are_kept_and_shown_last() {
+    let build = r#"{ "weaponId": "x", "muzzle": { "attachmentId": "m", "verticalTuning": 0,
+                         "horizontalTuning": 0, "slot": "muzzle", "name": "Muzzle" },
+                     "underGrip": { "attachmentId": "u", "verticalTuning": 1,
+                         "horizontalTuning": { "value": -2 }, "slot": "underGrip", "name": "Grip" },
+                     "title": "not an attachment" }"#;
+    let build: Weapon = serde_json::from_str(build).unwrap();
+    let attachments = build.get_loadout_attachments();
+    assert_eq!(attachments.len(), 2);
+    assert_eq!(attachments[0].0, AttachmentSlot::Muzzle);
+
+    let (slot, attachment) = &attachments[1];
+    assert_eq!(*slot, AttachmentSlot::Other("underGrip".to_string()));
+    assert_eq!(slot.to_string(), "Under Grip");
+    assert_eq!(attachment.slot, *slot);
+    assert_eq!(attachment.get_tuning(), Some((-2.0, 1.0)));
+
+    // Unknown slots survive being cached
+    let cached: Weapon = serde_json::from_str(&serde_json::to_string(&build).unwrap()).unwrap();
+    assert_eq!(cached.get_loadout_attachments().len(), 2);
+    assert_eq!(cached.title, "not an attachment");
+}
+
+#[test]
+fn malformed_tuning_does_not_break_builds() {
+    let build = r#"{ "weaponId": "x", "stock": { "attachmentId": "s", "verticalTuning": { "value": "high" },
+                         "horizontalTuning": 0.5, "slot": "stock", "name": "Stock" } }"#;
+    let build: Weapon = serde_json::from_str(build).unwrap();
+    let stock = build.attachment(AttachmentSlot::Stock).unwrap();
+    assert_eq!(stock.get_tuning(), None);
+    assert_eq!(stock.to_string(), "Stock");
+    assert!(render_build_card(&build, "X", None).is_ok());
+}
@@ -0,0 +1,62 @@
+use animeboys_bot::wz::{
+    catalog::WeaponCatalog,
+    embed::tier_list_pages,
+    types::{TierListMap, TierListResponse, Tiers},
+};
+
+#[test]
+fn every_map_can_be_chosen() {
+    assert_eq!("ashika".parse(), Ok(TierListMap::AshikaIsland));
+    assert_eq!("Al Mazrah".parse(), Ok(TierListMap::AlMazrah));
+    assert_eq!("mw2".parse(), Ok(TierListMap::Mw2Ranked));
+    assert_eq!("ranked".parse(), Ok(TierListMap::Wz2Ranked));
+    assert!("verdansk".parse::<TierListMap>().is_err());
+
+    let tier_list: TierListResponse = serde_json::from_str(
+        r#"{ "wzStatsTierList": { "ashikaIsland": { "META": ["iso-45"] }, "wz2Ranked": { "A": ["m4"] } } }"#,
+    )
+    .unwrap();
+    let list = &tier_list.wz_stats_tier_list;
+    assert_eq!(
+        list.get_tiers(TierListMap::AshikaIsland).tier_of("iso-45"),
+        Some("META")
+    );
+    assert_eq!(
+        list.get_tiers(TierListMap::Wz2Ranked).tier_of("m4"),
+        Some("A")
+    );
+    assert_eq!(
+        list.get_tiers(TierListMap::Wz2Ranked).tier_of("iso-45"),
+        None
+    );
+}
+
+#[test]
+fn tiers_are_paged_with_weapon_names() {
+    let tiers = Tiers {
+        meta: vec!["kastov-762".to_string()],
+        a: (0..20).map(|i| format!("new-gun-{}", i)).collect(),
+        c: vec!["m4".to_string()],
+        ..Default::default()
+    };
+    let pages = tier_list_pages(&tiers, TierListMap::AlMazrah, &WeaponCatalog::default());
+
+    let titles = pages
+        .iter()
+        .map(|p| p.0["title"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        titles,
+        [
+            "Al Mazrah Tier List - META",
+            "Al Mazrah Tier List - A (1/2)",
+            "Al Mazrah Tier List - A (2/2)",
+            "Al Mazrah Tier List - C",
+        ]
+    );
+    assert_eq!(pages[0].0["description"], "1. Kastov 762 (`kastov-762`)");
+    assert!(pages[2].0["description"]
+        .as_str()
+        .unwrap()
+        .starts_with("16. New Gun 15 (`new-gun-15`)"));
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use std::sync::Arc;
+
+use animeboys_bot::wz::{client::WzStatsClient, embed::top_builds_text, types::TierListMap};
+use common::{FakeWzStats, BROKEN_WEAPON};
+
+#[tokio::test]
+async fn top_builds_are_fetched_concurrently_in_tier_order() {
+    let wzstats = FakeWzStats::default();
+    let client = Arc::new(WzStatsClient::default().with_base_url(wzstats.start().await));
+
+    let top = client.top_builds(TierListMap::Wz2Ranked, 10).await.unwrap();
+    let ranked = top
+        .iter()
+        .map(|t| (t.tier, t.weapon_id.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ranked,
+        [
+            ("META", "kastov-762"),
+            ("META", "m4"),
+            ("A", "mcpr-300"),
+            ("A", "static-hv"),
+            ("B", BROKEN_WEAPON),
+        ]
+    );
+    let max_in_flight = wzstats.max_in_flight();
+    assert!((2..=4).contains(&max_in_flight), "{}", max_in_flight);
+
+    // The ranked build is used, and a weapon that fails doesn't fail the others
+    assert_eq!(
+        top[0].build.as_ref().unwrap().description,
+        "ranked kastov-762"
+    );
+    assert!(top[4].build.is_none());
+
+    let text = top_builds_text(TierListMap::Wz2Ranked, &top, client.catalog());
+    let lines = text.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "**Top 5 Warzone Ranked Builds**");
+    assert_eq!(
+        lines[1],
+        "`#1` **META** Kastov 762 - Long Range: Harbinger D20"
+    );
+    assert_eq!(lines[5], "`#5` **B** Bp50 - build unavailable right now");
+
+    let top = client.top_builds(TierListMap::Wz2Ranked, 2).await.unwrap();
+    assert_eq!(top.len(), 2);
+    assert!(client
+        .top_builds(TierListMap::AshikaIsland, 3)
+        .await
+        .unwrap()
+        .is_empty());
+}